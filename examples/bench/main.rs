@@ -0,0 +1,107 @@
+//! Compare the execution backends
+//!
+//! Runs every program in `examples/`, and the generated programs from
+//! [`workloads`], a few times at several optimisation levels and reports the
+//! fastest run on each backend, use
+//! `cargo run --release --example bench [PROGRAM.bf...]`
+//!
+//! A program reads `PROGRAM.in` as its input if that file exists.
+
+mod workloads;
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use bf_rust::closure::ClosureProgram;
+use bf_rust::instruction::{LinkedInstructions, UnlinkedInstructions};
+use bf_rust::interpret::Interpreter;
+use bf_rust::jit::JitProgram;
+use bf_rust::packed::{PackedInterpreter, PackedProgram};
+use bf_rust::pass::PassManager;
+
+const RUNS: usize = 5;
+const LEVELS: [u8; 3] = [0, 1, 3];
+
+/// Time the fastest of `RUNS` runs, returning the output of the last one
+fn fastest(mut run: impl FnMut(&mut Vec<u8>)) -> (Duration, Vec<u8>) {
+	let mut best = Duration::MAX;
+	let mut output = vec![];
+
+	for _ in 0..RUNS {
+		output.clear();
+		let start = Instant::now();
+		run(&mut output);
+		best = best.min(start.elapsed());
+	}
+
+	(best, output)
+}
+
+fn bench(name: &str, level: u8, insts: &LinkedInstructions, input: &[u8]) {
+	let (reference, expected) = fastest(|output| {
+		Interpreter::new(insts).run_with(&mut &input[..], output).unwrap();
+	});
+
+	let program = PackedProgram::new(insts).unwrap();
+	let packed = fastest(|output| {
+		PackedInterpreter::new(&program).run_with(&mut &input[..], output).unwrap();
+	});
+
+	// Compiling is part of running for these
+	let closure = fastest(|output| {
+		let mut program = ClosureProgram::new(insts).unwrap();
+		program.run_with(&mut &input[..], output).unwrap();
+	});
+	let jit = fastest(|output| {
+		let mut program = JitProgram::new(insts).unwrap();
+		program.run_with(&mut &input[..], output).unwrap();
+	});
+
+	print!("{:<24} -O{:<2} {:>10.2?}", name, level, reference);
+	for (backend, (time, output)) in [("packed", packed), ("closure", closure), ("jit", jit)] {
+		assert_eq!(expected, output, "{} -O{}: the {} backend disagrees", name, level, backend);
+		print!(" {:>10.2?} {:>6.2}x", time, reference.as_secs_f64() / time.as_secs_f64());
+	}
+	println!();
+}
+
+fn main() {
+	let mut paths: Vec<PathBuf> = std::env::args().skip(1).map(PathBuf::from).collect();
+	let generate = paths.is_empty();
+	if generate {
+		let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+		paths = std::fs::read_dir(examples)
+			.unwrap()
+			.map(|entry| entry.unwrap().path())
+			.filter(|path| path.extension().is_some_and(|ext| ext == "bf"))
+			.collect();
+		paths.sort();
+	}
+
+	let mut programs: Vec<(String, Vec<u8>, Vec<u8>)> = paths
+		.iter()
+		.map(|path| {
+			let name = path.file_name().unwrap().to_string_lossy().into_owned();
+			let source = std::fs::read(path).unwrap();
+			let input = std::fs::read(path.with_extension("in")).unwrap_or_default();
+			(name, source, input)
+		})
+		.collect();
+	if generate {
+		programs.push(("hanoi(16)".to_owned(), workloads::hanoi(16).into_bytes(), vec![]));
+		programs.push(("coprimes(64)".to_owned(), workloads::coprimes(64).into_bytes(), vec![]));
+	}
+
+	println!(
+		"{:<24} {:<4} {:>10} {:>18} {:>18} {:>18}",
+		"program", "opt", "interpret", "packed", "closure", "jit"
+	);
+
+	for (name, source, input) in programs {
+		for level in LEVELS {
+			let passes = PassManager::from_level(level).unwrap();
+			let (insts, _) = passes.run(UnlinkedInstructions::from_text(&source)).unwrap();
+			bench(&name, level, &insts, &input);
+		}
+	}
+}
@@ -0,0 +1,241 @@
+//! Generated programs that run long enough to time dispatch
+//!
+//! Like mandelbrot and hanoi, they spend their time in data dependent loops
+//! that no optimisation can replace by a closed form.
+
+/// Writes brainfuck working on cells at fixed offsets from where it started
+#[derive(Default)]
+struct Asm {
+	code: String,
+	/// The offset of the cell the data pointer is on
+	at:   isize,
+}
+
+impl Asm {
+	fn go(&mut self, cell: isize) {
+		let step = if cell > self.at { '>' } else { '<' };
+		self.code.extend(std::iter::repeat_n(step, cell.abs_diff(self.at)));
+		self.at = cell;
+	}
+
+	fn add(&mut self, cell: isize, amount: i32) {
+		self.go(cell);
+		let step = if amount > 0 { '+' } else { '-' };
+		self.code.extend(std::iter::repeat_n(step, amount.unsigned_abs() as usize));
+	}
+
+	fn clear(&mut self, cell: isize) {
+		self.go(cell);
+		self.code.push_str("[-]");
+	}
+
+	fn print(&mut self, cell: isize) {
+		self.go(cell);
+		self.code.push('.');
+	}
+
+	/// Repeat `body` while `cell` isn't 0, `body` has to be balanced
+	fn repeat(&mut self, cell: isize, body: impl FnOnce(&mut Self)) {
+		self.go(cell);
+		self.code.push('[');
+		body(self);
+		self.go(cell);
+		self.code.push(']');
+	}
+
+	/// Run `body` once if `cell` isn't 0, clearing `cell`
+	fn once(&mut self, cell: isize, body: impl FnOnce(&mut Self)) {
+		self.repeat(cell, |asm| {
+			asm.clear(cell);
+			body(asm);
+		});
+	}
+
+	/// Add `from` to `to`, clearing `from`
+	fn shift(&mut self, from: isize, to: isize) {
+		self.repeat(from, |asm| {
+			asm.add(from, -1);
+			asm.add(to, 1);
+		});
+	}
+
+	/// Add `from` to `to`, using `tmp`, which has to be 0
+	fn copy(&mut self, from: isize, to: isize, tmp: isize) {
+		self.repeat(from, |asm| {
+			asm.add(from, -1);
+			asm.add(to, 1);
+			asm.add(tmp, 1);
+		});
+		self.shift(tmp, from);
+	}
+}
+
+/// Print the moves solving the towers of hanoi with `disks` disks, one line
+/// like `AC` per move
+///
+/// The recursion keeps a stack of frames on the tape. Every iteration works
+/// on the topmost frame, and either pushes a new one or pops it.
+pub fn hanoi(disks: u8) -> String {
+	// active n from to via state a b c d e spare
+	const ACTIVE: isize = 0;
+	const N: isize = 1;
+	const FROM: isize = 2;
+	const TO: isize = 3;
+	const VIA: isize = 4;
+	const STATE: isize = 5;
+	const WIDTH: isize = 12;
+	let [a, b, c, d, e] = [6, 7, 8, 9, 10];
+
+	let mut asm = Asm::default();
+	let push = |asm: &mut Asm, from: isize, to: isize, via: isize| {
+		asm.add(WIDTH + ACTIVE, 1);
+		asm.copy(N, WIDTH + N, a);
+		asm.add(WIDTH + N, -1);
+		asm.copy(from, WIDTH + FROM, a);
+		asm.copy(to, WIDTH + TO, a);
+		asm.copy(via, WIDTH + VIA, a);
+	};
+	let pop = |asm: &mut Asm| {
+		for cell in ACTIVE..=STATE {
+			asm.clear(cell);
+		}
+	};
+
+	// The frame at 0 stays empty and ends the main loop
+	asm.add(WIDTH + ACTIVE, 1);
+	asm.add(WIDTH + N, disks as i32);
+	asm.add(WIDTH + TO, 2);
+	asm.add(WIDTH + VIA, 1);
+	asm.go(WIDTH);
+	asm.at = 0;
+
+	asm.repeat(ACTIVE, |asm| {
+		// Exactly one of c, d, and e gets set for states 0, 1, and 2
+		asm.copy(STATE, a, b);
+		asm.add(c, 1);
+		asm.repeat(a, |asm| {
+			asm.add(c, -1);
+			asm.add(d, 1);
+			asm.add(a, -1);
+			asm.once(a, |asm| {
+				asm.add(d, -1);
+				asm.add(e, 1);
+			});
+		});
+
+		// Before the first move, stop at 0 disks or solve for one disk fewer
+		asm.once(c, |asm| {
+			asm.copy(N, a, b);
+			asm.add(b, 1);
+			asm.once(a, |asm| asm.add(b, -1));
+			asm.add(11, 1);
+			asm.once(b, |asm| {
+				asm.add(11, -1);
+				pop(asm);
+			});
+			asm.once(11, |asm| {
+				asm.add(STATE, 1);
+				push(asm, FROM, VIA, TO);
+			});
+		});
+
+		// Move the largest disk, then solve the rest on top of it
+		asm.once(d, |asm| {
+			for peg in [FROM, TO] {
+				asm.copy(peg, a, b);
+				asm.add(a, 'A' as i32);
+				asm.print(a);
+				asm.clear(a);
+			}
+			asm.add(a, '\n' as i32);
+			asm.print(a);
+			asm.clear(a);
+
+			asm.add(STATE, 1);
+			push(asm, VIA, TO, FROM);
+		});
+
+		asm.once(e, pop);
+
+		// A popped frame isn't active, a pushed one is followed by one that
+		// isn't, either way this finds the new topmost frame
+		asm.go(ACTIVE);
+		asm.code.push('[');
+		asm.code.extend(std::iter::repeat_n('>', WIDTH as usize));
+		asm.code.push(']');
+		asm.code.extend(std::iter::repeat_n('<', WIDTH as usize));
+	});
+
+	asm.code
+}
+
+/// Print a `size` by `size` grid with a `#` where the row and column are
+/// coprime, and a `.` where they aren't
+///
+/// Every point runs Euclid's algorithm by repeatedly subtracting one cell
+/// from the other.
+pub fn coprimes(size: u8) -> String {
+	let [row, column, x, y, min, both, t, u, running, out, tmp] =
+		[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+	let mut asm = Asm::default();
+	// Whether both x and y aren't 0
+	let both_nonzero = |asm: &mut Asm| {
+		asm.copy(x, t, tmp);
+		asm.once(t, |asm| {
+			asm.copy(y, u, tmp);
+			asm.once(u, |asm| asm.add(both, 1));
+		});
+	};
+
+	asm.add(row, size as i32);
+	asm.repeat(row, |asm| {
+		asm.add(column, size as i32);
+		asm.repeat(column, |asm| {
+			asm.copy(row, x, tmp);
+			asm.copy(column, y, tmp);
+
+			asm.add(running, 1);
+			asm.repeat(running, |asm| {
+				// Subtract the smaller of x and y from both
+				both_nonzero(asm);
+				asm.repeat(both, |asm| {
+					asm.clear(both);
+					asm.add(x, -1);
+					asm.add(y, -1);
+					asm.add(min, 1);
+					both_nonzero(asm);
+				});
+
+				// The one that got to 0 gets the smaller value back, unless
+				// both did and it was the greatest common divisor
+				asm.copy(x, t, tmp);
+				asm.copy(y, u, tmp);
+				asm.once(t, |asm| asm.shift(min, y));
+				asm.once(u, |asm| asm.shift(min, x));
+
+				asm.copy(min, t, tmp);
+				asm.once(t, |asm| {
+					asm.clear(running);
+
+					asm.add(u, 1);
+					asm.add(min, -1);
+					asm.once(min, |asm| asm.add(u, -1));
+					asm.add(out, '.' as i32);
+					asm.once(u, |asm| asm.add(out, '#' as i32 - '.' as i32));
+					asm.print(out);
+					asm.clear(out);
+				});
+			});
+
+			asm.add(column, -1);
+		});
+
+		asm.add(out, '\n' as i32);
+		asm.print(out);
+		asm.clear(out);
+		asm.add(row, -1);
+	});
+
+	asm.code
+}
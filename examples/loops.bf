@@ -0,0 +1,2 @@
+Three nested counting loops that resist closed forms
+[-]------[>[-]------[>[-]------[-->+<]<-]<-]>>>.[-]++++++++++.<<<
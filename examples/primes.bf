@@ -0,0 +1,2 @@
+Print all primes below 128 by trial division
+[-]++>[-]++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++[>[-]+>[-]++>>>>>>>>>>>>>>>>>[-]<<<<<<<<<<<<<<<[-]<<[->>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<+<<]>>[-<<+>>]>>>>>>>>>>>>>>>>[-]<<<<<<<<<<<<<<<<[-]<<<<<[->>>>>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<+<<<<<]>>>>>[-<<<<<+>>>>>]>>>>>>>>>>>>>>>>>[-]>[-]>[-]>[-]>[-]<<<<<<[>>+<<-]>[>>+>>+<<<<-]>>>>[<<<<+>>>>-]<<<[->>+<[>[-]>+<<-]>>[<<+>>-]>+<<[<<[-]>>>>-<<-]>>[<<<->>>-]<<<<]>[<<<+>>>[-]]<<<<<<<<<<<<<<<<<<<[-]>>>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>]<<<<<<<<<<<<<<<<[>>>>>>[-]<<<<<[-]<<<<<[->>>>>>>>>>+<<<<<+<<<<<]>>>>>[-<<<<<+>>>>>]>>>>>>[-]<<<<<<[-]<<[->>>>>>>>+<<<<<<+<<]>>[-<<+>>]>>>>>>>[-]>[-]>[-]>[-]>[-]<<<<<[>>>+>>+<<<<<-]>>>>>[<<<<<+>>>>>-]<<<<<<[->>+>>->+<[>[-]>+<<-]>>[<<+>>-]<[<<+<[-]<[>>>+>>+<<<<<-]>>>>>[<<<<<+>>>>>-]<-]<<<<<]<<<<[-]+>>>>>>[<<<<<<[-]>>>>>>[-]]<<<<<<[<<<<[-]>>>>[-]]>>>>>[-]>>[-]<<<<<<<<<<+>>>>>>>>>>>>>>>>>[-]<<<<<<<<<<<<<<<[-]<<[->>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<+<<]>>[-<<+>>]>>>>>>>>>>>>>>>>[-]<<<<<<<<<<<<<<<<[-]<<<<<[->>>>>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<+<<<<<]>>>>>[-<<<<<+>>>>>]>>>>>>>>>>>>>>>>>[-]>[-]>[-]>[-]>[-]<<<<<<[>>+<<-]>[>>+>>+<<<<-]>>>>[<<<<+>>>>-]<<<[->>+<[>[-]>+<<-]>>[<<+>>-]>+<<[<<[-]>>>>-<<-]>>[<<<->>>-]<<<<]>[<<<+>>>[-]]<<<<<<<<<<<<<<<<<<<[-]>>>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<<<+>>>>>>>>>>>>>>>>]<<<<<<<<<<<<<<<<]<<[>>>>>>>>>>>>>>>>>>>>>>>>>>>>[-]<<<<<<<<<<<<<<<<<<<<<<<<<[-]<<<<<[->>>>>>>>>>>>>>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<<<<<<<<<<+<<<<<]>>>>>[-<<<<<+>>>>>]>>>>>>>>>>>>>>>>>>>>>>>>>>[-]>[-]>[-]>[-]>[-]>[-]>[-]>[-]<<<<<<<<[>+>>>>>>+<<<<<<<-]>>>>>>>[<<<<<<<+>>>>>>>-]<<<<<<[->+[>>>>>+>+<<<<<<-]>>>>>>[<<<<<<+>>>>>>-]<----------<<+>>[<<->>[-]]<<[<<<[-]>+[>>>>+>+<<<<<-]>>>>>[<<<<<+>>>>>-]<----------<+>[<->[-]]<[<<<[-]>+>>-]<-]<<<<]>>>[>>>+>+<<<<-]>>>>[<<<<+>>>>-]<[<<[-]+>>[-]]<<[<++++++++++++++++++++++++++++++++++++++++++++++++.[-]>>+<-]<<[>>>>+>+<<<<<-]>>>>>[<<<<<+>>>>>-]<[<<[-]+>>[-]]<[<[-]+>-]<[<<++++++++++++++++++++++++++++++++++++++++++++++++.>>-]<<[-]<++++++++++++++++++++++++++++++++++++++++++++++++.[-]<<[-]++++++++++.[-]<<<<<<<<<<<<<<<<<<<<<<<<<<<<[-]]<<+>-]<
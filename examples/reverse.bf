@@ -0,0 +1,2 @@
+Reads a line and prints it reversed
+>,----------[++++++++++>,----------]<[.<]++++++++++.
@@ -0,0 +1,20 @@
+//! Run brainfuck programs compiled into this example at build time, use
+//! `cargo run --example embed -p bf-rust-macros`
+
+use std::io::{self, Write};
+
+use bf_rust_macros::{bf, bf_file};
+
+fn main() -> io::Result<()> {
+	bf_file!("../examples/hello.bf")(io::empty(), io::stdout())?;
+
+	// Cat the input, with 0 at the end of the input ending the loop
+	let mut output = vec![];
+	bf!(",[.,]")(&b"Hello from a macro\n\0"[..], &mut output)?;
+	io::stdout().write_all(&output)?;
+
+	bf_file!("../examples/reverse.bf")(
+		&include_bytes!("../../examples/reverse.in")[..],
+		io::stdout(),
+	)
+}
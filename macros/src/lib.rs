@@ -0,0 +1,75 @@
+//! Brainfuck programs compiled into Rust at build time
+//!
+//! Both macros parse and optimise the program with the pipeline of `-O3`,
+//! then expand to a function `fn(impl Read, impl Write) -> io::Result<()>`
+//! running it, as generated by
+//! [`LinkedInstructions::to_rust`](bf_rust::instruction::LinkedInstructions::to_rust). The program
+//! runs on the default machine, with 65536 cells and an error when reading
+//! past the end of the input.
+//!
+//! Mistakes in the program, like unbalanced brackets, are reported as compile
+//! errors.
+
+use std::path::PathBuf;
+
+use bf_rust::error::Error;
+use bf_rust::instruction::UnlinkedInstructions;
+use bf_rust::pass::PassManager;
+use proc_macro::TokenStream;
+use syn::{LitStr, parse_macro_input};
+
+/// Compile the brainfuck program in a string literal into a function
+///
+/// `bf!("++++++++[>++++++++<-]>+.")(std::io::stdin(), std::io::stdout())`
+/// prints an `A`
+///
+/// A program with unbalanced brackets doesn't compile
+///
+/// ```compile_fail
+/// bf_rust_macros::bf!("+[>+[-]<")(std::io::empty(), std::io::sink()).unwrap();
+/// ```
+#[proc_macro]
+pub fn bf(input: TokenStream) -> TokenStream {
+	let literal = parse_macro_input!(input as LitStr);
+
+	match compile(literal.value().as_bytes()) {
+		Ok(function) => expand(&function, ""),
+		Err(e) => syn::Error::new(literal.span(), e).to_compile_error().into(),
+	}
+}
+
+/// Compile the brainfuck program in a file into a function, see [`bf!`]
+///
+/// The path is relative to the directory of the crate's `Cargo.toml`, and
+/// changing the file rebuilds the crate
+#[proc_macro]
+pub fn bf_file(input: TokenStream) -> TokenStream {
+	let literal = parse_macro_input!(input as LitStr);
+
+	let mut path = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default());
+	path.push(literal.value());
+
+	let result = std::fs::read(&path)
+		.map_err(|e| format!("Could not read {}: {}", path.display(), e))
+		.and_then(|bytes| compile(&bytes).map_err(|e| e.to_string()));
+
+	match result {
+		// Including the file makes cargo track it
+		Ok(function) => expand(&function, &format!("const _: &[u8] = include_bytes!({:?});", path)),
+		Err(e) => syn::Error::new(literal.span(), e).to_compile_error().into(),
+	}
+}
+
+/// Parse and optimise a program, then translate it into a function `run`
+fn compile(bytes: &[u8]) -> Result<String, Error> {
+	let passes = PassManager::from_level(3)?;
+	let (insts, _) = passes.run(UnlinkedInstructions::from_text(bytes))?;
+
+	insts.to_rust(&Default::default())
+}
+
+/// A block defining the function, and any other items, that evaluates to the
+/// function
+fn expand(function: &str, items: &str) -> TokenStream {
+	format!("{{ {} {} run }}", items, function).parse().unwrap()
+}
@@ -0,0 +1,65 @@
+//! The functions `bf!` and `bf_file!` expand to against the interpreter
+
+use std::io::ErrorKind;
+
+use bf_rust::error::Error;
+use bf_rust::instruction::UnlinkedInstructions;
+use bf_rust::interpret::Interpreter;
+use bf_rust::pass::PassManager;
+use bf_rust_macros::{bf, bf_file};
+
+/// Run a program unoptimised on the interpreter, returning its output and
+/// whether it read past the end of its input
+fn interpret(source: &[u8], input: &[u8]) -> (Vec<u8>, bool) {
+	let passes = PassManager::from_level(0).unwrap();
+	let (insts, _) = passes.run(UnlinkedInstructions::from_text(source)).unwrap();
+
+	let mut output = vec![];
+	match Interpreter::new(&insts).run_with(&mut &input[..], &mut output) {
+		Ok(()) => (output, false),
+		Err(Error::CouldNotReadInput) => (output, true),
+		Err(e) => panic!("{}", e),
+	}
+}
+
+/// Check the outcome of an expanded function against the interpreter
+fn check(
+	run: impl FnOnce(&[u8], &mut Vec<u8>) -> std::io::Result<()>,
+	source: &[u8],
+	input: &[u8],
+) {
+	let mut output = vec![];
+	let failed = match run(input, &mut output) {
+		Ok(()) => false,
+		Err(e) if e.kind() == ErrorKind::UnexpectedEof => true,
+		Err(e) => panic!("{}", e),
+	};
+
+	assert_eq!((output, failed), interpret(source, input));
+}
+
+#[test]
+fn literal() {
+	check(
+		|i, o| bf!("+++[>+++++<-]>[<++++>-]<+++++.")(i, o),
+		b"+++[>+++++<-]>[<++++>-]<+++++.",
+		b"",
+	);
+	check(|i, o| bf!(",[.,]")(i, o), b",[.,]", b"cat\0");
+	check(|i, o| bf!(",.,.,.")(i, o), b",.,.,.", b"ab");
+}
+
+#[test]
+fn file() {
+	let examples = concat!(env!("CARGO_MANIFEST_DIR"), "/../examples/");
+	let source = |name: &str| std::fs::read(format!("{}{}", examples, name)).unwrap();
+
+	check(|i, o| bf_file!("../examples/hello.bf")(i, o), &source("hello.bf"), b"");
+	check(|i, o| bf_file!("../examples/squares.bf")(i, o), &source("squares.bf"), b"");
+	check(|i, o| bf_file!("../examples/primes.bf")(i, o), &source("primes.bf"), b"");
+	check(
+		|i, o| bf_file!("../examples/reverse.bf")(i, o),
+		&source("reverse.bf"),
+		&source("reverse.in"),
+	);
+}
@@ -0,0 +1,281 @@
+//! A backend that compiles instructions into a tree of closures
+//!
+//! Every instruction becomes a closure specialised to its operands and to the
+//! tape, every basic block becomes a closure running the instructions in it,
+//! and every loop becomes a closure running its body, so running a program
+//! never has to look at an [`Instruction`] again.
+
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::error::Error;
+use crate::instruction::{Ast, Instruction, LinkedInstructions, Node};
+use crate::interpret::{FullTape, MAX_TAPE_SIZE, Options, ShortTape, Tape, read_cell};
+
+/// A compiled basic block, loop, or I/O instruction
+type Block = Box<dyn Fn(&mut Machine, &mut Io<'_>) -> Result<(), Error>>;
+
+/// A compiled instruction that can't fail
+type Op = Box<dyn Fn(&mut Machine)>;
+
+/// The tape and data pointer the closures operate on
+struct Machine {
+	dp:     u16,
+	memory: Box<[u8; MAX_TAPE_SIZE]>,
+}
+
+/// The streams a running program reads from and writes to
+struct Io<'a> {
+	reader: &'a mut dyn Read,
+	writer: &'a mut dyn Write,
+}
+
+/// A program compiled into closures, together with the machine it runs on
+pub struct ClosureProgram {
+	root:      Block,
+	machine:   Machine,
+	tape_size: usize,
+}
+
+impl ClosureProgram {
+	/// Compile linked instructions for the default machine
+	pub fn new(insts: &LinkedInstructions) -> Result<Self, Error> {
+		Self::with_options(insts, Options::default())
+	}
+
+	/// Compile linked instructions for a differently configured machine, the
+	/// tape size gets clamped to `1..=MAX_TAPE_SIZE`
+	pub fn with_options(insts: &LinkedInstructions, options: Options) -> Result<Self, Error> {
+		let tape_size = options.tape_size.clamp(1, MAX_TAPE_SIZE);
+
+		let ast = Ast::from_linked(insts)?;
+		let root = match tape_size {
+			MAX_TAPE_SIZE => Compiler { tape: FullTape, options }.block(&ast.0),
+			size => Compiler { tape: ShortTape(size), options }.block(&ast.0),
+		};
+		let machine = Machine { dp: 0, memory: Box::new([0; MAX_TAPE_SIZE]) };
+
+		Ok(Self { root, machine, tape_size })
+	}
+
+	/// Run the program
+	pub fn run(&mut self) -> Result<(), Error> {
+		let mut writer = BufWriter::new(std::io::stdout());
+		let mut reader = BufReader::new(std::io::stdin());
+
+		self.run_with(&mut reader, &mut writer)
+	}
+
+	/// Run the program, reading from and writing to the given streams instead
+	/// of stdin and stdout
+	pub fn run_with<R: Read, W: Write>(
+		&mut self,
+		reader: &mut R,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let mut io = Io { reader, writer };
+		let result = (self.root)(&mut self.machine, &mut io);
+		io.writer.flush()?;
+
+		result
+	}
+
+	/// The position of the data pointer
+	pub fn dp(&self) -> u16 { self.machine.dp }
+
+	/// The contents of the tape
+	pub fn memory(&self) -> &[u8] { &self.machine.memory[..self.tape_size] }
+}
+
+/// Turns nodes into closures for a specific tape
+struct Compiler<T: Tape> {
+	tape:    T,
+	options: Options,
+}
+
+impl<T: Tape + 'static> Compiler<T> {
+	/// Compile a sequence of nodes into a single closure, grouping runs of
+	/// infallible instructions into basic blocks
+	fn block(&self, nodes: &[Node]) -> Block {
+		let mut parts: Vec<Block> = vec![];
+		let mut ops: Vec<Op> = vec![];
+
+		for node in nodes {
+			if let Node::Inst(inst) = node {
+				if let Some(op) = self.op(*inst) {
+					ops.push(op);
+					continue;
+				}
+			}
+
+			if !ops.is_empty() {
+				parts.push(basic_block(std::mem::take(&mut ops)));
+			}
+			parts.push(self.node(node));
+		}
+		if !ops.is_empty() {
+			parts.push(basic_block(ops));
+		}
+
+		match parts.len() {
+			0 => Box::new(|_, _| Ok(())),
+			1 => parts.pop().unwrap(),
+			_ => {
+				Box::new(move |m, io| {
+					for part in parts.iter() {
+						part(m, io)?;
+					}
+
+					Ok(())
+				})
+			},
+		}
+	}
+
+	/// Compile a loop, an if, or an instruction that does I/O
+	fn node(&self, node: &Node) -> Block {
+		let tape = self.tape;
+
+		match node {
+			Node::Loop(body) => {
+				let body = self.block(body);
+
+				Box::new(move |m, io| {
+					while m.memory[tape.wrap(m.dp, 0) as usize] != 0 {
+						body(m, io)?;
+					}
+
+					Ok(())
+				})
+			},
+			Node::If(body) => {
+				let body = self.block(body);
+
+				Box::new(move |m, io| {
+					if m.memory[tape.wrap(m.dp, 0) as usize] != 0 { body(m, io) } else { Ok(()) }
+				})
+			},
+			Node::Inst(Instruction::Read) => {
+				let eof = self.options.eof;
+
+				Box::new(move |m, io| {
+					io.writer.flush()?;
+					read_cell(io.reader, eof, &mut m.memory[tape.wrap(m.dp, 0) as usize])
+				})
+			},
+			Node::Inst(Instruction::Write) => {
+				Box::new(move |m, io| {
+					io.writer.write_all(&[m.memory[tape.wrap(m.dp, 0) as usize]])?;
+					Ok(())
+				})
+			},
+			Node::Inst(Instruction::WriteDecimal) => {
+				Box::new(move |m, io| {
+					write!(io.writer, "{}", m.memory[tape.wrap(m.dp, 0) as usize])?;
+
+					for ofst in 1..=8 {
+						m.memory[tape.wrap(m.dp, ofst) as usize] = 0;
+					}
+					Ok(())
+				})
+			},
+			// The tree structure of the Ast already took care of branches
+			Node::Inst(_) => Box::new(|_, _| Ok(())),
+		}
+	}
+
+	/// Compile an instruction that can't fail, `None` for I/O and branches
+	fn op(&self, inst: Instruction) -> Option<Op> {
+		let tape = self.tape;
+
+		// Any u16 is a valid index into the memory, and the tape makes sure
+		// only its own cells get used
+		macro_rules! cell {
+			($m:ident, $ofst:expr) => {
+				$m.memory[tape.wrap($m.dp, $ofst as i32) as usize]
+			};
+		}
+
+		let op: Op = match inst {
+			Instruction::IncrDp { amount } => {
+				Box::new(move |m| m.dp = tape.wrap(m.dp, amount as i32))
+			},
+			Instruction::Incr { amount, offset } => {
+				Box::new(move |m| cell!(m, offset) = cell!(m, offset).wrapping_add(amount as u8))
+			},
+			Instruction::Set { amount, offset } => {
+				Box::new(move |m| cell!(m, offset) = amount as u8)
+			},
+			Instruction::Mul { amount, offset } => {
+				Box::new(move |m| {
+					cell!(m, offset) =
+						cell!(m, offset).wrapping_add(cell!(m, 0).wrapping_mul(amount as u8))
+				})
+			},
+			Instruction::MulAcc { amount, source, offset } => {
+				Box::new(move |m| {
+					let product =
+						cell!(m, 0).wrapping_mul(cell!(m, source)).wrapping_mul(amount as u8);
+					cell!(m, offset) = cell!(m, offset).wrapping_add(product);
+				})
+			},
+			Instruction::TriAcc { amount, offset } => {
+				Box::new(move |m| {
+					let n = cell!(m, 0) as u32;
+					let triangle = (n * (n + 1) / 2) as u8;
+
+					cell!(m, offset) =
+						cell!(m, offset).wrapping_add(triangle.wrapping_mul(amount as u8));
+				})
+			},
+			Instruction::DivMod => {
+				Box::new(move |m| {
+					let n = cell!(m, 0) as u16;
+					let d = match cell!(m, 1) {
+						0 => 256,
+						d => d as u16,
+					};
+
+					cell!(m, 0) = 0;
+					cell!(m, 2) = (n % d) as u8;
+					cell!(m, 3) = (n / d) as u8;
+					cell!(m, 4) = (d - n % d) as u8;
+					cell!(m, 5) = 0;
+					cell!(m, 6) = 0;
+				})
+			},
+			Instruction::Compare => {
+				Box::new(move |m| {
+					let x = cell!(m, 0);
+					let y = cell!(m, 1);
+
+					cell!(m, 0) = (x < y) as u8;
+					for ofst in 2..=6 {
+						cell!(m, ofst) = 0;
+					}
+				})
+			},
+			_ => return None,
+		};
+
+		Some(op)
+	}
+}
+
+/// Run a straight line of instructions
+fn basic_block(mut ops: Vec<Op>) -> Block {
+	if ops.len() == 1 {
+		let op = ops.pop().unwrap();
+		return Box::new(move |m, _| {
+			op(m);
+			Ok(())
+		});
+	}
+
+	Box::new(move |m, _| {
+		for op in ops.iter() {
+			op(m);
+		}
+
+		Ok(())
+	})
+}
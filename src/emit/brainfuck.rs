@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+
+use crate::error::Error;
+use crate::idiom::IDIOMS;
+use crate::instruction::{Ast, Instruction, LinkedInstructions, Node};
+
+/// How far away from the cell it changes a temporary cell may be
+const MAX_TEMPORARY_DISTANCE: i32 = 8;
+
+impl LinkedInstructions {
+	/// Translate the instructions back into brainfuck, using nothing but its
+	/// eight commands
+	///
+	/// Constants are built with the shortest of adding them one by one and
+	/// multiplying in a temporary cell known to be zero. Multiplications that
+	/// read a cell other than their own counter, or keep their counter, need
+	/// such a temporary as well, or a cell that gets overwritten right after
+	/// them, and fail with [`Error::NoTemporaryCell`] otherwise.
+	pub fn to_brainfuck(&self) -> Result<String, Error> {
+		let ast = Ast::from_linked(self)?;
+
+		let mut decompiler = Decompiler {
+			bf:        String::with_capacity(ast.instruction_count() * 8),
+			cursor:    0,
+			known:     Knowledge { values: HashMap::new(), zeroed: true },
+			constants: Constants::new(),
+		};
+		decompiler.block(&ast.0)?;
+
+		// Moving the pointer at the very end does nothing
+		let len = decompiler.bf.trim_end_matches(['<', '>']).len();
+		decompiler.bf.truncate(len);
+		decompiler.bf.push('\n');
+
+		Ok(decompiler.bf)
+	}
+}
+
+/// What is known about the values of the cells, by their offset from the data
+/// pointer
+struct Knowledge {
+	values: HashMap<i32, Option<u8>>,
+	/// Whether every cell missing from `values` is 0, which holds until the
+	/// first loop
+	zeroed: bool,
+}
+
+impl Knowledge {
+	/// Nothing is known, except perhaps the value of the current cell
+	fn unknown(current: Option<u8>) -> Self {
+		Self { values: HashMap::from([(0, current)]), zeroed: false }
+	}
+
+	fn get(&self, offset: i32) -> Option<u8> {
+		match self.values.get(&offset) {
+			Some(value) => *value,
+			None if self.zeroed => Some(0),
+			None => None,
+		}
+	}
+
+	fn set(&mut self, offset: i32, value: Option<u8>) { self.values.insert(offset, value); }
+
+	/// Move the data pointer by `amount` cells
+	fn shift(&mut self, amount: i32) {
+		self.values = self.values.drain().map(|(offset, value)| (offset - amount, value)).collect();
+	}
+}
+
+/// The shortest way to add each value to a cell with a loop, ignoring the
+/// pointer movement between the cell and its temporary
+///
+/// Running `n` times and adding `m` each time adds `n * m`, which is then
+/// corrected by adding one more constant
+struct Constants {
+	/// The iterations, the amount per iteration, the correction, and the
+	/// length of all the `+`, `-`, `[` and `]` involved
+	best: [(u8, u8, u8, usize); 256],
+}
+
+impl Constants {
+	fn new() -> Self {
+		let mut products = [(0, 0, usize::MAX); 256];
+		for iterations in 2..=u8::MAX {
+			for amount in 2..=u8::MAX {
+				// The loop and the decrement of the temporary
+				let len = adds(iterations) + adds(amount) + 3;
+				let product = &mut products[iterations.wrapping_mul(amount) as usize];
+				if len < product.2 {
+					*product = (iterations, amount, len);
+				}
+			}
+		}
+
+		let mut best = [(0, 0, 0, usize::MAX); 256];
+		for (value, best) in best.iter_mut().enumerate() {
+			for (product, (iterations, amount, len)) in products.iter().enumerate() {
+				if *len == usize::MAX {
+					continue;
+				}
+
+				let correction = (value as u8).wrapping_sub(product as u8);
+				let len = len + adds(correction);
+				if len < best.3 {
+					*best = (*iterations, *amount, correction, len);
+				}
+			}
+		}
+
+		Self { best }
+	}
+}
+
+/// The number of `+` or `-` needed to add a value
+fn adds(value: u8) -> usize { value.min(value.wrapping_neg()) as usize }
+
+struct Decompiler {
+	bf:        String,
+	/// The offset of the cell the brainfuck pointer is at, from the data
+	/// pointer of the instructions
+	cursor:    i32,
+	known:     Knowledge,
+	constants: Constants,
+}
+
+impl Decompiler {
+	fn block(&mut self, nodes: &[Node]) -> Result<(), Error> {
+		let mut idx = 0;
+		while idx < nodes.len() {
+			match &nodes[idx] {
+				Node::Inst(inst) if is_multiplication(*inst) => {
+					let len = nodes[idx..]
+						.iter()
+						.take_while(|n| matches!(n, Node::Inst(i) if is_multiplication(*i)))
+						.count();
+					let group: Vec<_> = nodes[idx..idx + len]
+						.iter()
+						.filter_map(|n| if let Node::Inst(i) = n { Some(*i) } else { None })
+						.collect();
+
+					self.multiply(&group, &nodes[idx + len..])?;
+					idx += len;
+					continue;
+				},
+				Node::Inst(inst) => self.instruction(*inst),
+				// An If ends with its cell at 0 just like a loop, so it can
+				// become one
+				Node::Loop(body) | Node::If(body) => {
+					self.go(0);
+					self.bf.push('[');
+					self.known = Knowledge::unknown(None);
+					self.block(body)?;
+					self.go(0);
+					self.bf.push(']');
+					self.known = Knowledge::unknown(Some(0));
+				},
+			}
+
+			idx += 1;
+		}
+
+		Ok(())
+	}
+
+	fn instruction(&mut self, inst: Instruction) {
+		match inst {
+			Instruction::IncrDp { amount } => {
+				self.cursor -= amount as i32;
+				self.known.shift(amount as i32);
+			},
+			Instruction::Incr { amount, offset } => self.add(offset as i32, amount as u8),
+			Instruction::Set { amount, offset } => {
+				let offset = offset as i32;
+				match self.known.get(offset) {
+					Some(value) => self.add(offset, (amount as u8).wrapping_sub(value)),
+					None => {
+						self.go(offset);
+						self.bf.push_str("[-]");
+						self.known.set(offset, Some(0));
+						self.add(offset, amount as u8);
+					},
+				}
+			},
+			Instruction::Read => {
+				self.go(0);
+				self.bf.push(',');
+				self.known.set(0, None);
+			},
+			Instruction::Write => {
+				self.go(0);
+				self.bf.push('.');
+			},
+			Instruction::WriteDecimal | Instruction::DivMod | Instruction::Compare => {
+				// Unwrap is safe as these instructions only come from idioms
+				let idiom = IDIOMS.iter().find(|idiom| idiom.replacement == inst).unwrap();
+
+				self.go(0);
+				self.bf.push_str(idiom.source);
+				for offset in 0..idiom.width {
+					self.known.set(offset as i32, None);
+				}
+			},
+			// Multiplications are handled in groups by multiply, and the tree
+			// structure of the Ast already took care of branches
+			Instruction::Mul { .. }
+			| Instruction::MulAcc { .. }
+			| Instruction::TriAcc { .. }
+			| Instruction::BranchIfZero { .. }
+			| Instruction::BranchIfNotZero { .. }
+			| Instruction::If { .. }
+			| Instruction::EndIf => (),
+		}
+	}
+
+	/// Add a value to the cell at an offset, multiplying it in a temporary
+	/// cell if that is shorter
+	fn add(&mut self, offset: i32, value: u8) {
+		if value == 0 {
+			return;
+		}
+
+		let (iterations, amount, correction, len) = self.constants.best[value as usize];
+		let direct = self.cursor.abs_diff(offset) as usize + adds(value);
+
+		let temporary = self.zero_cell_near(offset, &[]).filter(|temporary| {
+			let distance = temporary.abs_diff(offset) as usize;
+			self.cursor.abs_diff(*temporary) as usize + 3 * distance + len < direct
+		});
+
+		match temporary {
+			Some(temporary) => {
+				self.go(temporary);
+				push_adds(&mut self.bf, iterations);
+				self.bf.push('[');
+				self.go(offset);
+				push_adds(&mut self.bf, amount);
+				self.go(temporary);
+				self.bf.push_str("-]");
+				self.go(offset);
+				push_adds(&mut self.bf, correction);
+			},
+			None => {
+				self.go(offset);
+				push_adds(&mut self.bf, value);
+			},
+		}
+
+		let known = self.known.get(offset).map(|known| known.wrapping_add(value));
+		self.known.set(offset, known);
+	}
+
+	/// Translate consecutive Mul, MulAcc and TriAcc instructions into a single
+	/// loop counting down the current cell
+	///
+	/// If a Set of the current cell follows them, the loop may clear it,
+	/// otherwise a temporary cell gets counted up alongside it to restore it
+	/// afterwards. MulAcc and TriAcc copy a cell in every iteration, which
+	/// needs another temporary.
+	fn multiply(&mut self, group: &[Instruction], rest: &[Node]) -> Result<(), Error> {
+		let mut used = vec![0];
+		for inst in group {
+			match *inst {
+				Instruction::Mul { offset, .. } | Instruction::TriAcc { offset, .. } => {
+					used.push(offset as i32);
+				},
+				Instruction::MulAcc { source, offset, .. } => {
+					used.extend([source as i32, offset as i32]);
+				},
+				_ => (),
+			}
+		}
+
+		// Cells that get overwritten after the group before anything reads them
+		// can be used as well, once they are cleared
+		let mut overwritten = vec![];
+		let mut read = vec![];
+		let mut clears_counter = false;
+		for node in rest {
+			match node {
+				Node::Inst(Instruction::Set { offset: 0, .. }) => {
+					clears_counter = true;
+					break;
+				},
+				Node::Inst(Instruction::Set { offset, .. })
+					if !read.contains(&(*offset as i32)) =>
+				{
+					overwritten.push(*offset as i32);
+				},
+				Node::Inst(Instruction::Set { .. }) => (),
+				Node::Inst(Instruction::Incr { offset, .. }) if *offset != 0 => {
+					read.push(*offset as i32);
+				},
+				_ => break,
+			}
+		}
+
+		let copies = group
+			.iter()
+			.any(|i| matches!(i, Instruction::MulAcc { .. } | Instruction::TriAcc { .. }));
+		let mut temporaries = vec![];
+		for _ in 0..(!clears_counter as usize + copies as usize) {
+			let excluded: Vec<_> = used.iter().chain(&temporaries).copied().collect();
+			let temporary = match self.zero_cell_near(0, &excluded) {
+				Some(temporary) => temporary,
+				None => {
+					let temporary = overwritten
+						.iter()
+						.copied()
+						.find(|o| !excluded.contains(o))
+						.ok_or(Error::NoTemporaryCell)?;
+					self.go(temporary);
+					self.bf.push_str("[-]");
+					self.known.set(temporary, Some(0));
+					temporary
+				},
+			};
+			temporaries.push(temporary);
+		}
+		let restore = if clears_counter { None } else { temporaries.pop() };
+		let copy = temporaries.pop();
+
+		self.go(0);
+		self.bf.push_str("[-");
+		for inst in group {
+			match *inst {
+				Instruction::Mul { amount, offset } => {
+					self.go(offset as i32);
+					push_adds(&mut self.bf, amount as u8);
+				},
+				Instruction::MulAcc { amount, source, offset } => {
+					// Unwrap is safe as a temporary was found for copies
+					self.add_copy(source as i32, offset as i32, amount as u8, copy.unwrap());
+				},
+				Instruction::TriAcc { amount, offset } => {
+					// The counter was already decremented, so it has to be added
+					// once more
+					self.go(offset as i32);
+					push_adds(&mut self.bf, amount as u8);
+					self.add_copy(0, offset as i32, amount as u8, copy.unwrap());
+				},
+				_ => (),
+			}
+		}
+		if let Some(restore) = restore {
+			self.go(restore);
+			self.bf.push('+');
+		}
+		self.go(0);
+		self.bf.push(']');
+
+		match restore {
+			Some(restore) => {
+				self.go(restore);
+				self.bf.push_str("[-");
+				self.go(0);
+				self.bf.push('+');
+				self.go(restore);
+				self.bf.push(']');
+			},
+			None => self.known.set(0, Some(0)),
+		}
+		for offset in used.into_iter().skip(1) {
+			self.known.set(offset, None);
+		}
+
+		Ok(())
+	}
+
+	/// Add a multiple of the cell at `source` to the one at `offset`, using a
+	/// temporary cell to restore the source
+	fn add_copy(&mut self, source: i32, offset: i32, amount: u8, temporary: i32) {
+		self.go(source);
+		self.bf.push_str("[-");
+		self.go(offset);
+		push_adds(&mut self.bf, amount);
+		self.go(temporary);
+		self.bf.push('+');
+		self.go(source);
+		self.bf.push(']');
+
+		self.go(temporary);
+		self.bf.push_str("[-");
+		self.go(source);
+		self.bf.push('+');
+		self.go(temporary);
+		self.bf.push(']');
+	}
+
+	/// The closest cell to the one at an offset that is known to be 0, apart
+	/// from the excluded ones
+	fn zero_cell_near(&self, offset: i32, excluded: &[i32]) -> Option<i32> {
+		(1..=MAX_TEMPORARY_DISTANCE)
+			.flat_map(|distance| [offset + distance, offset - distance])
+			.find(|o| *o != offset && !excluded.contains(o) && self.known.get(*o) == Some(0))
+	}
+
+	/// Move the pointer to the cell at an offset
+	fn go(&mut self, offset: i32) {
+		let direction = if offset > self.cursor { '>' } else { '<' };
+		for _ in 0..self.cursor.abs_diff(offset) {
+			self.bf.push(direction);
+		}
+		self.cursor = offset;
+	}
+}
+
+fn is_multiplication(inst: Instruction) -> bool {
+	matches!(
+		inst,
+		Instruction::Mul { .. } | Instruction::MulAcc { .. } | Instruction::TriAcc { .. }
+	)
+}
+
+/// Add a value to the current cell with `+` or `-`, whichever is shorter
+fn push_adds(bf: &mut String, value: u8) {
+	let (command, count) = if value <= 128 { ('+', value) } else { ('-', value.wrapping_neg()) };
+	for _ in 0..count {
+		bf.push(command);
+	}
+}
@@ -0,0 +1,157 @@
+use std::fmt::Write;
+
+use itertools::Itertools;
+
+use crate::error::Error;
+use crate::instruction::{Ast, Instruction, LinkedInstructions, Node};
+use crate::interpret::{Eof, MAX_TAPE_SIZE, Options};
+
+/// Everything in front of the translated program
+///
+/// Offsets are always reduced to `0..TAPE_SIZE`, so moving the pointer never
+/// has to wrap around the start of the tape, and never leaves it either
+const PRELUDE: &str = "\
+#include <stdint.h>
+#include <stdio.h>
+
+static uint8_t tape[TAPE_SIZE];
+#define END (tape + TAPE_SIZE)
+
+/* The cell o cells to the right of p, wrapping around the end of the tape */
+#define CELL(o) (*((o) < END - p ? p + (o) : p + ((o) - TAPE_SIZE)))
+/* Move p o cells to the right, wrapping around the end of the tape */
+#define MOVE(o) (p = (o) < END - p ? p + (o) : p + ((o) - TAPE_SIZE))
+
+int main(void) {
+\tuint8_t *p = tape;
+";
+
+impl LinkedInstructions {
+	/// Translate the instructions into a standalone C program
+	pub fn to_c(&self, options: &Options) -> Result<String, Error> {
+		let tape_size = options.tape_size.clamp(1, MAX_TAPE_SIZE);
+		let ast = Ast::from_linked(self)?;
+
+		let mut c = String::with_capacity(ast.instruction_count() * 16 + PRELUDE.len());
+		writeln!(c, "#define TAPE_SIZE {}", tape_size).unwrap();
+		c.push_str(PRELUDE);
+		if self.0.contains(&Instruction::Read) {
+			c.push_str("\tint c;\n");
+		}
+		c.push('\n');
+
+		let emitter = CEmitter { tape_size: tape_size as i64, eof: options.eof };
+		emitter.block(&mut c, &ast.0, 1);
+
+		c.push_str("\n\tfflush(stdout);\n\treturn 0;\n}\n");
+
+		Ok(c)
+	}
+}
+
+struct CEmitter {
+	tape_size: i64,
+	eof:       Eof,
+}
+
+impl CEmitter {
+	fn block(&self, c: &mut String, nodes: &[Node], depth: usize) {
+		for node in nodes {
+			match node {
+				Node::Inst(inst) => self.instruction(c, *inst, depth),
+				Node::Loop(body) => {
+					line(c, depth, "while (*p) {");
+					self.block(c, body, depth + 1);
+					line(c, depth, "}");
+				},
+				Node::If(body) => {
+					line(c, depth, "if (*p) {");
+					self.block(c, body, depth + 1);
+					line(c, depth, "}");
+				},
+			}
+		}
+	}
+
+	/// The cell at an offset from `p`
+	fn cell(&self, offset: i16) -> String {
+		match (offset as i64).rem_euclid(self.tape_size) {
+			0 => "*p".to_owned(),
+			offset => format!("CELL({})", offset),
+		}
+	}
+
+	fn instruction(&self, c: &mut String, inst: Instruction, depth: usize) {
+		let code = match inst {
+			Instruction::IncrDp { amount } => {
+				match (amount as i64).rem_euclid(self.tape_size) {
+					0 => return,
+					amount => format!("MOVE({});", amount),
+				}
+			},
+			Instruction::Incr { amount, offset } => {
+				format!("{} += {};", self.cell(offset), amount as u8)
+			},
+			Instruction::Set { amount, offset } => {
+				format!("{} = {};", self.cell(offset), amount as u8)
+			},
+			Instruction::Mul { amount, offset } => {
+				format!("{} += *p * {};", self.cell(offset), amount as u8)
+			},
+			Instruction::MulAcc { amount, source, offset } => {
+				format!("{} += *p * {} * {};", self.cell(offset), self.cell(source), amount as u8)
+			},
+			Instruction::TriAcc { amount, offset } => {
+				format!("{} += (uint8_t)(*p * (*p + 1) / 2) * {};", self.cell(offset), amount as u8)
+			},
+			Instruction::Read => {
+				let on_eof = match self.eof {
+					Eof::Error => {
+						"{ fputs(\"Failed to read input\\n\", stderr); fflush(stdout); return 1; }"
+					},
+					Eof::Unchanged => "{}",
+					Eof::Zero => "*p = 0;",
+					Eof::MinusOne => "*p = 255;",
+				};
+
+				line(c, depth, "fflush(stdout);");
+				format!("if ((c = getchar()) != EOF) *p = c; else {}", on_eof)
+			},
+			Instruction::Write => "putchar(*p);".to_owned(),
+			Instruction::WriteDecimal => {
+				line(c, depth, "printf(\"%d\", *p);");
+				(1..=8).map(|offset| format!("{} = 0;", self.cell(offset))).join(" ")
+			},
+			Instruction::DivMod => {
+				line(c, depth, "{");
+				let divisor = self.cell(1);
+				line(c, depth + 1, &format!("int n = *p, d = {0} ? {0} : 256;", divisor));
+				line(c, depth + 1, "*p = 0;");
+				let results = [(2, "n % d"), (3, "n / d"), (4, "d - n % d"), (5, "0"), (6, "0")];
+				for (offset, value) in results {
+					line(c, depth + 1, &format!("{} = {};", self.cell(offset), value));
+				}
+				"}".to_owned()
+			},
+			Instruction::Compare => {
+				line(c, depth, &format!("*p = *p < {};", self.cell(1)));
+				(2..=6).map(|offset| format!("{} = 0;", self.cell(offset))).join(" ")
+			},
+			// The tree structure of the Ast already took care of branches
+			Instruction::BranchIfZero { .. }
+			| Instruction::BranchIfNotZero { .. }
+			| Instruction::If { .. }
+			| Instruction::EndIf => return,
+		};
+
+		line(c, depth, &code);
+	}
+}
+
+fn line(c: &mut String, depth: usize, code: &str) {
+	for _ in 0..depth {
+		c.push('\t');
+	}
+	c.push_str(code);
+	c.push('\n');
+}
@@ -0,0 +1,271 @@
+//! Static x86-64 Linux executables
+//!
+//! The program is compiled by the same [`Assembler`] as the JIT, but instead
+//! of calling back into Rust it calls a small runtime doing buffered I/O with
+//! raw `read` and `write` syscalls, so the executable needs nothing but the
+//! kernel. It consists of a single loadable segment with the code, and one
+//! zero initialised segment holding the runtime's state and the tape.
+
+use crate::error::Error;
+use crate::instruction::{Ast, LinkedInstructions};
+use crate::interpret::{Eof, MAX_TAPE_SIZE, Options};
+use crate::jit::assembler::{Assembler, Callbacks};
+
+/// The address the file gets loaded at
+const BASE: u64 = 0x400000;
+const PAGE_SIZE: u64 = 0x1000;
+/// The size of the ELF header followed by the two program headers
+const HEADERS_LEN: u64 = 64 + 2 * 56;
+
+/// The size of both I/O buffers
+const BUFFER_SIZE: u64 = 4096;
+/// The offset of the tape in the zero initialised segment, which starts with
+/// the context passed to the runtime:
+///  - `0`: the data pointer, see [`Assembler::compile`]
+///  - `8`: the number of buffered output bytes
+///  - `16`: the position in the input buffer
+///  - `24`: the number of buffered input bytes
+///  - `32`: the output buffer, followed by the input buffer
+const TAPE_OFFSET: u64 = 32 + 2 * BUFFER_SIZE;
+
+/// The offsets of the runtime's functions, which take the context in `rdi`
+/// and return 0, 1 if a syscall failed, or 2 if reading hit the end of the
+/// input
+const FLUSH: u64 = 0x00;
+const WRITE: u64 = 0x40;
+const WRITE_DECIMAL: u64 = 0x5B;
+const READ: u64 = 0x8B;
+
+/// The runtime, up to what `read` does at the end of the input
+#[rustfmt::skip]
+const RUNTIME: [u8; 0xE1] = [
+	// flush: write out the output buffer
+	0x48, 0x8B, 0x57, 0x08,                   // mov rdx, [rdi + 8]
+	0x48, 0x8D, 0x77, 0x20,                   // lea rsi, [rdi + 32]
+	0x49, 0x89, 0xF8,                         // mov r8, rdi
+	0x48, 0x85, 0xD2,                         // .loop: test rdx, rdx
+	0x74, 0x19,                               // jz .done
+	0xB8, 0x01, 0x00, 0x00, 0x00,             // mov eax, 1 (write)
+	0xBF, 0x01, 0x00, 0x00, 0x00,             // mov edi, 1 (stdout)
+	0x0F, 0x05,                               // syscall
+	0x48, 0x85, 0xC0,                         // test rax, rax
+	0x7E, 0x16,                               // jle .fail
+	0x48, 0x01, 0xC6,                         // add rsi, rax
+	0x48, 0x29, 0xC2,                         // sub rdx, rax
+	0xEB, 0xE2,                               // jmp .loop
+	0x49, 0xC7, 0x40, 0x08, 0x00, 0x00, 0x00, // .done: mov qword [r8 + 8], 0
+	0x00,
+	0x4C, 0x89, 0xC7,                         // mov rdi, r8
+	0x31, 0xC0,                               // xor eax, eax
+	0xC3,                                     // ret
+	0x4C, 0x89, 0xC7,                         // .fail: mov rdi, r8
+	0xB8, 0x01, 0x00, 0x00, 0x00,             // mov eax, 1
+	0xC3,                                     // ret
+
+	// write: buffer the byte in sil
+	0x48, 0x8B, 0x47, 0x08,                   // mov rax, [rdi + 8]
+	0x40, 0x88, 0x74, 0x07, 0x20,             // mov [rdi + rax + 32], sil
+	0x48, 0xFF, 0xC0,                         // inc rax
+	0x48, 0x89, 0x47, 0x08,                   // mov [rdi + 8], rax
+	0x48, 0x3D, 0x00, 0x10, 0x00, 0x00,       // cmp rax, BUFFER_SIZE
+	0x74, 0xA8,                               // je flush
+	0x31, 0xC0,                               // xor eax, eax
+	0xC3,                                     // ret
+
+	// write_decimal: write sil as a decimal number
+	0x40, 0x0F, 0xB6, 0xC6,                   // movzx eax, sil
+	0xB9, 0x0A, 0x00, 0x00, 0x00,             // mov ecx, 10
+	0x45, 0x31, 0xC9,                         // xor r9d, r9d (digits)
+	0x45, 0x31, 0xD2,                         // xor r10d, r10d (status)
+	0x31, 0xD2,                               // .divide: xor edx, edx
+	0xF7, 0xF1,                               // div ecx
+	0x83, 0xC2, 0x30,                         // add edx, '0'
+	0x52,                                     // push rdx
+	0x41, 0xFF, 0xC1,                         // inc r9d
+	0x85, 0xC0,                               // test eax, eax
+	0x75, 0xF1,                               // jnz .divide
+	0x5E,                                     // .print: pop rsi
+	0xE8, 0xC1, 0xFF, 0xFF, 0xFF,             // call write
+	0x41, 0x09, 0xC2,                         // or r10d, eax
+	0x41, 0xFF, 0xC9,                         // dec r9d
+	0x75, 0xF2,                               // jnz .print
+	0x44, 0x89, 0xD0,                         // mov eax, r10d
+	0xC3,                                     // ret
+
+	// read: flush the output, then read a byte into [rsi]
+	0x56,                                     // push rsi
+	0xE8, 0x6F, 0xFF, 0xFF, 0xFF,             // call flush
+	0x5E,                                     // pop rsi
+	0x85, 0xC0,                               // test eax, eax
+	0x75, 0x44,                               // jnz .return
+	0x48, 0x8B, 0x47, 0x10,                   // mov rax, [rdi + 16]
+	0x48, 0x3B, 0x47, 0x18,                   // cmp rax, [rdi + 24]
+	0x72, 0x27,                               // jb .take
+	0x56,                                     // push rsi
+	0x49, 0x89, 0xF8,                         // mov r8, rdi
+	0x31, 0xC0,                               // xor eax, eax (read)
+	0x31, 0xFF,                               // xor edi, edi (stdin)
+	0x49, 0x8D, 0xB0, 0x20, 0x10, 0x00, 0x00, // lea rsi, [r8 + 4128]
+	0xBA, 0x00, 0x10, 0x00, 0x00,             // mov edx, BUFFER_SIZE
+	0x0F, 0x05,                               // syscall
+	0x4C, 0x89, 0xC7,                         // mov rdi, r8
+	0x5E,                                     // pop rsi
+	0x48, 0x85, 0xC0,                         // test rax, rax
+	0x78, 0x1C,                               // js .fail
+	0x74, 0x20,                               // jz .eof
+	0x48, 0x89, 0x47, 0x18,                   // mov [rdi + 24], rax
+	0x31, 0xC0,                               // xor eax, eax
+	0x0F, 0xB6, 0x8C, 0x07, 0x20, 0x10, 0x00, // .take: movzx ecx, [rdi + rax + 4128]
+	0x00,
+	0x88, 0x0E,                               // mov [rsi], cl
+	0x48, 0xFF, 0xC0,                         // inc rax
+	0x48, 0x89, 0x47, 0x10,                   // mov [rdi + 16], rax
+	0x31, 0xC0,                               // xor eax, eax
+	0xC3,                                     // .return: ret
+	0xB8, 0x01, 0x00, 0x00, 0x00,             // .fail: mov eax, 1
+	0xC3,                                     // ret
+	// .eof: see eof_handler
+];
+
+/// Printed if the program stops at the end of the input
+const EOF_MESSAGE: &[u8] = b"Failed to read input\n";
+
+/// The end of `read`, handling the end of the input
+fn eof_handler(eof: Eof) -> &'static [u8] {
+	match eof {
+		// mov eax, 2; ret
+		Eof::Error => &[0xB8, 0x02, 0x00, 0x00, 0x00, 0xC3],
+		// xor eax, eax; ret
+		Eof::Unchanged => &[0x31, 0xC0, 0xC3],
+		// mov byte [rsi], 0; xor eax, eax; ret
+		Eof::Zero => &[0xC6, 0x06, 0x00, 0x31, 0xC0, 0xC3],
+		// mov byte [rsi], 255; xor eax, eax; ret
+		Eof::MinusOne => &[0xC6, 0x06, 0xFF, 0x31, 0xC0, 0xC3],
+	}
+}
+
+impl LinkedInstructions {
+	/// Compile the instructions into a static x86-64 Linux executable
+	pub fn to_elf(&self, options: &Options) -> Result<Vec<u8>, Error> {
+		let tape_size = options.tape_size.clamp(1, MAX_TAPE_SIZE);
+		let ast = Ast::from_linked(self)?;
+
+		let runtime = BASE + HEADERS_LEN;
+		let callbacks = Callbacks {
+			read:          runtime + READ,
+			write:         runtime + WRITE,
+			write_decimal: runtime + WRITE_DECIMAL,
+		};
+		let program = Assembler::new(tape_size as u32, callbacks).compile(&ast.0);
+
+		let mut code = RUNTIME.to_vec();
+		code.extend_from_slice(eof_handler(options.eof));
+		let program_start = code.len() as u64;
+		code.extend_from_slice(&program);
+		let message = code.len() as u64;
+		code.extend_from_slice(EOF_MESSAGE);
+
+		let file_len = HEADERS_LEN + code.len() as u64 + START_LEN;
+		let context = (BASE + file_len).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+		let entry = runtime + code.len() as u64;
+		let start = Start {
+			entry,
+			context,
+			program: runtime + program_start,
+			flush: runtime + FLUSH,
+			message: runtime + message,
+		};
+		code.extend_from_slice(&start.compile());
+
+		let mut elf = Vec::with_capacity(file_len as usize);
+		elf_header(&mut elf, entry);
+		// The code, including the headers so the whole file can be mapped
+		program_header(&mut elf, 0b101, BASE, file_len, file_len);
+		// The context and the tape, zero initialised
+		program_header(&mut elf, 0b110, context, 0, TAPE_OFFSET + tape_size as u64);
+		elf.extend_from_slice(&code);
+
+		Ok(elf)
+	}
+}
+
+/// The length of the code [`Start::compile`] generates
+const START_LEN: u64 = 0x47;
+
+/// The entry point, which runs the program, flushes the output and exits
+struct Start {
+	/// The address of the entry point itself
+	entry:   u64,
+	context: u64,
+	program: u64,
+	flush:   u64,
+	message: u64,
+}
+
+impl Start {
+	fn compile(&self) -> Vec<u8> {
+		let mut code = Vec::with_capacity(START_LEN as usize);
+		// mov edi, context; mov esi, tape; call program
+		code.push(0xBF);
+		code.extend_from_slice(&(self.context as u32).to_le_bytes());
+		code.push(0xBE);
+		code.extend_from_slice(&((self.context + TAPE_OFFSET) as u32).to_le_bytes());
+		self.call(&mut code, self.program);
+		// mov ebx, eax; mov edi, context; call flush; or ebx, eax
+		code.extend_from_slice(&[0x89, 0xC3, 0xBF]);
+		code.extend_from_slice(&(self.context as u32).to_le_bytes());
+		self.call(&mut code, self.flush);
+		code.extend_from_slice(&[0x09, 0xC3]);
+		// cmp ebx, 2; jne .exit; mov eax, 1 (write); mov edi, 2 (stderr)
+		code.extend_from_slice(&[0x83, 0xFB, 0x02, 0x75, 0x16]);
+		code.extend_from_slice(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xBF, 0x02, 0x00, 0x00, 0x00]);
+		// mov esi, message; mov edx, len; syscall
+		code.push(0xBE);
+		code.extend_from_slice(&(self.message as u32).to_le_bytes());
+		code.push(0xBA);
+		code.extend_from_slice(&(EOF_MESSAGE.len() as u32).to_le_bytes());
+		code.extend_from_slice(&[0x0F, 0x05]);
+		// .exit: xor edi, edi; test ebx, ebx; setnz dil; mov eax, 60 (exit); syscall
+		code.extend_from_slice(&[0x31, 0xFF, 0x85, 0xDB, 0x40, 0x0F, 0x95, 0xC7]);
+		code.extend_from_slice(&[0xB8, 0x3C, 0x00, 0x00, 0x00, 0x0F, 0x05]);
+
+		debug_assert_eq!(code.len() as u64, START_LEN);
+		code
+	}
+
+	/// call target
+	fn call(&self, code: &mut Vec<u8>, target: u64) {
+		let next = self.entry + code.len() as u64 + 5;
+		code.push(0xE8);
+		code.extend_from_slice(&((target as i64 - next as i64) as i32).to_le_bytes());
+	}
+}
+
+fn elf_header(elf: &mut Vec<u8>, entry: u64) {
+	// Magic, 64 bit, little endian, version 1, System V ABI, padding
+	elf.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+	// Executable, x86-64, version 1
+	elf.extend_from_slice(&2u16.to_le_bytes());
+	elf.extend_from_slice(&0x3Eu16.to_le_bytes());
+	elf.extend_from_slice(&1u32.to_le_bytes());
+	elf.extend_from_slice(&entry.to_le_bytes());
+	// The program headers follow right away, there are no section headers
+	elf.extend_from_slice(&64u64.to_le_bytes());
+	elf.extend_from_slice(&0u64.to_le_bytes());
+	// Flags, header size, program header size and count, section header
+	// size, count and string table index
+	elf.extend_from_slice(&0u32.to_le_bytes());
+	for value in [64u16, 56, 2, 64, 0, 0] {
+		elf.extend_from_slice(&value.to_le_bytes());
+	}
+}
+
+/// A loadable segment with the given permissions (read, write, execute) and
+/// sizes in the file and in memory, taken from the start of the file
+fn program_header(elf: &mut Vec<u8>, flags: u32, address: u64, file_len: u64, memory_len: u64) {
+	elf.extend_from_slice(&1u32.to_le_bytes());
+	elf.extend_from_slice(&flags.to_le_bytes());
+	for value in [0, address, address, file_len, memory_len, PAGE_SIZE] {
+		elf.extend_from_slice(&value.to_le_bytes());
+	}
+}
@@ -0,0 +1,321 @@
+use std::fmt::Write;
+
+use crate::error::Error;
+use crate::instruction::{Ast, Instruction, LinkedInstructions, Node};
+use crate::interpret::{Eof, MAX_TAPE_SIZE, Options};
+
+/// Everything the program refers to, in front of `main`
+const DECLARATIONS: &str = "\
+@eof_message = private unnamed_addr constant [21 x i8] c\"Failed to read input\\0A\"
+@decimal = private unnamed_addr constant [3 x i8] c\"%d\\00\"
+
+declare i32 @getchar()
+declare i32 @putchar(i32)
+declare i32 @printf(i8*, ...)
+declare i32 @fflush(i8*)
+declare i64 @write(i32, i8*, i64)
+";
+
+impl LinkedInstructions {
+	/// Translate the instructions into a textual LLVM IR module, defining a
+	/// `main` function that runs the program
+	///
+	/// The module uses typed pointers, as opaque pointers are still
+	/// experimental in LLVM 14, and typed ones are understood up to LLVM 16
+	pub fn to_llvm(&self, options: &Options) -> Result<String, Error> {
+		let tape_size = options.tape_size.clamp(1, MAX_TAPE_SIZE);
+		let ast = Ast::from_linked(self)?;
+
+		let mut emitter = LlvmEmitter {
+			ir:        String::with_capacity(ast.instruction_count() * 128),
+			tape_size: tape_size as i64,
+			eof:       options.eof,
+			values:    0,
+			labels:    0,
+		};
+
+		writeln!(emitter.ir, "@tape = internal global [{} x i8] zeroinitializer", tape_size)
+			.unwrap();
+		emitter.ir.push_str(DECLARATIONS);
+		emitter.ir.push_str("\ndefine i32 @main() {\nentry:\n");
+		// mem2reg turns the data pointer into a register again
+		emitter.ir.push_str("\t%dp = alloca i32\n\tstore i32 0, i32* %dp\n");
+
+		emitter.block(&ast.0);
+
+		emitter.ir.push_str("\tcall i32 @fflush(i8* null)\n\tret i32 0\n}\n");
+
+		Ok(emitter.ir)
+	}
+}
+
+struct LlvmEmitter {
+	ir:        String,
+	tape_size: i64,
+	eof:       Eof,
+	/// The number of values defined so far, used to name the next one
+	values:    usize,
+	/// The number of loops and ifs so far, used to name their blocks
+	labels:    usize,
+}
+
+impl LlvmEmitter {
+	fn block(&mut self, nodes: &[Node]) {
+		for node in nodes {
+			match node {
+				Node::Inst(inst) => self.instruction(*inst),
+				Node::Loop(body) => {
+					let label = self.next_label();
+					self.line(&format!("br label %loop{}", label));
+					self.label(&format!("loop{}", label));
+					let condition = self.nonzero();
+					self.line(&format!(
+						"br i1 {}, label %body{1}, label %end{1}",
+						condition, label
+					));
+
+					self.label(&format!("body{}", label));
+					self.block(body);
+					self.line(&format!("br label %loop{}", label));
+					self.label(&format!("end{}", label));
+				},
+				Node::If(body) => {
+					let label = self.next_label();
+					let condition = self.nonzero();
+					self.line(&format!(
+						"br i1 {}, label %then{1}, label %end{1}",
+						condition, label
+					));
+
+					self.label(&format!("then{}", label));
+					self.block(body);
+					self.line(&format!("br label %end{}", label));
+					self.label(&format!("end{}", label));
+				},
+			}
+		}
+	}
+
+	fn instruction(&mut self, inst: Instruction) {
+		match inst {
+			Instruction::IncrDp { amount } => {
+				let dp = self.index(amount);
+				self.line(&format!("store i32 {}, i32* %dp", dp));
+			},
+			Instruction::Incr { amount, offset } => {
+				let cell = self.cell(offset);
+				let value = self.load(&cell);
+				let sum = self.value(&format!("add i8 {}, {}", value, amount));
+				self.store(&cell, &sum);
+			},
+			Instruction::Set { amount, offset } => {
+				let cell = self.cell(offset);
+				self.store(&cell, &amount.to_string());
+			},
+			Instruction::Mul { amount, offset } => {
+				let factor = self.load_offset(0);
+				let product = self.value(&format!("mul i8 {}, {}", factor, amount));
+				self.accumulate(offset, &product);
+			},
+			Instruction::MulAcc { amount, source, offset } => {
+				let factor = self.load_offset(0);
+				let source = self.load_offset(source);
+				let product = self.value(&format!("mul i8 {}, {}", factor, source));
+				let product = self.value(&format!("mul i8 {}, {}", product, amount));
+				self.accumulate(offset, &product);
+			},
+			Instruction::TriAcc { amount, offset } => {
+				// The triangular number has to be computed with more than 8
+				// bits before halving it
+				let value = self.load_offset(0);
+				let value = self.value(&format!("zext i8 {} to i32", value));
+				let next = self.value(&format!("add i32 {}, 1", value));
+				let product = self.value(&format!("mul i32 {}, {}", value, next));
+				let half = self.value(&format!("lshr i32 {}, 1", product));
+				let half = self.value(&format!("trunc i32 {} to i8", half));
+				let product = self.value(&format!("mul i8 {}, {}", half, amount));
+				self.accumulate(offset, &product);
+			},
+			Instruction::Read => self.read(),
+			Instruction::Write => {
+				let value = self.load_offset(0);
+				let value = self.value(&format!("zext i8 {} to i32", value));
+				self.line(&format!("call i32 @putchar(i32 {})", value));
+			},
+			Instruction::WriteDecimal => {
+				let value = self.load_offset(0);
+				let value = self.value(&format!("zext i8 {} to i32", value));
+				self.line(&format!(
+					"call i32 (i8*, ...) @printf(i8* getelementptr inbounds ([3 x i8], [3 x i8]* \
+					 @decimal, i32 0, i32 0), i32 {})",
+					value
+				));
+				for offset in 1..=8 {
+					let cell = self.cell(offset);
+					self.store(&cell, "0");
+				}
+			},
+			Instruction::DivMod => {
+				let n = self.load_offset(0);
+				let n = self.value(&format!("zext i8 {} to i32", n));
+				let d = self.load_offset(1);
+				let d = self.value(&format!("zext i8 {} to i32", d));
+				// A divisor of 0 behaves like 256
+				let zero = self.value(&format!("icmp eq i32 {}, 0", d));
+				let d = self.value(&format!("select i1 {}, i32 256, i32 {}", zero, d));
+
+				let remainder = self.value(&format!("urem i32 {}, {}", n, d));
+				let quotient = self.value(&format!("udiv i32 {}, {}", n, d));
+				let rest = self.value(&format!("sub i32 {}, {}", d, remainder));
+
+				let cell = self.cell(0);
+				self.store(&cell, "0");
+				for (offset, value) in [(2, remainder), (3, quotient), (4, rest)] {
+					let value = self.value(&format!("trunc i32 {} to i8", value));
+					let cell = self.cell(offset);
+					self.store(&cell, &value);
+				}
+				for offset in 5..=6 {
+					let cell = self.cell(offset);
+					self.store(&cell, "0");
+				}
+			},
+			Instruction::Compare => {
+				let x = self.load_offset(0);
+				let y = self.load_offset(1);
+				let less = self.value(&format!("icmp ult i8 {}, {}", x, y));
+				let less = self.value(&format!("zext i1 {} to i8", less));
+				let cell = self.cell(0);
+				self.store(&cell, &less);
+				for offset in 2..=6 {
+					let cell = self.cell(offset);
+					self.store(&cell, "0");
+				}
+			},
+			// The tree structure of the Ast already took care of branches
+			Instruction::BranchIfZero { .. }
+			| Instruction::BranchIfNotZero { .. }
+			| Instruction::If { .. }
+			| Instruction::EndIf => (),
+		}
+	}
+
+	/// Flush the output, then read a byte into the current cell, handling the
+	/// end of the input according to the EOF behaviour
+	fn read(&mut self) {
+		let label = self.next_label();
+
+		self.line("call i32 @fflush(i8* null)");
+		let c = self.value("call i32 @getchar()");
+		let eof = self.value(&format!("icmp eq i32 {}, -1", c));
+		self.line(&format!("br i1 {}, label %eof{1}, label %read{1}", eof, label));
+
+		self.label(&format!("read{}", label));
+		let byte = self.value(&format!("trunc i32 {} to i8", c));
+		let cell = self.cell(0);
+		self.store(&cell, &byte);
+		self.line(&format!("br label %end{}", label));
+
+		self.label(&format!("eof{}", label));
+		let value = match self.eof {
+			Eof::Error => {
+				self.line(
+					"call i64 @write(i32 2, i8* getelementptr inbounds ([21 x i8], [21 x i8]* \
+					 @eof_message, i32 0, i32 0), i64 21)",
+				);
+				self.line("ret i32 1");
+				None
+			},
+			Eof::Unchanged => None,
+			Eof::Zero => Some("0"),
+			Eof::MinusOne => Some("-1"),
+		};
+		if let Some(value) = value {
+			let cell = self.cell(0);
+			self.store(&cell, value);
+		}
+		if self.eof != Eof::Error {
+			self.line(&format!("br label %end{}", label));
+		}
+
+		self.label(&format!("end{}", label));
+	}
+
+	/// Add a value to the cell at an offset
+	fn accumulate(&mut self, offset: i16, value: &str) {
+		let cell = self.cell(offset);
+		let current = self.load(&cell);
+		let sum = self.value(&format!("add i8 {}, {}", current, value));
+		self.store(&cell, &sum);
+	}
+
+	/// The index of the cell at an offset from the data pointer
+	fn index(&mut self, offset: i16) -> String {
+		let dp = self.value("load i32, i32* %dp");
+		let offset = (offset as i64).rem_euclid(self.tape_size);
+		if offset == 0 {
+			return dp;
+		}
+
+		let index = self.value(&format!("add i32 {}, {}", dp, offset));
+		if self.tape_size == MAX_TAPE_SIZE as i64 {
+			self.value(&format!("and i32 {}, {}", index, MAX_TAPE_SIZE - 1))
+		} else {
+			// Both are below the tape size, so subtracting it once is enough
+			let wrapped = self.value(&format!("sub i32 {}, {}", index, self.tape_size));
+			let inside = self.value(&format!("icmp ult i32 {}, {}", index, self.tape_size));
+			self.value(&format!("select i1 {}, i32 {}, i32 {}", inside, index, wrapped))
+		}
+	}
+
+	/// A pointer to the cell at an offset from the data pointer
+	fn cell(&mut self, offset: i16) -> String {
+		let index = self.index(offset);
+		self.value(&format!(
+			"getelementptr inbounds [{0} x i8], [{0} x i8]* @tape, i32 0, i32 {1}",
+			self.tape_size, index
+		))
+	}
+
+	fn load(&mut self, cell: &str) -> String { self.value(&format!("load i8, i8* {}", cell)) }
+
+	fn load_offset(&mut self, offset: i16) -> String {
+		let cell = self.cell(offset);
+		self.load(&cell)
+	}
+
+	fn store(&mut self, cell: &str, value: &str) {
+		self.line(&format!("store i8 {}, i8* {}", value, cell));
+	}
+
+	/// Whether the current cell is not zero
+	fn nonzero(&mut self) -> String {
+		let value = self.load_offset(0);
+		self.value(&format!("icmp ne i8 {}, 0", value))
+	}
+
+	/// Define a new value, returning its name
+	fn value(&mut self, code: &str) -> String {
+		self.values += 1;
+		let name = format!("%v{}", self.values);
+		self.line(&format!("{} = {}", name, code));
+
+		name
+	}
+
+	fn next_label(&mut self) -> usize {
+		self.labels += 1;
+		self.labels
+	}
+
+	fn label(&mut self, name: &str) {
+		self.ir.push_str(name);
+		self.ir.push_str(":\n");
+	}
+
+	fn line(&mut self, code: &str) {
+		self.ir.push('\t');
+		self.ir.push_str(code);
+		self.ir.push('\n');
+	}
+}
@@ -0,0 +1,13 @@
+//! Translating instructions into other languages
+//!
+//! Every target is generated from the [`Ast`](crate::instruction::Ast), so
+//! loops become structured control flow. Apart from plain brainfuck, which
+//! leaves them to whatever runs it, targets honour the tape size and EOF
+//! behaviour of the [`Options`](crate::interpret::Options) they are given.
+
+mod brainfuck;
+mod c;
+mod elf;
+mod llvm;
+mod rust;
+pub mod wasm;
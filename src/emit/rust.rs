@@ -0,0 +1,180 @@
+use std::fmt::Write;
+
+use itertools::Itertools;
+
+use crate::error::Error;
+use crate::instruction::{Ast, Instruction, LinkedInstructions, Node};
+use crate::interpret::{Eof, MAX_TAPE_SIZE, Options};
+
+/// Everything in front of the translated program
+///
+/// The lints are silenced as a program might never read, move or even touch
+/// the tape, and the code may end up inside someone else's crate
+const PRELUDE: &str = "\
+/// Run the program, reading its input from `input` and writing its output to
+/// `output`
+#[allow(unused_mut, unused_variables, unused_assignments, clippy::all)]
+pub fn run(input: impl ::std::io::Read, output: impl ::std::io::Write) -> ::std::io::Result<()> {
+\tlet mut input = input.bytes();
+\tlet mut output = output;
+\tlet mut dp: usize = 0;
+";
+
+impl LinkedInstructions {
+	/// Translate the instructions into Rust source code, defining a function
+	/// `run(input: impl Read, output: impl Write) -> io::Result<()>`
+	///
+	/// Reading past the end of the input with the error EOF behaviour returns
+	/// an error of kind [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof)
+	pub fn to_rust(&self, options: &Options) -> Result<String, Error> {
+		let tape_size = options.tape_size.clamp(1, MAX_TAPE_SIZE);
+		let ast = Ast::from_linked(self)?;
+
+		let mut rust = String::with_capacity(ast.instruction_count() * 48 + PRELUDE.len());
+		rust.push_str(PRELUDE);
+		writeln!(rust, "\tlet mut tape = ::std::vec![0u8; {}];\n", tape_size).unwrap();
+
+		let emitter = RustEmitter { tape_size: tape_size as i64, eof: options.eof };
+		emitter.block(&mut rust, &ast.0, 1);
+
+		rust.push_str("\n\toutput.flush()\n}\n");
+
+		Ok(rust)
+	}
+}
+
+struct RustEmitter {
+	tape_size: i64,
+	eof:       Eof,
+}
+
+impl RustEmitter {
+	fn block(&self, rust: &mut String, nodes: &[Node], depth: usize) {
+		for node in nodes {
+			match node {
+				Node::Inst(inst) => self.instruction(rust, *inst, depth),
+				Node::Loop(body) => {
+					line(rust, depth, "while tape[dp] != 0 {");
+					self.block(rust, body, depth + 1);
+					line(rust, depth, "}");
+				},
+				Node::If(body) => {
+					line(rust, depth, "if tape[dp] != 0 {");
+					self.block(rust, body, depth + 1);
+					line(rust, depth, "}");
+				},
+			}
+		}
+	}
+
+	/// The cell at an offset from `dp`
+	fn cell(&self, offset: i16) -> String {
+		match (offset as i64).rem_euclid(self.tape_size) {
+			0 => "tape[dp]".to_owned(),
+			offset => format!("tape[(dp + {}) % {}]", offset, self.tape_size),
+		}
+	}
+
+	/// Add a value to the cell at an offset
+	fn accumulate(&self, offset: i16, value: &str) -> String {
+		let cell = self.cell(offset);
+		format!("{0} = {0}.wrapping_add({1});", cell, value)
+	}
+
+	fn instruction(&self, rust: &mut String, inst: Instruction, depth: usize) {
+		let code = match inst {
+			Instruction::IncrDp { amount } => {
+				match (amount as i64).rem_euclid(self.tape_size) {
+					0 => return,
+					amount => format!("dp = (dp + {}) % {};", amount, self.tape_size),
+				}
+			},
+			Instruction::Incr { amount, offset } => {
+				self.accumulate(offset, &(amount as u8).to_string())
+			},
+			Instruction::Set { amount, offset } => {
+				format!("{} = {};", self.cell(offset), amount as u8)
+			},
+			Instruction::Mul { amount, offset } => {
+				self.accumulate(offset, &format!("tape[dp].wrapping_mul({})", amount as u8))
+			},
+			Instruction::MulAcc { amount, source, offset } => {
+				let product = format!(
+					"tape[dp].wrapping_mul({}).wrapping_mul({})",
+					self.cell(source),
+					amount as u8
+				);
+				self.accumulate(offset, &product)
+			},
+			Instruction::TriAcc { amount, offset } => {
+				// The triangular number has to be computed with more than 8 bits
+				// before halving it
+				let product = "(tape[dp] as u32 * (tape[dp] as u32 + 1) / 2) as u8";
+				self.accumulate(offset, &format!("({}).wrapping_mul({})", product, amount as u8))
+			},
+			Instruction::Read => {
+				let on_eof = match self.eof {
+					Eof::Error => {
+						"return Err(::std::io::Error::new(::std::io::ErrorKind::UnexpectedEof, \
+						 \"Failed to read input\")),"
+					},
+					Eof::Unchanged => "(),",
+					Eof::Zero => "tape[dp] = 0,",
+					Eof::MinusOne => "tape[dp] = 255,",
+				};
+
+				line(rust, depth, "output.flush()?;");
+				line(rust, depth, "match input.next().transpose()? {");
+				line(rust, depth + 1, "Some(byte) => tape[dp] = byte,");
+				line(rust, depth + 1, &format!("None => {}", on_eof));
+				"}".to_owned()
+			},
+			Instruction::Write => "output.write_all(&[tape[dp]])?;".to_owned(),
+			Instruction::WriteDecimal => {
+				line(rust, depth, "::std::write!(output, \"{}\", tape[dp])?;");
+				(1..=8).map(|offset| format!("{} = 0;", self.cell(offset))).join(" ")
+			},
+			Instruction::DivMod => {
+				line(rust, depth, "{");
+				let divisor = self.cell(1);
+				line(rust, depth + 1, "let n = tape[dp] as u32;");
+				line(
+					rust,
+					depth + 1,
+					&format!("let d = if {0} != 0 {{ {0} as u32 }} else {{ 256 }};", divisor),
+				);
+				line(rust, depth + 1, "tape[dp] = 0;");
+				let results = [
+					(2, "(n % d) as u8"),
+					(3, "(n / d) as u8"),
+					(4, "(d - n % d) as u8"),
+					(5, "0"),
+					(6, "0"),
+				];
+				for (offset, value) in results {
+					line(rust, depth + 1, &format!("{} = {};", self.cell(offset), value));
+				}
+				"}".to_owned()
+			},
+			Instruction::Compare => {
+				line(rust, depth, &format!("tape[dp] = (tape[dp] < {}) as u8;", self.cell(1)));
+				(2..=6).map(|offset| format!("{} = 0;", self.cell(offset))).join(" ")
+			},
+			// The tree structure of the Ast already took care of branches
+			Instruction::BranchIfZero { .. }
+			| Instruction::BranchIfNotZero { .. }
+			| Instruction::If { .. }
+			| Instruction::EndIf => return,
+		};
+
+		line(rust, depth, &code);
+	}
+}
+
+fn line(rust: &mut String, depth: usize, code: &str) {
+	for _ in 0..depth {
+		rust.push('\t');
+	}
+	rust.push_str(code);
+	rust.push('\n');
+}
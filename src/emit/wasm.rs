@@ -0,0 +1,506 @@
+//! WebAssembly modules
+//!
+//! The module imports two functions from the host, and exports the tape as
+//! its memory along with a function running the program:
+//!  - `env.read: () -> i32` returns the next input byte, or -1 at the end of the input
+//!  - `env.write: (i32)` writes the byte in the low bits of its argument
+//!  - `memory` is a single page, its first `tape_size` bytes are the tape
+//!  - `run: () -> i32` runs the program, returning 0, or 1 if it stopped because the input ran out
+//!
+//! A custom section named [`MACHINE_SECTION`] describes the machine the
+//! program expects, as the LEB128 encoded cell width in bits followed by the
+//! tape size.
+
+use std::fmt::Write;
+
+use crate::error::Error;
+use crate::instruction::{Ast, Instruction, LinkedInstructions, Node};
+use crate::interpret::{Eof, MAX_TAPE_SIZE, Options};
+
+/// The name of the custom section describing the machine
+pub const MACHINE_SECTION: &str = "bf-machine";
+
+/// The width of a cell in bits
+const CELL_WIDTH: u32 = 8;
+
+/// The locals of `run`, all of them i32
+const DP: u32 = 0;
+const INDEX: u32 = 1;
+const X: u32 = 2;
+const Y: u32 = 3;
+const LOCAL_NAMES: [&str; 4] = ["$dp", "$index", "$x", "$y"];
+
+/// The functions, the imports come first
+const READ: u32 = 0;
+const WRITE: u32 = 1;
+const FUNCTION_NAMES: [&str; 2] = ["$read", "$write"];
+
+/// The handful of wasm instructions the translated programs need
+#[derive(Clone, Copy)]
+enum Op {
+	Block,
+	Loop,
+	If,
+	Else,
+	End,
+	BrIf(u32),
+	Return,
+	Call(u32),
+	LocalGet(u32),
+	LocalSet(u32),
+	LocalTee(u32),
+	Const(i32),
+	Load8,
+	Store8,
+	Eqz,
+	Eq,
+	LtU,
+	GeU,
+	Add,
+	Sub,
+	Mul,
+	DivU,
+	RemU,
+	And,
+	ShrU,
+	Select,
+}
+
+impl Op {
+	fn opcode(self) -> u8 {
+		match self {
+			Self::Block => 0x02,
+			Self::Loop => 0x03,
+			Self::If => 0x04,
+			Self::Else => 0x05,
+			Self::End => 0x0B,
+			Self::BrIf(_) => 0x0D,
+			Self::Return => 0x0F,
+			Self::Call(_) => 0x10,
+			Self::LocalGet(_) => 0x20,
+			Self::LocalSet(_) => 0x21,
+			Self::LocalTee(_) => 0x22,
+			Self::Const(_) => 0x41,
+			Self::Load8 => 0x2D,
+			Self::Store8 => 0x3A,
+			Self::Eqz => 0x45,
+			Self::Eq => 0x46,
+			Self::LtU => 0x49,
+			Self::GeU => 0x4F,
+			Self::Add => 0x6A,
+			Self::Sub => 0x6B,
+			Self::Mul => 0x6C,
+			Self::DivU => 0x6E,
+			Self::RemU => 0x70,
+			Self::And => 0x71,
+			Self::ShrU => 0x76,
+			Self::Select => 0x1B,
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Self::Block => "block",
+			Self::Loop => "loop",
+			Self::If => "if",
+			Self::Else => "else",
+			Self::End => "end",
+			Self::BrIf(_) => "br_if",
+			Self::Return => "return",
+			Self::Call(_) => "call",
+			Self::LocalGet(_) => "local.get",
+			Self::LocalSet(_) => "local.set",
+			Self::LocalTee(_) => "local.tee",
+			Self::Const(_) => "i32.const",
+			Self::Load8 => "i32.load8_u",
+			Self::Store8 => "i32.store8",
+			Self::Eqz => "i32.eqz",
+			Self::Eq => "i32.eq",
+			Self::LtU => "i32.lt_u",
+			Self::GeU => "i32.ge_u",
+			Self::Add => "i32.add",
+			Self::Sub => "i32.sub",
+			Self::Mul => "i32.mul",
+			Self::DivU => "i32.div_u",
+			Self::RemU => "i32.rem_u",
+			Self::And => "i32.and",
+			Self::ShrU => "i32.shr_u",
+			Self::Select => "select",
+		}
+	}
+
+	/// Append the binary encoding
+	fn encode(self, code: &mut Vec<u8>) {
+		code.push(self.opcode());
+		match self {
+			// Blocks have no result
+			Self::Block | Self::Loop | Self::If => code.push(0x40),
+			Self::BrIf(depth) => unsigned(code, depth),
+			Self::Call(function) => unsigned(code, function),
+			Self::LocalGet(local) | Self::LocalSet(local) | Self::LocalTee(local) => {
+				unsigned(code, local)
+			},
+			Self::Const(value) => signed(code, value),
+			// Byte aligned, without an offset
+			Self::Load8 | Self::Store8 => code.extend_from_slice(&[0, 0]),
+			_ => (),
+		}
+	}
+
+	/// Write the text format
+	fn write(self, wat: &mut String) {
+		wat.push_str(self.name());
+		match self {
+			Self::BrIf(depth) => write!(wat, " {}", depth),
+			Self::Call(function) => write!(wat, " {}", FUNCTION_NAMES[function as usize]),
+			Self::LocalGet(local) | Self::LocalSet(local) | Self::LocalTee(local) => {
+				write!(wat, " {}", LOCAL_NAMES[local as usize])
+			},
+			Self::Const(value) => write!(wat, " {}", value),
+			_ => Ok(()),
+		}
+		.unwrap();
+	}
+}
+
+impl LinkedInstructions {
+	/// Translate the instructions into a binary WebAssembly module
+	pub fn to_wasm(&self, options: &Options) -> Result<Vec<u8>, Error> {
+		Ok(WasmModule::new(self, options)?.binary())
+	}
+
+	/// Translate the instructions into a WebAssembly module in the text format
+	pub fn to_wat(&self, options: &Options) -> Result<String, Error> {
+		Ok(WasmModule::new(self, options)?.text())
+	}
+}
+
+struct WasmModule {
+	tape_size: u32,
+	/// The body of `run`
+	ops:       Vec<Op>,
+}
+
+impl WasmModule {
+	fn new(insts: &LinkedInstructions, options: &Options) -> Result<Self, Error> {
+		let tape_size = options.tape_size.clamp(1, MAX_TAPE_SIZE);
+		let ast = Ast::from_linked(insts)?;
+
+		let mut emitter = WasmEmitter {
+			ops:       Vec::with_capacity(ast.instruction_count() * 8),
+			tape_size: tape_size as i64,
+			eof:       options.eof,
+		};
+		emitter.block(&ast.0);
+		emitter.ops.extend_from_slice(&[Op::Const(0), Op::End]);
+
+		Ok(Self { tape_size: tape_size as u32, ops: emitter.ops })
+	}
+
+	/// The payload of the machine section
+	fn machine(&self) -> Vec<u8> {
+		let mut machine = vec![];
+		unsigned(&mut machine, CELL_WIDTH);
+		unsigned(&mut machine, self.tape_size);
+		machine
+	}
+
+	fn binary(&self) -> Vec<u8> {
+		let mut wasm = b"\0asm".to_vec();
+		wasm.extend_from_slice(&1u32.to_le_bytes());
+
+		// Types: (i32) -> (), () -> i32
+		section(&mut wasm, 1, &[2, 0x60, 1, 0x7F, 0, 0x60, 0, 1, 0x7F]);
+
+		let mut imports = vec![2];
+		for (name, ty) in [("read", 1), ("write", 0)] {
+			string(&mut imports, "env");
+			string(&mut imports, name);
+			imports.extend_from_slice(&[0x00, ty]);
+		}
+		section(&mut wasm, 2, &imports);
+
+		// run: () -> i32
+		section(&mut wasm, 3, &[1, 1]);
+		// A single page, which is always exactly enough
+		section(&mut wasm, 5, &[1, 0x01, 1, 1]);
+
+		let mut exports = vec![2];
+		string(&mut exports, "run");
+		exports.extend_from_slice(&[0x00, 2]);
+		string(&mut exports, "memory");
+		exports.extend_from_slice(&[0x02, 0]);
+		section(&mut wasm, 7, &exports);
+
+		// All locals are i32
+		let mut body = vec![1, LOCAL_NAMES.len() as u8, 0x7F];
+		for op in &self.ops {
+			op.encode(&mut body);
+		}
+		let mut code = vec![1];
+		unsigned(&mut code, body.len() as u32);
+		code.extend_from_slice(&body);
+		section(&mut wasm, 10, &code);
+
+		let mut machine = vec![];
+		string(&mut machine, MACHINE_SECTION);
+		machine.extend_from_slice(&self.machine());
+		section(&mut wasm, 0, &machine);
+
+		wasm
+	}
+
+	fn text(&self) -> String {
+		let mut wat = String::with_capacity(self.ops.len() * 16 + 512);
+		wat.push_str("(module\n");
+		wat.push_str("\t(import \"env\" \"read\" (func $read (result i32)))\n");
+		wat.push_str("\t(import \"env\" \"write\" (func $write (param i32)))\n");
+		wat.push_str("\t(memory (export \"memory\") 1 1)\n");
+
+		write!(wat, "\t(@custom \"{}\" \"", MACHINE_SECTION).unwrap();
+		for byte in self.machine() {
+			write!(wat, "\\{:02x}", byte).unwrap();
+		}
+		wat.push_str("\")\n");
+
+		wat.push_str("\t(func (export \"run\") (result i32)\n");
+		wat.push_str("\t\t(local $dp i32) (local $index i32) (local $x i32) (local $y i32)\n");
+
+		let mut depth = 2;
+		// The last op ends the function
+		for op in &self.ops[..self.ops.len() - 1] {
+			if let Op::Else | Op::End = op {
+				depth -= 1;
+			}
+			for _ in 0..depth {
+				wat.push('\t');
+			}
+			op.write(&mut wat);
+			wat.push('\n');
+			if let Op::Block | Op::Loop | Op::If | Op::Else = op {
+				depth += 1;
+			}
+		}
+
+		wat.push_str("\t)\n)\n");
+		wat
+	}
+}
+
+struct WasmEmitter {
+	ops:       Vec<Op>,
+	tape_size: i64,
+	eof:       Eof,
+}
+
+impl WasmEmitter {
+	fn block(&mut self, nodes: &[Node]) {
+		for node in nodes {
+			match node {
+				Node::Inst(inst) => self.instruction(*inst),
+				Node::Loop(body) => {
+					self.ops.push(Op::Block);
+					self.load(0);
+					self.ops.extend_from_slice(&[Op::Eqz, Op::BrIf(0), Op::Loop]);
+					self.block(body);
+					self.load(0);
+					self.ops.extend_from_slice(&[Op::BrIf(0), Op::End, Op::End]);
+				},
+				Node::If(body) => {
+					self.load(0);
+					self.ops.push(Op::If);
+					self.block(body);
+					self.ops.push(Op::End);
+				},
+			}
+		}
+	}
+
+	fn instruction(&mut self, inst: Instruction) {
+		match inst {
+			Instruction::IncrDp { amount } => {
+				self.address(amount);
+				self.ops.push(Op::LocalSet(DP));
+			},
+			Instruction::Incr { amount, offset } => {
+				self.modify(offset);
+				self.ops.extend_from_slice(&[Op::Const(amount as i32), Op::Add, Op::Store8]);
+			},
+			Instruction::Set { amount, offset } => self.set(offset, amount as u8 as i32),
+			Instruction::Mul { amount, offset } => {
+				self.modify(offset);
+				self.load(0);
+				self.ops.extend_from_slice(&[Op::Const(amount as i32), Op::Mul]);
+				self.ops.extend_from_slice(&[Op::Add, Op::Store8]);
+			},
+			Instruction::MulAcc { amount, source, offset } => {
+				self.modify(offset);
+				self.load(0);
+				self.load(source);
+				self.ops.extend_from_slice(&[Op::Mul, Op::Const(amount as i32), Op::Mul]);
+				self.ops.extend_from_slice(&[Op::Add, Op::Store8]);
+			},
+			Instruction::TriAcc { amount, offset } => {
+				self.modify(offset);
+				self.load(0);
+				self.ops.extend_from_slice(&[Op::LocalTee(X), Op::LocalGet(X), Op::Const(1)]);
+				self.ops.extend_from_slice(&[Op::Add, Op::Mul, Op::Const(1), Op::ShrU]);
+				self.ops.extend_from_slice(&[Op::Const(amount as i32), Op::Mul]);
+				self.ops.extend_from_slice(&[Op::Add, Op::Store8]);
+			},
+			Instruction::Read => self.read(),
+			Instruction::Write => {
+				self.load(0);
+				self.ops.push(Op::Call(WRITE));
+			},
+			Instruction::WriteDecimal => {
+				self.load(0);
+				self.ops.push(Op::LocalSet(X));
+				// Every digit but the last is only written if the number has
+				// that many digits
+				for divisor in [100, 10] {
+					self.ops.extend_from_slice(&[Op::LocalGet(X), Op::Const(divisor), Op::GeU]);
+					self.ops.extend_from_slice(&[Op::If, Op::LocalGet(X), Op::Const(divisor)]);
+					self.ops.extend_from_slice(&[Op::DivU, Op::Const(10), Op::RemU]);
+					self.ops.extend_from_slice(&[Op::Const(b'0' as i32), Op::Add]);
+					self.ops.extend_from_slice(&[Op::Call(WRITE), Op::End]);
+				}
+				self.ops.extend_from_slice(&[Op::LocalGet(X), Op::Const(10), Op::RemU]);
+				self.ops.extend_from_slice(&[Op::Const(b'0' as i32), Op::Add, Op::Call(WRITE)]);
+
+				for offset in 1..=8 {
+					self.set(offset, 0);
+				}
+			},
+			Instruction::DivMod => {
+				self.load(0);
+				self.ops.push(Op::LocalSet(X));
+				// A divisor of 0 behaves like 256
+				self.load(1);
+				self.ops.extend_from_slice(&[Op::LocalTee(Y), Op::Const(256), Op::LocalGet(Y)]);
+				self.ops.extend_from_slice(&[Op::Select, Op::LocalSet(Y)]);
+
+				self.set(0, 0);
+				self.address(2);
+				self.ops.extend_from_slice(&[Op::LocalGet(X), Op::LocalGet(Y), Op::RemU]);
+				self.ops.push(Op::Store8);
+				self.address(3);
+				self.ops.extend_from_slice(&[Op::LocalGet(X), Op::LocalGet(Y), Op::DivU]);
+				self.ops.push(Op::Store8);
+				self.address(4);
+				self.ops.extend_from_slice(&[Op::LocalGet(Y), Op::LocalGet(X), Op::LocalGet(Y)]);
+				self.ops.extend_from_slice(&[Op::RemU, Op::Sub, Op::Store8]);
+				self.set(5, 0);
+				self.set(6, 0);
+			},
+			Instruction::Compare => {
+				self.load(0);
+				self.ops.push(Op::LocalSet(X));
+				self.load(1);
+				self.ops.push(Op::LocalSet(Y));
+
+				self.address(0);
+				self.ops.extend_from_slice(&[Op::LocalGet(X), Op::LocalGet(Y), Op::LtU]);
+				self.ops.push(Op::Store8);
+				for offset in 2..=6 {
+					self.set(offset, 0);
+				}
+			},
+			// The tree structure of the Ast already took care of branches
+			Instruction::BranchIfZero { .. }
+			| Instruction::BranchIfNotZero { .. }
+			| Instruction::If { .. }
+			| Instruction::EndIf => (),
+		}
+	}
+
+	/// Read a byte into the current cell, handling the end of the input
+	/// according to the EOF behaviour
+	fn read(&mut self) {
+		self.ops.extend_from_slice(&[Op::Call(READ), Op::LocalTee(X), Op::Const(-1), Op::Eq]);
+		self.ops.push(Op::If);
+		match self.eof {
+			Eof::Error => self.ops.extend_from_slice(&[Op::Const(1), Op::Return]),
+			Eof::Unchanged => (),
+			Eof::Zero => self.set(0, 0),
+			Eof::MinusOne => self.set(0, 255),
+		}
+		self.ops.push(Op::Else);
+		self.address(0);
+		self.ops.extend_from_slice(&[Op::LocalGet(X), Op::Store8, Op::End]);
+	}
+
+	/// Push the address of the cell at an offset from the data pointer
+	fn address(&mut self, offset: i16) {
+		self.ops.push(Op::LocalGet(DP));
+		let offset = (offset as i64).rem_euclid(self.tape_size) as i32;
+		if offset == 0 {
+			return;
+		}
+
+		self.ops.extend_from_slice(&[Op::Const(offset), Op::Add]);
+		let size = self.tape_size as i32;
+		if self.tape_size == MAX_TAPE_SIZE as i64 {
+			self.ops.extend_from_slice(&[Op::Const(size - 1), Op::And]);
+		} else {
+			// Both are below the tape size, so subtracting it once is enough
+			self.ops.extend_from_slice(&[Op::LocalTee(INDEX), Op::LocalGet(INDEX)]);
+			self.ops.extend_from_slice(&[Op::Const(size), Op::Sub, Op::LocalGet(INDEX)]);
+			self.ops.extend_from_slice(&[Op::Const(size), Op::LtU, Op::Select]);
+		}
+	}
+
+	/// Push the value of the cell at an offset from the data pointer
+	fn load(&mut self, offset: i16) {
+		self.address(offset);
+		self.ops.push(Op::Load8);
+	}
+
+	/// Push the address of the cell at an offset, followed by its value, so
+	/// that pushing a new value and storing it modifies the cell
+	fn modify(&mut self, offset: i16) {
+		self.address(offset);
+		self.ops.extend_from_slice(&[Op::LocalTee(INDEX), Op::LocalGet(INDEX), Op::Load8]);
+	}
+
+	fn set(&mut self, offset: i16, value: i32) {
+		self.address(offset);
+		self.ops.extend_from_slice(&[Op::Const(value), Op::Store8]);
+	}
+}
+
+fn unsigned(bytes: &mut Vec<u8>, mut value: u32) {
+	loop {
+		let byte = (value & 0x7F) as u8;
+		value >>= 7;
+		if value == 0 {
+			bytes.push(byte);
+			return;
+		}
+		bytes.push(byte | 0x80);
+	}
+}
+
+fn signed(bytes: &mut Vec<u8>, mut value: i32) {
+	loop {
+		let byte = (value & 0x7F) as u8;
+		value >>= 7;
+		let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+		if done {
+			bytes.push(byte);
+			return;
+		}
+		bytes.push(byte | 0x80);
+	}
+}
+
+fn string(bytes: &mut Vec<u8>, string: &str) {
+	unsigned(bytes, string.len() as u32);
+	bytes.extend_from_slice(string.as_bytes());
+}
+
+fn section(wasm: &mut Vec<u8>, id: u8, contents: &[u8]) {
+	wasm.push(id);
+	unsigned(wasm, contents.len() as u32);
+	wasm.extend_from_slice(contents);
+}
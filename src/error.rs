@@ -10,4 +10,22 @@ pub enum Error {
 	MissingOpeningBracket(usize),
 	#[error("Missing closing bracket for bracket at position {0}")]
 	MissingClosingBracket(usize),
+	#[error("Branch at position {0} closes a different kind of branch")]
+	MismatchedBranch(usize),
+	#[error("Idiom '{0}' behaves differently from its snippet for inputs {1:?}")]
+	IdiomMismatch(&'static str, Vec<u8>),
+	#[error("Unknown optimisation '{0}'")]
+	UnknownOptimisation(String),
+	#[error("Unknown optimisation level '{0}', only 0 to 3 are supported")]
+	UnknownOptLevel(u8),
+	#[error("Unknown optimisation pass '{0}'")]
+	UnknownPass(String),
+	#[error("An optimisation pass named '{0}' already exists")]
+	DuplicatePass(String),
+	#[error("Packed instruction {0} would let the program run out of bounds")]
+	InvalidPackedProgram(usize),
+	#[error("The JIT backend only supports x86-64 Linux")]
+	UnsupportedJit,
+	#[error("A multiplication needs a temporary cell, but none is known to be free")]
+	NoTemporaryCell,
 }
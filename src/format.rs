@@ -0,0 +1,404 @@
+//! Formatting brainfuck source
+//!
+//! Unlike [`UnlinkedInstructions::from_text`], the parser here keeps the
+//! comments between the commands, so formatting only ever changes the layout
+//! of a file, and with [`Style::normalise`] removes commands that cancel out.
+//!
+//! [`UnlinkedInstructions::from_text`]: crate::instruction::UnlinkedInstructions::from_text
+
+use crate::error::Error;
+
+/// The column a line of commands gets wrapped at by default
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// The width a tab counts as when wrapping, loop bodies are indented with one
+/// tab per level
+const TAB_WIDTH: usize = 4;
+
+/// A piece of brainfuck source, as read by [`Source::parse`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+	/// A run of commands other than brackets
+	Commands(Vec<u8>),
+	/// The text of a comment, without the whitespace around it
+	Comment {
+		text:     Vec<u8>,
+		/// Whether the comment follows commands on the same line
+		trailing: bool,
+	},
+	/// One or more empty lines
+	Blank,
+	Loop(Vec<Item>),
+}
+
+/// Brainfuck source with its comments
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source(pub Vec<Item>);
+
+/// How to lay out brainfuck source
+#[derive(Clone, Copy, Debug)]
+pub struct Style {
+	/// The column to wrap lines of commands at, a run of commands is only
+	/// split across lines if it does not fit
+	pub width:     usize,
+	/// Remove adjacent commands that cancel out, like `+-` and `<>`
+	///
+	/// This assumes the tape wraps around, as `<>` in the first cell fails
+	/// otherwise
+	pub normalise: bool,
+}
+
+impl Default for Style {
+	fn default() -> Self { Self { width: DEFAULT_WIDTH, normalise: false } }
+}
+
+impl Source {
+	/// Split a file into commands, loops, and comments
+	///
+	/// Every byte that isn't a command or whitespace is part of a comment,
+	/// bracket positions in errors count commands only, like when linking
+	pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+		let mut parser = Parser {
+			blocks:        vec![],
+			current:       vec![],
+			comment:       vec![],
+			trailing:      false,
+			newlines:      0,
+			line_has_code: false,
+		};
+		let mut open = vec![];
+		let mut position = 0;
+
+		for byte in bytes {
+			match byte {
+				b'+' | b'-' | b'<' | b'>' | b'.' | b',' => {
+					parser.item_start();
+					match parser.current.last_mut() {
+						Some(Item::Commands(commands)) => commands.push(*byte),
+						_ => parser.current.push(Item::Commands(vec![*byte])),
+					}
+				},
+				b'[' => {
+					parser.item_start();
+					parser.blocks.push(std::mem::take(&mut parser.current));
+					open.push(position);
+				},
+				b']' => {
+					parser.item_start();
+					let Some(outer) = parser.blocks.pop() else {
+						return Err(Error::MissingOpeningBracket(position));
+					};
+					open.pop();
+					let body = std::mem::replace(&mut parser.current, outer);
+					parser.current.push(Item::Loop(body));
+				},
+				b'\n' => {
+					parser.end_comment();
+					parser.newlines += 1;
+					parser.line_has_code = false;
+				},
+				_ if byte.is_ascii_whitespace() => {
+					// Whitespace inside a comment belongs to it
+					if !parser.comment.is_empty() {
+						parser.comment.push(*byte);
+					}
+				},
+				_ => {
+					if parser.comment.is_empty() {
+						parser.blank_line();
+						parser.trailing = parser.line_has_code;
+					}
+					parser.comment.push(*byte);
+				},
+			}
+
+			if b"+-<>[].,".contains(byte) {
+				position += 1;
+				parser.line_has_code = true;
+			}
+		}
+
+		if let Some(position) = open.pop() {
+			return Err(Error::MissingClosingBracket(position));
+		}
+		parser.end_comment();
+
+		Ok(Self(parser.current))
+	}
+
+	/// Lay out the source, indenting loop bodies by their depth
+	///
+	/// A loop without comments or nested loops stays on a single line if it
+	/// fits, any other loop opens at the end of a line and closes on a line of
+	/// its own. Comments that followed commands on the same line still do.
+	pub fn format(&self, style: &Style) -> Vec<u8> {
+		let normalised;
+		let items = if style.normalise {
+			normalised = normalise(&self.0);
+			&normalised
+		} else {
+			&self.0
+		};
+
+		let mut formatter = Formatter { out: vec![], line: vec![], depth: 0, style };
+		formatter.block(items, 0);
+		formatter.flush();
+
+		while formatter.out.ends_with(b"\n\n") {
+			formatter.out.pop();
+		}
+
+		formatter.out
+	}
+}
+
+/// The state of [`Source::parse`]
+struct Parser {
+	/// The items of every loop around the current one
+	blocks:        Vec<Vec<Item>>,
+	current:       Vec<Item>,
+	/// The comment being read
+	comment:       Vec<u8>,
+	trailing:      bool,
+	/// The number of newlines since the last item
+	newlines:      usize,
+	/// Whether the current line has commands so far
+	line_has_code: bool,
+}
+
+impl Parser {
+	/// Finish the previous item, before a command or bracket
+	fn item_start(&mut self) {
+		self.end_comment();
+		self.blank_line();
+	}
+
+	fn end_comment(&mut self) {
+		if self.comment.is_empty() {
+			return;
+		}
+
+		let len = self.comment.trim_ascii_end().len();
+		self.comment.truncate(len);
+		let text = std::mem::take(&mut self.comment);
+		self.current.push(Item::Comment { text, trailing: self.trailing });
+		self.newlines = 0;
+	}
+
+	/// Insert a blank line if the next item is separated from the previous one
+	/// by at least one empty line
+	fn blank_line(&mut self) {
+		if self.newlines >= 2 && !self.current.is_empty() {
+			self.current.push(Item::Blank);
+		}
+		self.newlines = 0;
+	}
+}
+
+/// Remove the commands that cancel out, and with them the runs of commands
+/// left empty, along with the blank lines that end up at the start or end of a
+/// block or next to another blank line
+///
+/// Formatting the result never has anything left to remove, so formatting a
+/// normalised file again does not change it.
+fn normalise(items: &[Item]) -> Vec<Item> {
+	let mut result = vec![];
+
+	for item in items {
+		match item {
+			Item::Commands(commands) => {
+				let commands = cancel(commands);
+				if !commands.is_empty() {
+					result.push(Item::Commands(commands));
+				}
+			},
+			Item::Blank if result.is_empty() || result.last() == Some(&Item::Blank) => (),
+			Item::Loop(body) => result.push(Item::Loop(normalise(body))),
+			_ => result.push(item.clone()),
+		}
+	}
+
+	if result.last() == Some(&Item::Blank) {
+		result.pop();
+	}
+
+	result
+}
+
+/// Remove adjacent commands that cancel out
+fn cancel(commands: &[u8]) -> Vec<u8> {
+	let mut result: Vec<u8> = vec![];
+	for command in commands {
+		let cancels = matches!(
+			(result.last(), command),
+			(Some(b'+'), b'-') | (Some(b'-'), b'+') | (Some(b'<'), b'>') | (Some(b'>'), b'<')
+		);
+		if cancels {
+			result.pop();
+		} else {
+			result.push(*command);
+		}
+	}
+
+	result
+}
+
+struct Formatter<'s> {
+	out:   Vec<u8>,
+	/// The line being laid out
+	line:  Vec<u8>,
+	/// The depth of the line being laid out
+	depth: usize,
+	style: &'s Style,
+}
+
+impl Formatter<'_> {
+	fn block(&mut self, items: &[Item], depth: usize) {
+		for item in items {
+			match item {
+				Item::Commands(commands) => self.commands(commands, depth),
+				Item::Comment { text, trailing } => {
+					if *trailing && !self.line.is_empty() {
+						self.line.push(b' ');
+					} else {
+						self.flush();
+						self.depth = depth;
+					}
+					self.line.extend(text);
+					self.flush();
+				},
+				Item::Blank => {
+					self.flush();
+					if !self.out.is_empty() && !self.out.ends_with(b"\n\n") {
+						self.out.push(b'\n');
+					}
+				},
+				Item::Loop(body) => {
+					match self.inline(body) {
+						Some(text) if depth * TAB_WIDTH + text.len() + 2 <= self.style.width => {
+							if self.depth != depth
+								|| self.column() + text.len() + 2 > self.style.width
+							{
+								self.flush();
+							}
+							self.depth = depth;
+							self.line.push(b'[');
+							self.line.extend(text);
+							self.line.push(b']');
+						},
+						_ => {
+							self.commands(b"[", depth);
+							self.block(body, depth + 1);
+							self.commands(b"]", depth);
+						},
+					}
+				},
+			}
+		}
+	}
+
+	/// Add commands to the current line, starting a new one whenever it gets
+	/// too long
+	fn commands(&mut self, commands: &[u8], depth: usize) {
+		if self.depth != depth {
+			self.flush();
+			self.depth = depth;
+		}
+
+		for command in commands {
+			if !self.line.is_empty() && self.column() >= self.style.width {
+				self.flush();
+			}
+			self.line.push(*command);
+		}
+	}
+
+	/// The text of a loop body that can stay on a single line
+	fn inline(&self, body: &[Item]) -> Option<Vec<u8>> {
+		let mut text = vec![];
+		for item in body {
+			match item {
+				Item::Commands(commands) => text.extend(commands),
+				_ => return None,
+			}
+		}
+
+		Some(text)
+	}
+
+	fn column(&self) -> usize { self.depth * TAB_WIDTH + self.line.len() }
+
+	fn flush(&mut self) {
+		if self.line.is_empty() {
+			return;
+		}
+
+		self.out.extend(std::iter::repeat_n(b'\t', self.depth));
+		self.out.append(&mut self.line);
+		self.out.push(b'\n');
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Check that formatting the formatted source changes nothing
+	fn idempotent(source: &str) {
+		for width in [16, DEFAULT_WIDTH] {
+			for normalise in [false, true] {
+				let style = Style { width, normalise };
+				let once = Source::parse(source.as_bytes()).unwrap().format(&style);
+				let twice = Source::parse(&once).unwrap().format(&style);
+				assert_eq!(
+					String::from_utf8_lossy(&twice),
+					String::from_utf8_lossy(&once),
+					"{:?} with {:?}",
+					source,
+					style
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn blank_lines_in_loops() {
+		idempotent(",[+-\n\n<]");
+		idempotent(",[<\n\n+-]");
+		idempotent(",[<\n\n+-\n\n>]");
+		idempotent("+-\n\n[<>\n\n-]\n\n<>");
+	}
+
+	#[test]
+	fn loops_that_cancel_out() {
+		idempotent("[+-]");
+		idempotent("+[<>[-+]]-");
+	}
+
+	#[test]
+	fn comments_and_wrapping() {
+		idempotent("read, a byte\n[>+<-] move it\n\n>.");
+		idempotent("+++++++++++++++++++++++++++++++++++++++[>++++++++++++<-]");
+		idempotent("[[->+<] inner\n>>>>>>>>>>>>>>>>>>>>>>>>>>>>]");
+	}
+
+	#[test]
+	fn examples() {
+		let examples = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+		for entry in std::fs::read_dir(examples).unwrap() {
+			let path = entry.unwrap().path();
+			if path.extension().is_some_and(|ext| ext == "bf") {
+				idempotent(&String::from_utf8(std::fs::read(path).unwrap()).unwrap());
+			}
+		}
+	}
+
+	#[test]
+	fn normalised() {
+		let style = Style { normalise: true, ..Default::default() };
+		let format = |source: &str| Source::parse(source.as_bytes()).unwrap().format(&style);
+
+		assert_eq!(format(",[+-\n\n<]"), b",[<]\n");
+		assert_eq!(format("+<>-\n\n\n\n."), b".\n");
+	}
+}
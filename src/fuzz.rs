@@ -0,0 +1,330 @@
+//! Random program generation for differential fuzzing of the optimiser
+//!
+//! Every generated program is optimised with every distinct combination of
+//! [`Optimisations`], round tripped through bytecode, and run against the
+//! unoptimised program on the same input. Optimised programs that stop are
+//! also run on the other backends, which have to agree with the interpreter.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::closure::ClosureProgram;
+use crate::error::Error;
+use crate::instruction::{LinkedInstructions, UnlinkedInstructions};
+use crate::jit::JitProgram;
+use crate::optimise::Optimisations;
+use crate::packed::{PackedInterpreter, PackedProgram};
+use crate::pass::PassManager;
+use crate::verify::{Divergence, End, Execution, compare, execute};
+
+/// A small splitmix64 generator, good enough to pick random programs
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+	pub fn new(seed: u64) -> Self { Self(seed) }
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^ (z >> 31)
+	}
+
+	/// A number in `0..n`, `n` must not be 0
+	pub fn below(&mut self, n: u64) -> u64 { self.next_u64() % n }
+
+	/// Pick an index with a probability proportional to its weight
+	pub fn weighted(&mut self, weights: &[u32]) -> usize {
+		let total: u64 = weights.iter().map(|w| *w as u64).sum();
+		let mut pick = self.below(total.max(1));
+
+		for (idx, weight) in weights.iter().enumerate() {
+			if pick < *weight as u64 {
+				return idx;
+			}
+			pick -= *weight as u64;
+		}
+
+		0
+	}
+}
+
+/// Tunes the shape of generated programs
+///
+/// The weights are relative to each other, a weight of 0 disables that kind
+/// of snippet
+#[derive(Clone, Debug)]
+pub struct GeneratorConfig {
+	/// Roughly how many commands a program consists of
+	pub length:          usize,
+	/// How deep loops may be nested
+	pub max_depth:       usize,
+	/// The maximum number of input bytes
+	pub max_input:       usize,
+	/// A single `+-<>.,` command
+	pub command_weight:  u32,
+	/// A loop around a randomly generated body
+	pub loop_weight:     u32,
+	/// A balanced multiply loop, eg. `[->++>+++<<]`
+	pub multiply_weight: u32,
+	/// A scan loop, eg. `[>>]`
+	pub scan_weight:     u32,
+	/// A clear loop, eg. `[-]`
+	pub clear_weight:    u32,
+}
+
+impl Default for GeneratorConfig {
+	fn default() -> Self {
+		Self {
+			length:          40,
+			max_depth:       3,
+			max_input:       8,
+			command_weight:  12,
+			loop_weight:     2,
+			multiply_weight: 2,
+			scan_weight:     1,
+			clear_weight:    1,
+		}
+	}
+}
+
+/// Generates random well-bracketed programs and their input
+#[derive(Clone, Debug)]
+pub struct Fuzzer {
+	rng:        Rng,
+	pub config: GeneratorConfig,
+}
+
+impl Fuzzer {
+	pub fn new(seed: u64, config: GeneratorConfig) -> Self { Self { rng: Rng::new(seed), config } }
+
+	/// Generate the next program and input
+	pub fn next_case(&mut self) -> (Vec<u8>, Vec<u8>) {
+		let mut source = vec![];
+		self.block(&mut source, 0, self.config.length);
+
+		let input_len = self.rng.below(self.config.max_input as u64 + 1);
+		let input = (0..input_len).map(|_| self.rng.next_u64() as u8).collect();
+
+		(source, input)
+	}
+
+	/// Append snippets until roughly `budget` commands were generated
+	fn block(&mut self, out: &mut Vec<u8>, depth: usize, budget: usize) {
+		let start = out.len();
+
+		while out.len() - start < budget {
+			let loop_weight =
+				if depth < self.config.max_depth { self.config.loop_weight } else { 0 };
+			let kind = self.rng.weighted(&[
+				self.config.command_weight,
+				loop_weight,
+				self.config.multiply_weight,
+				self.config.scan_weight,
+				self.config.clear_weight,
+			]);
+
+			match kind {
+				0 => {
+					// Favour arithmetic and movement over I/O
+					let commands = b"++--<>>.,";
+					out.push(commands[self.rng.below(commands.len() as u64) as usize]);
+				},
+				1 => {
+					out.push(b'[');
+					// Decrementing the condition cell first makes termination likely
+					if self.rng.below(2) == 0 {
+						out.push(b'-');
+					}
+					let remaining = budget.saturating_sub(out.len() - start).max(2);
+					let body = 1 + self.rng.below(remaining as u64 / 2) as usize;
+					self.block(out, depth + 1, body);
+					out.push(b']');
+				},
+				2 => self.multiply_loop(out),
+				3 => {
+					let step = if self.rng.below(2) == 0 { b'>' } else { b'<' };
+					out.push(b'[');
+					for _ in 0..=self.rng.below(2) {
+						out.push(step);
+					}
+					out.push(b']');
+				},
+				_ => out.extend_from_slice(if self.rng.below(2) == 0 { b"[-]" } else { b"[+]" }),
+			}
+		}
+	}
+
+	/// Append a loop that adds multiples of the current cell to its neighbours
+	fn multiply_loop(&mut self, out: &mut Vec<u8>) {
+		out.extend_from_slice(b"[-");
+
+		let mut position: i64 = 0;
+		for _ in 0..=self.rng.below(3) {
+			let mut target = self.rng.below(7) as i64 - 3;
+			if target == 0 {
+				target = 1;
+			}
+
+			let step = if target > position { b'>' } else { b'<' };
+			for _ in 0..(target - position).abs() {
+				out.push(step);
+			}
+			position = target;
+
+			let amount = if self.rng.below(4) == 0 { b'-' } else { b'+' };
+			for _ in 0..=self.rng.below(4) {
+				out.push(amount);
+			}
+		}
+
+		let step = if position > 0 { b'<' } else { b'>' };
+		for _ in 0..position.abs() {
+			out.push(step);
+		}
+		out.push(b']');
+	}
+}
+
+/// What went wrong with an optimised program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+	/// The optimiser panicked
+	Panic(String),
+	/// The optimiser returned an error
+	Error(String),
+	/// Reading back the bytecode gave different instructions
+	BytecodeRoundTrip,
+	/// The optimised program behaves differently
+	Diverged(Divergence),
+	/// A backend runs the optimised program differently than the interpreter
+	Backend(&'static str, Divergence),
+}
+
+/// A combination of optimisations that broke a program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Failure {
+	pub flags: Optimisations,
+	pub kind:  FailureKind,
+}
+
+impl fmt::Display for Failure {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "with -o {}: ", self.flags.to_strings().join(","))?;
+
+		match &self.kind {
+			FailureKind::Panic(message) => write!(f, "the optimiser panicked: {}", message),
+			FailureKind::Error(message) => write!(f, "the optimiser failed: {}", message),
+			FailureKind::BytecodeRoundTrip => write!(f, "the bytecode does not round trip"),
+			FailureKind::Diverged(divergence) => write!(f, "{}", divergence),
+			FailureKind::Backend(backend, divergence) => {
+				write!(f, "on the {} backend, {}", backend, divergence)
+			},
+		}
+	}
+}
+
+/// Check a program against every distinct combination of optimisations,
+/// returning the first one that breaks it
+///
+/// Combinations that result in the same pipeline are only checked once, and
+/// programs that don't link are skipped.
+pub fn check_case(source: &[u8], input: &[u8], max_steps: u64) -> Option<Failure> {
+	let unoptimised = UnlinkedInstructions::from_text(source).link().ok()?;
+	let reference = execute(&unoptimised, input, max_steps).ok()?;
+
+	let mut pipelines = HashSet::new();
+	for bits in 0..=Optimisations::all().bits() {
+		let flags = Optimisations::from_bits_truncate(bits);
+		if !pipelines.insert(PassManager::from_optimisations(&flags).pipeline()) {
+			continue;
+		}
+
+		let fail = |kind| Some(Failure { flags, kind });
+
+		let optimised = catch_unwind(AssertUnwindSafe(|| {
+			UnlinkedInstructions::from_text(source).optimise(&flags)
+		}));
+		let optimised = match optimised {
+			Ok(Ok(optimised)) => optimised,
+			Ok(Err(e)) => return fail(FailureKind::Error(e.to_string())),
+			Err(payload) => {
+				let message = payload
+					.downcast_ref::<&str>()
+					.map(|s| s.to_string())
+					.or_else(|| payload.downcast_ref::<String>().cloned())
+					.unwrap_or_default();
+
+				return fail(FailureKind::Panic(message));
+			},
+		};
+
+		let round_tripped = LinkedInstructions::from_bytecode(&optimised.to_bytecode());
+		if round_tripped != optimised {
+			return fail(FailureKind::BytecodeRoundTrip);
+		}
+
+		let execution = match execute(&round_tripped, input, max_steps) {
+			Ok(execution) => execution,
+			Err(e) => return fail(FailureKind::Error(e.to_string())),
+		};
+		if let Some(divergence) = compare(&reference, &execution) {
+			return fail(FailureKind::Diverged(divergence));
+		}
+
+		if let Some((backend, divergence)) = check_backends(&round_tripped, input, &execution) {
+			return fail(FailureKind::Backend(backend, divergence));
+		}
+	}
+
+	None
+}
+
+/// Run a program on every other backend, returning the first one that
+/// doesn't match the interpreter's run
+///
+/// The other backends can't stop after a number of steps, so this only
+/// checks programs that stopped on the interpreter
+fn check_backends(
+	insts: &LinkedInstructions,
+	input: &[u8],
+	expected: &Execution,
+) -> Option<(&'static str, Divergence)> {
+	if expected.end == End::StepLimit {
+		return None;
+	}
+
+	let execution = |result: Result<(), Error>, output, dp, memory: &[u8]| {
+		// Running out of input is the only error on in-memory streams
+		let end = if result.is_ok() { End::Halted } else { End::OutOfInput };
+
+		Execution { end, steps: expected.steps, output, dp, memory: memory.to_vec() }
+	};
+
+	// Unwrap is safe as the instructions are correctly linked
+	let program = PackedProgram::new(insts).unwrap();
+	let mut packed = PackedInterpreter::new(&program);
+	let mut output = vec![];
+	let result = packed.run_with(&mut &input[..], &mut output);
+	let packed = execution(result, output, packed.dp(), packed.memory());
+
+	let mut closures = ClosureProgram::new(insts).unwrap();
+	let mut output = vec![];
+	let result = closures.run_with(&mut &input[..], &mut output);
+	let closures = execution(result, output, closures.dp(), closures.memory());
+
+	let mut runs = vec![("packed", packed), ("closure", closures)];
+
+	// The JIT is skipped on platforms it doesn't support
+	if let Ok(mut jit) = JitProgram::new(insts) {
+		let mut output = vec![];
+		let result = jit.run_with(&mut &input[..], &mut output);
+		runs.push(("jit", execution(result, output, jit.dp(), jit.memory())));
+	}
+
+	runs.into_iter().find_map(|(name, actual)| compare(expected, &actual).map(|d| (name, d)))
+}
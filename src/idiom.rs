@@ -0,0 +1,191 @@
+//! A catalogue of well-known snippets that get replaced by a single native
+//! instruction
+//!
+//! Every snippet clears its own workspace before using it, so its effect only
+//! depends on the values of its input cells. This makes it possible to prove
+//! a replacement correct by running both for every possible input.
+
+use std::io::empty;
+
+use itertools::Itertools;
+
+use crate::error::Error;
+use crate::instruction::{Ast, Instruction, LinkedInstructions, Node, UnlinkedInstructions};
+use crate::interpret::Interpreter;
+
+pub struct Idiom {
+	pub name:        &'static str,
+	/// The brainfuck source of the snippet
+	pub source:      &'static str,
+	/// The offsets of the cells the snippet reads from
+	pub inputs:      &'static [u16],
+	/// The number of cells the snippet uses, starting at its first cell
+	pub width:       u16,
+	pub replacement: Instruction,
+}
+
+/// All idioms recognised by the optimiser
+pub const IDIOMS: [Idiom; 3] = [
+	// n d r q t f s -> 0 d n%d n/d d-n%d 0 0
+	// A divisor of 0 behaves like a divisor of 256
+	Idiom {
+		name:        "divmod",
+		source:      concat!(
+			">>[-]>[-]>[-]>[-]>[-]",
+			"<<<<<[>>>+>>+<<<<<-]>>>>>[<<<<<+>>>>>-]",
+			"<<<<<<[->>+>>->+<[>[-]>+<<-]>>[<<+>>-]",
+			"<[<<+<[-]<[>>>+>>+<<<<<-]>>>>>[<<<<<+>>>>>-]<-]<<<<<]",
+		),
+		inputs:      &[0, 1],
+		width:       7,
+		replacement: Instruction::DivMod,
+	},
+	// x y a b f s g -> x<y y 0 0 0 0 0
+	Idiom {
+		name:        "compare",
+		source:      concat!(
+			">>[-]>[-]>[-]>[-]>[-]",
+			"<<<<<<[>>+<<-]>[>>+>>+<<<<-]>>>>[<<<<+>>>>-]",
+			"<<<[->>+<[>[-]>+<<-]>>[<<+>>-]",
+			">+<<[<<[-]>>>>-<<-]>>[<<<->>>-]<<<<]",
+			">[<<<+>>>[-]]<<<",
+		),
+		inputs:      &[0, 1],
+		width:       7,
+		replacement: Instruction::Compare,
+	},
+	// x c o t h f e s g -> x 0 0 0 0 0 0 0 0, writing x as a decimal number
+	Idiom {
+		name:        "write-decimal",
+		source:      concat!(
+			">[-]>[-]>[-]>[-]>[-]>[-]>[-]>[-]",
+			"<<<<<<<<[>+>>>>>>+<<<<<<<-]>>>>>>>[<<<<<<<+>>>>>>>-]",
+			"<<<<<<[->+[>>>>>+>+<<<<<<-]>>>>>>[<<<<<<+>>>>>>-]<----------<<+>>[<<->>[-]]",
+			"<<[<<<[-]>+[>>>>+>+<<<<<-]>>>>>[<<<<<+>>>>>-]<----------<+>[<->[-]]",
+			"<[<<<[-]>+>>-]<-]<<<<]",
+			">>>[>>>+>+<<<<-]>>>>[<<<<+>>>>-]<[<<[-]+>>[-]]",
+			"<<[<++++++++++++++++++++++++++++++++++++++++++++++++.[-]>>+<-]",
+			"<<[>>>>+>+<<<<<-]>>>>>[<<<<<+>>>>>-]<[<<[-]+>>[-]]<[<[-]+>-]",
+			"<[<<++++++++++++++++++++++++++++++++++++++++++++++++.>>-]<<[-]",
+			"<++++++++++++++++++++++++++++++++++++++++++++++++.[-]<<",
+		),
+		inputs:      &[0],
+		width:       9,
+		replacement: Instruction::WriteDecimal,
+	},
+];
+
+impl Idiom {
+	/// The unoptimised instructions making up the snippet
+	pub fn pattern(&self) -> Vec<Instruction> {
+		UnlinkedInstructions::from_text(self.source.as_bytes()).0
+	}
+
+	/// The snippet as a tree, this is what the optimiser matches against
+	pub fn nodes(&self) -> Vec<Node> {
+		// Unwrap is safe as every snippet is balanced
+		Ast::from_unlinked(&UnlinkedInstructions(self.pattern())).unwrap().0
+	}
+
+	/// Check that the replacement behaves exactly like the snippet for every
+	/// possible value of the input cells
+	///
+	/// The rest of the snippet's cells are filled with garbage, as the snippet
+	/// is supposed to clear them itself
+	pub fn prove(&self) -> Result<(), Error> { self.prove_over(&(0..=u8::MAX).collect::<Vec<_>>()) }
+
+	/// Check that the replacement behaves exactly like the snippet for every
+	/// combination of the given values of the input cells
+	pub fn prove_over(&self, cell_values: &[u8]) -> Result<(), Error> {
+		let snippet = UnlinkedInstructions(self.pattern()).link()?;
+		let replacement = LinkedInstructions(vec![self.replacement]);
+
+		let grid = self.inputs.iter().map(|_| cell_values.iter().copied());
+		for values in grid.multi_cartesian_product() {
+			let (snippet_memory, snippet_dp, snippet_output) = run_on(&snippet, self, &values)?;
+			let (native_memory, native_dp, native_output) = run_on(&replacement, self, &values)?;
+
+			if snippet_memory != native_memory
+				|| snippet_dp != native_dp
+				|| snippet_output != native_output
+			{
+				return Err(Error::IdiomMismatch(self.name, values));
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Run a program on a tape initialised for the given idiom, returning the
+/// final tape, data pointer, and output
+fn run_on(
+	insts: &LinkedInstructions,
+	idiom: &Idiom,
+	values: &[u8],
+) -> Result<(Vec<u8>, u16, Vec<u8>), Error> {
+	let mut interpreter = Interpreter::new(insts);
+
+	let memory = interpreter.memory_mut();
+	for ofst in 0..idiom.width {
+		memory[ofst as usize] = 0xA5 ^ ofst as u8;
+	}
+	for (ofst, value) in idiom.inputs.iter().zip(values) {
+		memory[*ofst as usize] = *value;
+	}
+
+	let mut output = vec![];
+	interpreter.run_with(&mut empty(), &mut output)?;
+
+	Ok((interpreter.memory().to_vec(), interpreter.dp(), output))
+}
+
+/// Prove every idiom in the catalogue, see [`Idiom::prove`]
+pub fn prove_catalogue() -> Result<(), Error> {
+	for idiom in IDIOMS.iter() {
+		idiom.prove()?;
+	}
+
+	Ok(())
+}
+
+/// Find the idiom whose pattern the nodes start with
+///
+/// `patterns` holds the nodes of every idiom in [`IDIOMS`], in order
+pub fn match_idiom(patterns: &[Vec<Node>], nodes: &[Node]) -> Option<&'static Idiom> {
+	IDIOMS.iter().zip(patterns).find(|(_, pattern)| nodes.starts_with(pattern)).map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The edges of the cell range and of the decimal digits, and a few values
+	/// in between
+	const SAMPLE: [u8; 14] = [0, 1, 2, 3, 7, 9, 10, 11, 99, 100, 127, 128, 254, 255];
+
+	fn prove(name: &str) {
+		let idiom = IDIOMS.iter().find(|idiom| idiom.name == name).unwrap();
+		idiom.prove_over(&SAMPLE).unwrap();
+	}
+
+	#[test]
+	fn divmod() { prove("divmod"); }
+
+	#[test]
+	fn compare() { prove("compare"); }
+
+	#[test]
+	fn write_decimal() { prove("write-decimal"); }
+
+	#[test]
+	fn every_idiom_has_a_test() {
+		let names: Vec<&str> = IDIOMS.iter().map(|idiom| idiom.name).collect();
+		assert_eq!(names, ["divmod", "compare", "write-decimal"]);
+	}
+
+	/// Every possible input, run with `cargo test --release -- --ignored`
+	#[test]
+	#[ignore]
+	fn whole_catalogue() { prove_catalogue().unwrap(); }
+}
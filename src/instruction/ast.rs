@@ -0,0 +1,108 @@
+use super::{Ast, Instruction, LinkedInstructions, Node, UnlinkedInstructions};
+use crate::error::Error;
+
+impl Ast {
+	/// Build the tree from unlinked instructions
+	pub fn from_unlinked(insts: &UnlinkedInstructions) -> Result<Self, Error> {
+		Self::from_instructions(&insts.0)
+	}
+
+	/// Build the tree from linked instructions, their jump targets are ignored
+	pub fn from_linked(insts: &LinkedInstructions) -> Result<Self, Error> {
+		Self::from_instructions(&insts.0)
+	}
+
+	/// Nest the instructions between matching branches, failing with the same
+	/// errors as [`UnlinkedInstructions::link`]
+	fn from_instructions(insts: &[Instruction]) -> Result<Self, Error> {
+		// The index of every open branch, and the nodes of the body around it
+		let mut open: Vec<(usize, Vec<Node>)> = Vec::with_capacity(5);
+		let mut current = Vec::with_capacity(insts.len());
+
+		for (idx, inst) in insts.iter().enumerate() {
+			match inst {
+				Instruction::BranchIfZero { .. } | Instruction::If { .. } => {
+					open.push((idx, std::mem::take(&mut current)));
+				},
+				Instruction::BranchIfNotZero { .. } | Instruction::EndIf => {
+					let (opening_idx, parent) = match open.pop() {
+						Some(opening) => opening,
+						None => return Err(Error::MissingOpeningBracket(idx)),
+					};
+					let body = std::mem::replace(&mut current, parent);
+
+					current.push(match (insts[opening_idx], inst) {
+						(Instruction::BranchIfZero { .. }, Instruction::BranchIfNotZero { .. }) => {
+							Node::Loop(body)
+						},
+						(Instruction::If { .. }, Instruction::EndIf) => Node::If(body),
+						_ => return Err(Error::MismatchedBranch(idx)),
+					});
+				},
+				inst => current.push(Node::Inst(*inst)),
+			}
+		}
+
+		if let Some((opening_idx, _)) = open.pop() {
+			return Err(Error::MissingClosingBracket(opening_idx));
+		}
+
+		Ok(Self(current))
+	}
+
+	/// Flatten the tree, the jump targets are already filled in
+	pub fn to_unlinked(&self) -> UnlinkedInstructions { UnlinkedInstructions(self.flatten()) }
+
+	/// Flatten the tree into linked instructions, this can't fail as the tree
+	/// is always balanced
+	pub fn to_linked(&self) -> LinkedInstructions { LinkedInstructions(self.flatten()) }
+
+	/// The number of instructions the tree flattens to
+	pub fn instruction_count(&self) -> usize { count_instructions(&self.0) }
+
+	fn flatten(&self) -> Vec<Instruction> {
+		let mut insts = Vec::with_capacity(self.instruction_count());
+		flatten_into(&self.0, &mut insts);
+
+		insts
+	}
+}
+
+/// Append the flattened nodes to `insts`, linking every branch on the way
+fn flatten_into(nodes: &[Node], insts: &mut Vec<Instruction>) {
+	for node in nodes {
+		match node {
+			Node::Inst(inst) => insts.push(*inst),
+			Node::Loop(body) => {
+				let opening_idx = insts.len();
+				insts.push(Instruction::BranchIfZero { destination: 0 });
+				flatten_into(body, insts);
+
+				let closing_idx = insts.len();
+				insts.push(Instruction::BranchIfNotZero { destination: opening_idx as u64 });
+				insts[opening_idx] = Instruction::BranchIfZero { destination: closing_idx as u64 };
+			},
+			Node::If(body) => {
+				let opening_idx = insts.len();
+				insts.push(Instruction::If { destination: 0 });
+				flatten_into(body, insts);
+
+				let closing_idx = insts.len();
+				insts.push(Instruction::EndIf);
+				insts[opening_idx] = Instruction::If { destination: closing_idx as u64 };
+			},
+		}
+	}
+}
+
+fn count_instructions(nodes: &[Node]) -> usize {
+	nodes
+		.iter()
+		.map(|node| {
+			match node {
+				Node::Inst(_) => 1,
+				Node::Loop(body) | Node::If(body) => 2 + count_instructions(body),
+			}
+		})
+		.sum()
+}
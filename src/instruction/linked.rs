@@ -30,6 +30,17 @@ impl LinkedInstructions {
 		bytes
 	}
 
+	/// Convert the instructions into symbolic code, one instruction per line
+	pub fn to_symcode(&self) -> String {
+		let mut repr = String::with_capacity(self.0.len() * 4);
+		for i in self.0.iter() {
+			repr.push_str(&i.to_string());
+			repr.push('\n');
+		}
+
+		repr
+	}
+
 	/// Read bytecode into a series of instructions
 	pub fn from_bytecode(bytes: &[u8]) -> Self {
 		let mut instructions = Vec::with_capacity(bytes.len() / 2);
@@ -78,6 +89,32 @@ impl LinkedInstructions {
 
 					Instruction::Mul { amount, offset }
 				},
+				8 => {
+					let amount = *byte_iter.next().unwrap() as i8;
+					let src_parts = take2(&mut byte_iter);
+					let source = i16::from_be_bytes(src_parts);
+					let ofst_parts = take2(&mut byte_iter);
+					let offset = i16::from_be_bytes(ofst_parts);
+
+					Instruction::MulAcc { amount, source, offset }
+				},
+				9 => {
+					let amount = *byte_iter.next().unwrap() as i8;
+					let ofst_parts = take2(&mut byte_iter);
+					let offset = i16::from_be_bytes(ofst_parts);
+
+					Instruction::TriAcc { amount, offset }
+				},
+				10 => {
+					let parts = take8(&mut byte_iter);
+					let destination = u64::from_be_bytes(parts);
+
+					Instruction::If { destination }
+				},
+				11 => Instruction::EndIf,
+				12 => Instruction::DivMod,
+				13 => Instruction::Compare,
+				14 => Instruction::WriteDecimal,
 				_ => unreachable!(),
 			};
 
@@ -1,5 +1,6 @@
 use std::fmt;
 
+mod ast;
 mod linked;
 mod unlinked;
 
@@ -13,6 +14,22 @@ pub struct UnlinkedInstructions(pub Vec<Instruction>);
 #[repr(transparent)]
 pub struct LinkedInstructions(pub Vec<Instruction>);
 
+/// Instructions structured as a tree, where loops and ifs hold their body
+/// instead of jump targets
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Ast(pub Vec<Node>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+	/// Any instruction but a branch
+	Inst(Instruction),
+	/// `[ body ]`
+	Loop(Vec<Node>),
+	/// A body that runs at most once, see [`Instruction::If`]
+	If(Vec<Node>),
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Position {
 	start: usize,
@@ -67,6 +84,16 @@ pub enum Instruction {
 	// The following instructions are IR-only, the have no direct BF equivalent
 	Set { amount: Cell, offset: i16 },
 	Mul { amount: Cell, offset: i16 },
+	MulAcc { amount: Cell, source: i16, offset: i16 },
+	TriAcc { amount: Cell, offset: i16 },
+	If { destination: u64 },
+	EndIf,
+
+	// Native versions of the snippets in the idiom catalogue, these operate on
+	// a fixed layout of cells starting at MEM[DP]
+	DivMod,
+	Compare,
+	WriteDecimal,
 }
 
 impl fmt::Display for Instruction {
@@ -82,6 +109,17 @@ impl fmt::Display for Instruction {
 			Self::Mul { amount, offset } => {
 				write!(f, "MEM[DP + {}] += MEM[DP] * {}", offset, amount)
 			},
+			Self::MulAcc { amount, source, offset } => {
+				write!(f, "MEM[DP + {}] += MEM[DP] * MEM[DP + {}] * {}", offset, source, amount)
+			},
+			Self::TriAcc { amount, offset } => {
+				write!(f, "MEM[DP + {}] += TRI(MEM[DP]) * {}", offset, amount)
+			},
+			Self::If { destination } => write!(f, "IF FWD {}", destination),
+			Self::EndIf => write!(f, "END IF"),
+			Self::DivMod => write!(f, "DIVMOD MEM[DP] / MEM[DP + 1]"),
+			Self::Compare => write!(f, "MEM[DP] = MEM[DP] < MEM[DP + 1]"),
+			Self::WriteDecimal => write!(f, "WRITE DECIMAL <- MEM[DP]"),
 		}
 	}
 }
@@ -138,6 +176,68 @@ impl Instruction {
 
 				inst_bytes
 			},
+			Self::MulAcc { amount, source, offset } => {
+				let mut inst_bytes = vec![8, *amount as u8];
+				let src_parts: [u8; 2] = source.to_be_bytes();
+				inst_bytes.extend_from_slice(&src_parts);
+				let ofst_parts: [u8; 2] = offset.to_be_bytes();
+				inst_bytes.extend_from_slice(&ofst_parts);
+
+				inst_bytes
+			},
+			Self::TriAcc { amount, offset } => {
+				let mut inst_bytes = vec![9, *amount as u8];
+				let ofst_parts: [u8; 2] = offset.to_be_bytes();
+				inst_bytes.extend_from_slice(&ofst_parts);
+
+				inst_bytes
+			},
+			Self::If { destination } => {
+				let mut inst_bytes = vec![10];
+				let dest_parts: [u8; 8] = destination.to_be_bytes();
+				inst_bytes.extend_from_slice(&dest_parts);
+
+				inst_bytes
+			},
+			Self::EndIf => {
+				vec![11]
+			},
+			Self::DivMod => {
+				vec![12]
+			},
+			Self::Compare => {
+				vec![13]
+			},
+			Self::WriteDecimal => {
+				vec![14]
+			},
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn if_bytecode_round_trip() {
+		let insts = LinkedInstructions(vec![
+			Instruction::Incr { amount: 1, offset: 0 },
+			Instruction::If { destination: 4 },
+			Instruction::Incr { amount: 1, offset: 1 },
+			Instruction::Set { amount: 0, offset: 0 },
+			Instruction::EndIf,
+			Instruction::Write,
+		]);
+
+		let bytes = insts.to_bytecode();
+		assert_eq!(&bytes[4..13], [10, 0, 0, 0, 0, 0, 0, 0, 4]);
+		assert_eq!(LinkedInstructions::from_bytecode(&bytes), insts);
+	}
+
+	#[test]
+	fn if_symcode() {
+		assert_eq!(Instruction::If { destination: 4 }.to_string(), "IF FWD 4");
+		assert_eq!(Instruction::EndIf.to_string(), "END IF");
+	}
+}
@@ -23,7 +23,8 @@ impl UnlinkedInstructions {
 		UnlinkedInstructions(unlinked_insts)
 	}
 
-	/// Set the jump targets for corresponding `[` and `]` instructions
+	/// Set the jump targets for corresponding `[` and `]`, and If and EndIf
+	/// instructions
 	pub fn link(mut self) -> Result<LinkedInstructions, Error> {
 		let mut jump_stack: Vec<usize> = Vec::with_capacity(5);
 
@@ -36,7 +37,12 @@ impl UnlinkedInstructions {
 				// ]
 				Instruction::BranchIfNotZero { .. } => {
 					let opening_idx = match jump_stack.pop() {
-						Some(op_idx) => op_idx,
+						Some(op_idx)
+							if matches!(self.0[op_idx], Instruction::BranchIfZero { .. }) =>
+						{
+							op_idx
+						},
+						Some(_) => return Err(Error::MismatchedBranch(i)),
 						None => {
 							return Err(Error::MissingOpeningBracket(i));
 						},
@@ -47,6 +53,22 @@ impl UnlinkedInstructions {
 					// The corresponding [ needs to point to the current instruction
 					self.0[opening_idx] = Instruction::BranchIfZero { destination: i as u64 };
 				},
+				Instruction::If { .. } => {
+					jump_stack.push(i);
+				},
+				Instruction::EndIf => {
+					let opening_idx = match jump_stack.pop() {
+						Some(op_idx) if matches!(self.0[op_idx], Instruction::If { .. }) => op_idx,
+						Some(_) => return Err(Error::MismatchedBranch(i)),
+						None => {
+							return Err(Error::MissingOpeningBracket(i));
+						},
+					};
+
+					// Only the If needs a jump target, the EndIf just marks the end of
+					// the body
+					self.0[opening_idx] = Instruction::If { destination: i as u64 };
+				},
 				_ => (),
 			}
 		}
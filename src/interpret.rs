@@ -3,18 +3,219 @@ use std::io::{BufReader, BufWriter, Read, Write};
 use crate::error::Error;
 use crate::instruction::{Instruction, LinkedInstructions};
 
-const MEM_SIZE: usize = 65536;
+/// The largest, and default, number of cells on the tape
+pub const MAX_TAPE_SIZE: usize = 65536;
+
+/// What a read does once the input is exhausted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Eof {
+	/// Stop the program with [`Error::CouldNotReadInput`]
+	#[default]
+	Error,
+	/// Leave the cell as it is
+	Unchanged,
+	/// Set the cell to 0
+	Zero,
+	/// Set the cell to -1, ie. 255
+	MinusOne,
+}
+
+/// The machine a program runs on, shared by all backends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Options {
+	/// The number of cells on the tape, the data pointer wraps around at
+	/// either end, must be in `1..=MAX_TAPE_SIZE`
+	pub tape_size: usize,
+	pub eof:       Eof,
+}
+
+impl Default for Options {
+	fn default() -> Self { Self { tape_size: MAX_TAPE_SIZE, eof: Eof::default() } }
+}
+
+/// Move the data pointer `dp` by `amount` cells on a tape of `tape_size`
+/// cells, wrapping around its ends
+pub(crate) fn wrap(dp: u16, amount: i64, tape_size: usize) -> u16 {
+	if tape_size == MAX_TAPE_SIZE {
+		return dp.wrapping_add(amount as u16);
+	}
+
+	(dp as i64 + amount).rem_euclid(tape_size as i64) as u16
+}
+
+/// How the data pointer wraps around the ends of the tape
+///
+/// Backends get instantiated once per tape, so the full sized tape gets away
+/// with plain u16 arithmetic
+pub(crate) trait Tape: Copy {
+	/// Move the data pointer by `amount` cells
+	fn wrap(self, dp: u16, amount: i32) -> u16;
+}
+
+/// A tape of `MAX_TAPE_SIZE` cells, where u16 arithmetic wraps for free
+#[derive(Clone, Copy)]
+pub(crate) struct FullTape;
+
+impl Tape for FullTape {
+	#[inline(always)]
+	fn wrap(self, dp: u16, amount: i32) -> u16 { dp.wrapping_add(amount as u16) }
+}
+
+/// A tape of any smaller size
+#[derive(Clone, Copy)]
+pub(crate) struct ShortTape(pub usize);
+
+impl Tape for ShortTape {
+	#[inline(always)]
+	fn wrap(self, dp: u16, amount: i32) -> u16 { wrap(dp, amount as i64, self.0) }
+}
+
+/// Read a single byte into `cell`, handling the end of the input as `eof`
+/// says
+pub(crate) fn read_cell<R: Read + ?Sized>(
+	reader: &mut R,
+	eof: Eof,
+	cell: &mut u8,
+) -> Result<(), Error> {
+	let mut buffer = [0; 1];
+	if reader.read(&mut buffer)? == 1 {
+		*cell = buffer[0];
+		return Ok(());
+	}
+
+	match eof {
+		Eof::Error => return Err(Error::CouldNotReadInput),
+		Eof::Unchanged => (),
+		Eof::Zero => *cell = 0,
+		Eof::MinusOne => *cell = 255,
+	}
+
+	Ok(())
+}
+
+/// The implementation-defined behaviour a run relied on, as recorded by
+/// [`Interpreter::instrumented`]
+///
+/// Positions are on a tape without ends, so moving left of cell 0 gives
+/// negative positions rather than wrapping around
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+	/// The position of the data pointer
+	position:            i64,
+	/// The leftmost cell the data pointer reached or an instruction accessed
+	pub lowest:          i64,
+	/// The rightmost cell the data pointer reached or an instruction accessed
+	pub highest:         i64,
+	/// The index of the instruction that first moved left of cell 0
+	pub first_left:      Option<usize>,
+	/// The index of the instruction that first wrapped a cell around past 0
+	/// or 255
+	pub first_cell_wrap: Option<usize>,
+}
+
+impl Usage {
+	/// Record an instruction about to be executed
+	fn observe(&mut self, ip: usize, inst: Instruction, memory: &[u8], dp: u16, tape_size: usize) {
+		let cell = |offset: i16| memory[wrap(dp, offset as i64, tape_size) as usize] as i64;
+
+		match inst {
+			Instruction::IncrDp { amount } => {
+				self.position += amount as i64;
+				self.touch(ip, 0);
+			},
+			Instruction::Incr { amount, offset } => {
+				self.touch(ip, offset);
+				self.result(ip, cell(offset) + amount as i64);
+			},
+			Instruction::Set { offset, .. } => self.touch(ip, offset),
+			Instruction::Mul { amount, offset } => {
+				self.touch(ip, offset);
+				self.result(ip, cell(offset) + cell(0) * amount as i64);
+			},
+			Instruction::MulAcc { amount, source, offset } => {
+				self.touch(ip, source);
+				self.touch(ip, offset);
+				self.result(ip, cell(offset) + cell(0) * cell(source) * amount as i64);
+			},
+			Instruction::TriAcc { amount, offset } => {
+				self.touch(ip, offset);
+				self.result(ip, cell(offset) + cell(0) * (cell(0) + 1) / 2 * amount as i64);
+			},
+			Instruction::DivMod | Instruction::Compare => {
+				for offset in 0..=6 {
+					self.touch(ip, offset);
+				}
+			},
+			Instruction::WriteDecimal => {
+				for offset in 0..=8 {
+					self.touch(ip, offset);
+				}
+			},
+			_ => self.touch(ip, 0),
+		}
+	}
+
+	/// Record an access to the cell `offset` cells away from the data pointer
+	fn touch(&mut self, ip: usize, offset: i16) {
+		let position = self.position + offset as i64;
+		self.lowest = self.lowest.min(position);
+		self.highest = self.highest.max(position);
+
+		if position < 0 && self.first_left.is_none() {
+			self.first_left = Some(ip);
+		}
+	}
+
+	/// Record the value an instruction computes for a cell, before it wraps
+	fn result(&mut self, ip: usize, value: i64) {
+		if !(0..=255).contains(&value) && self.first_cell_wrap.is_none() {
+			self.first_cell_wrap = Some(ip);
+		}
+	}
+}
 
 pub struct Interpreter<'i> {
-	ip:     usize,
-	dp:     u16,
-	memory: [u8; MEM_SIZE],
-	insts:  &'i [Instruction],
+	ip:      usize,
+	dp:      u16,
+	steps:   u64,
+	/// Always the largest tape, so any u16 is a valid index
+	memory:  Box<[u8; MAX_TAPE_SIZE]>,
+	insts:   &'i [Instruction],
+	options: Options,
+	usage:   Option<Usage>,
 }
 
 impl<'i> Interpreter<'i> {
 	pub fn new(insts: &'i LinkedInstructions) -> Self {
-		Self { ip: 0, dp: 0, memory: [0; MEM_SIZE], insts: &insts.0 }
+		Self::with_options(insts, Options::default())
+	}
+
+	/// Create an interpreter for a differently configured machine, the tape
+	/// size gets clamped to `1..=MAX_TAPE_SIZE`
+	pub fn with_options(insts: &'i LinkedInstructions, mut options: Options) -> Self {
+		options.tape_size = options.tape_size.clamp(1, MAX_TAPE_SIZE);
+
+		Self {
+			ip: 0,
+			dp: 0,
+			steps: 0,
+			memory: Box::new([0; MAX_TAPE_SIZE]),
+			insts: &insts.0,
+			options,
+			usage: None,
+		}
+	}
+
+	/// Create an interpreter that records the implementation-defined
+	/// behaviour the program relies on, see [`Interpreter::usage`]
+	///
+	/// Only unoptimised instructions map onto the commands of the source, and
+	/// the optimiser may have removed cell wrap-around from a program
+	pub fn instrumented(insts: &'i LinkedInstructions, options: Options) -> Self {
+		let mut interpreter = Self::with_options(insts, options);
+		interpreter.usage = Some(Usage::default());
+
+		interpreter
 	}
 
 	/// Run the provided bytecode
@@ -22,46 +223,149 @@ impl<'i> Interpreter<'i> {
 		let mut writer = BufWriter::new(std::io::stdout());
 		let mut reader = BufReader::new(std::io::stdin());
 
+		self.run_with(&mut reader, &mut writer)
+	}
+
+	/// Run the provided bytecode, reading from and writing to the given
+	/// streams instead of stdin and stdout
+	pub fn run_with<R: Read, W: Write>(
+		&mut self,
+		reader: &mut R,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		self.run_limited(reader, writer, u64::MAX)?;
+
+		Ok(())
+	}
+
+	/// Run the provided bytecode until it halts, or until it executed
+	/// `max_steps` instructions in total
+	///
+	/// Returns whether the program halted, running it again continues where
+	/// it stopped
+	pub fn run_limited<R: Read, W: Write>(
+		&mut self,
+		reader: &mut R,
+		writer: &mut W,
+		max_steps: u64,
+	) -> Result<bool, Error> {
+		match self.usage {
+			Some(_) => self.execute::<true, _, _>(reader, writer, max_steps),
+			None => self.execute::<false, _, _>(reader, writer, max_steps),
+		}
+	}
+
+	/// The loop of [`Interpreter::run_limited`], recording the usage of every
+	/// instruction only if `INSTRUMENTED`, so normal runs don't check for it
+	/// on every step
+	fn execute<const INSTRUMENTED: bool, R: Read, W: Write>(
+		&mut self,
+		reader: &mut R,
+		writer: &mut W,
+		max_steps: u64,
+	) -> Result<bool, Error> {
 		while self.ip < self.insts.len() {
+			if self.steps >= max_steps {
+				writer.flush()?;
+				return Ok(false);
+			}
+			self.steps += 1;
+
+			if INSTRUMENTED {
+				if let Some(usage) = &mut self.usage {
+					let inst = self.insts[self.ip];
+					usage.observe(self.ip, inst, &self.memory[..], self.dp, self.options.tape_size);
+				}
+			}
+
+			let dp = self.dp as usize;
 			match self.insts[self.ip] {
 				Instruction::IncrDp { amount } => {
-					self.dp += amount as u16;
+					self.dp = self.index(amount);
 				},
 				Instruction::Incr { amount, offset } => {
-					self.memory[(self.dp + offset as u16) as usize] += amount as u8;
+					let idx = self.index(offset) as usize;
+					self.memory[idx] = self.memory[idx].wrapping_add(amount as u8);
 				},
 				Instruction::Write => {
-					writer.write_all(&[self.memory[self.dp as usize]])?;
+					writer.write_all(&[self.memory[dp]])?;
 				},
 				Instruction::Read => {
 					writer.flush()?;
-					let mut buffer = [0; 1];
-					let bytes = reader.read(&mut buffer)?;
-
-					if bytes == 1 {
-						self.memory[self.dp as usize] = buffer[0];
-					} else {
-						return Err(Error::CouldNotReadInput);
-					}
+					read_cell(reader, self.options.eof, &mut self.memory[dp])?;
 				},
 				Instruction::BranchIfZero { destination } => {
-					if self.memory[self.dp as usize] == 0 {
+					if self.memory[dp] == 0 {
 						self.ip = destination as usize;
 						continue;
 					}
 				},
 				Instruction::BranchIfNotZero { destination } => {
-					if self.memory[self.dp as usize] != 0 {
+					if self.memory[dp] != 0 {
 						self.ip = destination as usize;
 						continue;
 					}
 				},
 				Instruction::Set { amount, offset } => {
-					self.memory[(self.dp + offset as u16) as usize] = amount as u8;
+					let idx = self.index(offset) as usize;
+					self.memory[idx] = amount as u8;
 				},
 				Instruction::Mul { amount, offset } => {
-					self.memory[(self.dp + offset as u16) as usize] +=
-						self.memory[self.dp as usize] * amount as u8
+					let idx = self.index(offset) as usize;
+					self.memory[idx] =
+						self.memory[idx].wrapping_add(self.memory[dp].wrapping_mul(amount as u8));
+				},
+				Instruction::MulAcc { amount, source, offset } => {
+					let idx = self.index(offset) as usize;
+					let product = self.memory[dp]
+						.wrapping_mul(self.memory[self.index(source) as usize])
+						.wrapping_mul(amount as u8);
+					self.memory[idx] = self.memory[idx].wrapping_add(product);
+				},
+				Instruction::TriAcc { amount, offset } => {
+					let n = self.memory[dp] as u32;
+					let triangle = (n * (n + 1) / 2) as u8;
+
+					let idx = self.index(offset) as usize;
+					self.memory[idx] =
+						self.memory[idx].wrapping_add(triangle.wrapping_mul(amount as u8));
+				},
+				Instruction::If { destination } => {
+					if self.memory[dp] == 0 {
+						self.ip = destination as usize;
+						continue;
+					}
+				},
+				Instruction::EndIf => (),
+				Instruction::DivMod => {
+					let n = self.memory[dp] as u16;
+					let d = match self.memory[self.index(1) as usize] {
+						0 => 256,
+						d => d as u16,
+					};
+
+					self.set(0, 0);
+					self.set(2, (n % d) as u8);
+					self.set(3, (n / d) as u8);
+					self.set(4, (d - n % d) as u8);
+					self.set(5, 0);
+					self.set(6, 0);
+				},
+				Instruction::Compare => {
+					let x = self.memory[dp];
+					let y = self.memory[self.index(1) as usize];
+
+					self.set(0, (x < y) as u8);
+					for ofst in 2..=6 {
+						self.set(ofst, 0);
+					}
+				},
+				Instruction::WriteDecimal => {
+					write!(writer, "{}", self.memory[dp])?;
+
+					for ofst in 1..=8 {
+						self.set(ofst, 0);
+					}
 				},
 			}
 
@@ -70,6 +374,30 @@ impl<'i> Interpreter<'i> {
 
 		writer.flush()?;
 
-		Ok(())
+		Ok(true)
+	}
+
+	/// The position of the cell `offset` cells away from the data pointer
+	fn index(&self, offset: i16) -> u16 { wrap(self.dp, offset as i64, self.options.tape_size) }
+
+	fn set(&mut self, offset: i16, value: u8) {
+		let idx = self.index(offset) as usize;
+		self.memory[idx] = value;
 	}
+
+	/// The number of instructions executed so far
+	pub fn steps(&self) -> u64 { self.steps }
+
+	/// The position of the data pointer
+	pub fn dp(&self) -> u16 { self.dp }
+
+	/// What the program relied on so far, if the interpreter is instrumented
+	pub fn usage(&self) -> Option<&Usage> { self.usage.as_ref() }
+
+	/// The contents of the tape
+	pub fn memory(&self) -> &[u8] { &self.memory[..self.options.tape_size] }
+
+	/// Mutable access to the contents of the tape, eg. to set up a program's
+	/// input cells before running it
+	pub fn memory_mut(&mut self) -> &mut [u8] { &mut self.memory[..self.options.tape_size] }
 }
@@ -0,0 +1,525 @@
+//! Abstract interpretation of linked instructions with intervals
+//!
+//! Before every instruction, the analysis knows a range for the data pointer
+//! and for the value of every cell. The data pointer is tracked as if the
+//! tape had no ends, so a range reaching below 0 or past the end of the tape
+//! means the pointer may wrap around. Cells are tracked relative to the data
+//! pointer, which keeps what is known about the cells around it even after a
+//! loop moved it by an unknown amount. Once the tape may wrap around, two
+//! offsets can be the same cell, so an instruction that may access a cell
+//! outside the tape first forgets every cell.
+//!
+//! Loops get iterated until nothing changes anymore, bounds that keep growing
+//! at the start of a loop are widened to the largest possible range so that
+//! always happens.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::instruction::{Instruction, LinkedInstructions};
+
+/// How often the state at the start of a loop may grow before it gets
+/// widened
+const WIDENING_DELAY: u32 = 3;
+
+/// The integers from `lo` to `hi`, both included
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Interval {
+	pub lo: i64,
+	pub hi: i64,
+}
+
+impl Interval {
+	/// Every value a cell can have
+	pub const CELL: Self = Self { lo: 0, hi: 255 };
+	/// Every position, `i64::MIN` and `i64::MAX` standing in for infinity
+	pub const UNBOUNDED: Self = Self { lo: i64::MIN, hi: i64::MAX };
+
+	pub fn constant(value: i64) -> Self { Self { lo: value, hi: value } }
+
+	pub fn contains(self, value: i64) -> bool { self.lo <= value && value <= self.hi }
+
+	/// The only value in the interval, if there is just one
+	pub fn as_constant(self) -> Option<i64> { (self.lo == self.hi).then_some(self.lo) }
+
+	/// The smallest interval containing both
+	pub fn join(self, other: Self) -> Self {
+		Self { lo: self.lo.min(other.lo), hi: self.hi.max(other.hi) }
+	}
+
+	/// Extend the bounds that grew from `self` to `next` to those of `limit`
+	fn widen(self, next: Self, limit: Self) -> Self {
+		Self {
+			lo: if next.lo < self.lo { limit.lo } else { self.lo },
+			hi: if next.hi > self.hi { limit.hi } else { self.hi },
+		}
+	}
+
+	fn add(self, amount: i64) -> Self {
+		Self { lo: self.lo.saturating_add(amount), hi: self.hi.saturating_add(amount) }
+	}
+
+	/// The values of a cell that was assigned an integer from the interval,
+	/// wrapping around at 8 bits
+	fn wrapped(self) -> Self {
+		if self.hi - self.lo >= 255 || self.lo.div_euclid(256) != self.hi.div_euclid(256) {
+			return Self::CELL;
+		}
+
+		let base = self.lo.div_euclid(256) * 256;
+		Self { lo: self.lo - base, hi: self.hi - base }
+	}
+
+	/// The products of every value in each interval, for intervals of cells
+	fn product(factors: &[Self]) -> Self {
+		factors.iter().fold(Self::constant(1), |product, factor| {
+			let corners = [
+				product.lo * factor.lo,
+				product.lo * factor.hi,
+				product.hi * factor.lo,
+				product.hi * factor.hi,
+			];
+			Self { lo: *corners.iter().min().unwrap(), hi: *corners.iter().max().unwrap() }
+		})
+	}
+}
+
+impl fmt::Display for Interval {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match (self.lo, self.hi) {
+			(lo, hi) if lo == hi => write!(f, "{}", lo),
+			(i64::MIN, i64::MAX) => write!(f, "(-inf, inf)"),
+			(i64::MIN, hi) => write!(f, "(-inf, {}]", hi),
+			(lo, i64::MAX) => write!(f, "[{}, inf)", lo),
+			(lo, hi) => write!(f, "[{}, {}]", lo, hi),
+		}
+	}
+}
+
+/// What is known before an instruction that may be reached
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct State {
+	dp:    Interval,
+	/// Cells by their offset from the data pointer
+	cells: BTreeMap<i64, Interval>,
+	/// The value of every cell not in `cells`
+	rest:  Interval,
+}
+
+impl State {
+	fn start() -> Self {
+		Self { dp: Interval::constant(0), cells: BTreeMap::new(), rest: Interval::constant(0) }
+	}
+
+	fn get(&self, offset: i64) -> Interval { self.cells.get(&offset).copied().unwrap_or(self.rest) }
+
+	fn set(&mut self, offset: i64, value: Interval) {
+		if value == self.rest {
+			self.cells.remove(&offset);
+		} else {
+			self.cells.insert(offset, value);
+		}
+	}
+
+	fn move_dp(&mut self, amount: i64) {
+		self.dp = self.dp.add(amount);
+		self.cells = std::mem::take(&mut self.cells)
+			.into_iter()
+			.map(|(offset, value)| (offset - amount, value))
+			.collect();
+	}
+
+	fn join(&self, other: &Self) -> Self {
+		let mut joined = Self {
+			dp:    self.dp.join(other.dp),
+			cells: BTreeMap::new(),
+			rest:  self.rest.join(other.rest),
+		};
+
+		let offsets: BTreeSet<i64> = self.cells.keys().chain(other.cells.keys()).copied().collect();
+		for offset in offsets {
+			joined.set(offset, self.get(offset).join(other.get(offset)));
+		}
+
+		joined
+	}
+
+	/// Widen the bounds that grew from `self` to `next`
+	///
+	/// Cells only known in `next` are folded into `rest`, so the number of
+	/// cells tracked can't keep growing either
+	fn widen(&self, next: &Self) -> Self {
+		let mut rest = next.rest;
+		for (offset, value) in &next.cells {
+			if !self.cells.contains_key(offset) {
+				rest = rest.join(*value);
+			}
+		}
+
+		let mut widened = Self {
+			dp:    self.dp.widen(next.dp, Interval::UNBOUNDED),
+			cells: BTreeMap::new(),
+			rest:  self.rest.widen(rest, Interval::CELL),
+		};
+		for (offset, value) in &self.cells {
+			widened.set(*offset, value.widen(next.get(*offset), Interval::CELL));
+		}
+
+		widened
+	}
+
+	/// Forget the value of every cell
+	fn forget_cells(&mut self) {
+		self.cells.clear();
+		self.rest = Interval::CELL;
+	}
+
+	/// The state if the current cell is zero, `None` if it can't be
+	fn if_zero(mut self) -> Option<Self> {
+		if !self.get(0).contains(0) {
+			return None;
+		}
+		self.set(0, Interval::constant(0));
+
+		Some(self)
+	}
+
+	/// The state if the current cell isn't zero, `None` if it can't be
+	fn if_not_zero(mut self) -> Option<Self> {
+		let value = self.get(0);
+		match value.as_constant() {
+			Some(0) => None,
+			_ => {
+				self.set(0, Interval { lo: value.lo.max(1), hi: value.hi });
+				Some(self)
+			},
+		}
+	}
+}
+
+/// The ranges of the data pointer and cell values before every instruction
+#[derive(Clone, Debug)]
+pub struct Analysis {
+	insts:     Vec<Instruction>,
+	tape_size: usize,
+	/// `None` for instructions that can never be reached
+	states:    Vec<Option<State>>,
+}
+
+impl Analysis {
+	/// Analyse a program running on a tape of `tape_size` cells that start
+	/// at 0
+	pub fn new(insts: &LinkedInstructions, tape_size: usize) -> Self {
+		let insts = insts.0.clone();
+		let mut states: Vec<Option<State>> = vec![None; insts.len()];
+		let mut visits = vec![0; insts.len()];
+
+		// Loops are entered through their `[` both from before the loop and
+		// from the `]` jumping back, which makes those the places to widen
+		let mut loop_starts = vec![false; insts.len()];
+		for inst in &insts {
+			if let Instruction::BranchIfNotZero { destination } = inst {
+				loop_starts[*destination as usize] = true;
+			}
+		}
+
+		let mut worklist = BTreeSet::new();
+		if !insts.is_empty() {
+			states[0] = Some(State::start());
+			worklist.insert(0);
+		}
+
+		while let Some(index) = worklist.pop_first() {
+			// Unwrap is safe as only reachable instructions get queued
+			let state = states[index].clone().unwrap();
+
+			for (next, state) in successors(index, insts[index], state, tape_size) {
+				if next >= insts.len() {
+					continue;
+				}
+
+				let merged = match &states[next] {
+					None => state,
+					Some(old) => {
+						let mut merged = old.join(&state);
+						if loop_starts[next] && visits[next] >= WIDENING_DELAY {
+							merged = old.widen(&merged);
+						}
+						if merged == *old {
+							continue;
+						}
+						merged
+					},
+				};
+
+				states[next] = Some(merged);
+				visits[next] += 1;
+				worklist.insert(next);
+			}
+		}
+
+		Self { insts, tape_size, states }
+	}
+
+	/// Whether the instruction at `index` may run at all
+	pub fn is_reachable(&self, index: usize) -> bool {
+		self.states.get(index).is_some_and(|state| state.is_some())
+	}
+
+	/// The positions of the data pointer before the instruction at `index`,
+	/// on a tape without ends, or `None` if it is never reached
+	pub fn dp(&self, index: usize) -> Option<Interval> { Some(self.state(index)?.dp) }
+
+	/// The values of the cell `offset` cells away from the data pointer before
+	/// the instruction at `index`, or `None` if it is never reached
+	pub fn cell(&self, index: usize, offset: i64) -> Option<Interval> {
+		Some(self.state(index)?.get(offset))
+	}
+
+	/// The values of the cell `offset` cells away from the data pointer after
+	/// the instruction at `index` ran, when the next instruction is the one
+	/// after it, or `None` if that never happens
+	pub fn cell_after(&self, index: usize, offset: i64) -> Option<Interval> {
+		let state = self.state(index)?.clone();
+		successors(index, self.insts[index], state, self.tape_size)
+			.into_iter()
+			.find(|(next, _)| *next == index + 1)
+			.map(|(_, state)| state.get(offset))
+	}
+
+	/// The positions the instruction at `index` may access or move the data
+	/// pointer to, on a tape without ends, or `None` if it does neither or is
+	/// never reached
+	pub fn accessed(&self, index: usize) -> Option<Interval> {
+		accessed(self.dp(index)?, self.insts[index])
+	}
+
+	/// Whether the instruction at `index` stays on the tape without wrapping
+	/// around, which holds for anything that is never reached
+	pub fn in_bounds(&self, index: usize) -> bool {
+		self.accessed(index).is_none_or(|accessed| within(accessed, self.tape_size))
+	}
+
+	/// Whether the instruction at `index` is reached and certainly wraps
+	/// around an end of the tape
+	pub fn out_of_bounds(&self, index: usize) -> bool {
+		let Some(dp) = self.dp(index) else {
+			return false;
+		};
+
+		touched(self.insts[index]).is_some_and(|(lo, hi)| {
+			dp.hi.saturating_add(lo) < 0 || dp.lo.saturating_add(hi) >= self.tape_size as i64
+		})
+	}
+
+	fn state(&self, index: usize) -> Option<&State> { self.states.get(index)?.as_ref() }
+}
+
+/// The offsets from the data pointer an instruction accesses, or for a move
+/// where it ends up, as the smallest and largest one
+fn touched(inst: Instruction) -> Option<(i64, i64)> {
+	let offsets = |a: i16, b: i16| Some((a.min(b) as i64, a.max(b) as i64));
+
+	match inst {
+		Instruction::IncrDp { amount } => offsets(amount, amount),
+		Instruction::Incr { offset, .. } | Instruction::Set { offset, .. } => {
+			offsets(offset, offset)
+		},
+		Instruction::Mul { offset, .. } | Instruction::TriAcc { offset, .. } => offsets(0, offset),
+		Instruction::MulAcc { source, offset, .. } => {
+			offsets(0, source).map(|(lo, hi)| (lo.min(offset as i64), hi.max(offset as i64)))
+		},
+		Instruction::DivMod | Instruction::Compare => offsets(0, 6),
+		Instruction::WriteDecimal => offsets(0, 8),
+		Instruction::EndIf => None,
+		Instruction::BranchIfZero { .. }
+		| Instruction::BranchIfNotZero { .. }
+		| Instruction::If { .. }
+		| Instruction::Read
+		| Instruction::Write => offsets(0, 0),
+	}
+}
+
+/// The positions an instruction accesses or moves the data pointer to, when
+/// the data pointer is in `dp`
+fn accessed(dp: Interval, inst: Instruction) -> Option<Interval> {
+	let (lo, hi) = touched(inst)?;
+
+	Some(Interval { lo: dp.lo.saturating_add(lo), hi: dp.hi.saturating_add(hi) })
+}
+
+/// Whether positions are all on a tape of `tape_size` cells
+fn within(positions: Interval, tape_size: usize) -> bool {
+	positions.lo >= 0 && positions.hi < tape_size as i64
+}
+
+/// The instructions that may run after the one at `index`, with the state
+/// they start in
+fn successors(
+	index: usize,
+	inst: Instruction,
+	mut state: State,
+	tape_size: usize,
+) -> Vec<(usize, State)> {
+	if accessed(state.dp, inst).is_some_and(|accessed| !within(accessed, tape_size)) {
+		state.forget_cells();
+	}
+
+	let branch = |destination: u64, taken: Option<State>, not_taken: Option<State>| {
+		let mut next = vec![];
+		if let Some(state) = taken {
+			next.push((destination as usize, state));
+		}
+		if let Some(state) = not_taken {
+			next.push((index + 1, state));
+		}
+		next
+	};
+
+	match inst {
+		Instruction::BranchIfZero { destination } | Instruction::If { destination } => {
+			return branch(destination, state.clone().if_zero(), state.if_not_zero());
+		},
+		Instruction::BranchIfNotZero { destination } => {
+			return branch(destination, state.clone().if_not_zero(), state.if_zero());
+		},
+		Instruction::IncrDp { amount } => state.move_dp(amount as i64),
+		Instruction::Incr { amount, offset } => {
+			let offset = offset as i64;
+			state.set(offset, state.get(offset).add(amount as i64).wrapped());
+		},
+		Instruction::Set { amount, offset } => {
+			state.set(offset as i64, Interval::constant(amount as u8 as i64));
+		},
+		Instruction::Mul { amount, offset } => {
+			let product = Interval::product(&[state.get(0), Interval::constant(amount as i64)]);
+			accumulate(&mut state, offset as i64, product);
+		},
+		Instruction::MulAcc { amount, source, offset } => {
+			let factors =
+				[state.get(0), state.get(source as i64), Interval::constant(amount as i64)];
+			accumulate(&mut state, offset as i64, Interval::product(&factors));
+		},
+		Instruction::TriAcc { amount, offset } => {
+			let n = state.get(0);
+			let triangle = Interval { lo: n.lo * (n.lo + 1) / 2, hi: n.hi * (n.hi + 1) / 2 };
+			let product = Interval::product(&[triangle, Interval::constant(amount as i64)]);
+			accumulate(&mut state, offset as i64, product);
+		},
+		Instruction::Read => state.set(0, Interval::CELL),
+		Instruction::Write | Instruction::EndIf => (),
+		Instruction::WriteDecimal => {
+			for offset in 1..=8 {
+				state.set(offset, Interval::constant(0));
+			}
+		},
+		Instruction::DivMod => {
+			let n = state.get(0);
+			// A divisor of 0 divides by 256
+			let d = state.get(1);
+			let smallest = if d == Interval::constant(0) { 256 } else { d.lo.max(1) };
+			let largest = if d.contains(0) { 256 } else { d.hi };
+
+			state.set(0, Interval::constant(0));
+			state.set(2, Interval { lo: 0, hi: n.hi.min(largest - 1) });
+			state.set(3, Interval { lo: n.lo / largest, hi: n.hi / smallest });
+			state.set(4, Interval::CELL);
+			state.set(5, Interval::constant(0));
+			state.set(6, Interval::constant(0));
+		},
+		Instruction::Compare => {
+			state.set(0, Interval { lo: 0, hi: 1 });
+			for offset in 2..=6 {
+				state.set(offset, Interval::constant(0));
+			}
+		},
+	}
+
+	vec![(index + 1, state)]
+}
+
+/// Add a range of integers to a cell
+fn accumulate(state: &mut State, offset: i64, amount: Interval) {
+	let value = state.get(offset);
+	state.set(offset, Interval { lo: value.lo + amount.lo, hi: value.hi + amount.hi }.wrapped());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::instruction::UnlinkedInstructions;
+	use crate::interpret::MAX_TAPE_SIZE;
+
+	/// Analyse unoptimised source, where every command is an instruction
+	fn analyse(source: &str, tape_size: usize) -> Analysis {
+		let insts = UnlinkedInstructions::from_text(source.as_bytes()).link().unwrap();
+		Analysis::new(&insts, tape_size)
+	}
+
+	fn interval(lo: i64, hi: i64) -> Interval { Interval { lo, hi } }
+
+	#[test]
+	fn data_pointer() {
+		let analysis = analyse(">>+<<<+", MAX_TAPE_SIZE);
+		assert_eq!(analysis.dp(2), Some(Interval::constant(2)));
+		assert!(analysis.in_bounds(2));
+		assert_eq!(analysis.dp(6), Some(Interval::constant(-1)));
+		assert!(analysis.out_of_bounds(5) && analysis.out_of_bounds(6));
+
+		// The loop may move the pointer any number of cells right
+		let analysis = analyse(",[>,]+", MAX_TAPE_SIZE);
+		assert_eq!(analysis.dp(5), Some(interval(0, i64::MAX)));
+		assert!(!analysis.in_bounds(5) && !analysis.out_of_bounds(5));
+	}
+
+	#[test]
+	fn widening() {
+		// Without widening, every iteration would grow the bounds by one
+		let analysis = analyse("+[>+]", MAX_TAPE_SIZE);
+		assert_eq!(analysis.dp(4), Some(interval(0, i64::MAX)));
+		assert_eq!(analysis.cell(4, 0), Some(Interval::CELL));
+
+		let analysis = analyse("+[+]-", MAX_TAPE_SIZE);
+		assert_eq!(analysis.cell(2, 0), Some(interval(1, 255)));
+		assert_eq!(analysis.cell(4, 0), Some(Interval::constant(0)));
+	}
+
+	#[test]
+	fn branches() {
+		let analysis = analyse(",[-].", MAX_TAPE_SIZE);
+		assert_eq!(analysis.cell(2, 0), Some(interval(1, 255)));
+		assert_eq!(analysis.cell(4, 0), Some(Interval::constant(0)));
+
+		// A loop on a cell that is always 0 is never entered
+		let analysis = analyse("[+]", MAX_TAPE_SIZE);
+		assert!(!analysis.is_reachable(1));
+		assert!(analysis.is_reachable(2));
+	}
+
+	#[test]
+	fn divmod() {
+		let insts = LinkedInstructions(vec![
+			Instruction::Read,
+			Instruction::Set { amount: 10, offset: 1 },
+			Instruction::DivMod,
+			Instruction::Write,
+		]);
+		let analysis = Analysis::new(&insts, MAX_TAPE_SIZE);
+
+		assert_eq!(analysis.cell(3, 0), Some(Interval::constant(0)));
+		assert_eq!(analysis.cell(3, 2), Some(interval(0, 9)));
+		assert_eq!(analysis.cell(3, 3), Some(interval(0, 25)));
+		assert!(analysis.in_bounds(2));
+	}
+
+	#[test]
+	fn tape_wrap() {
+		// The pointer comes back to the decremented cell, which ends up at 0
+		let source = format!("-{}+[--]", ">".repeat(MAX_TAPE_SIZE));
+		let analysis = analyse(&source, MAX_TAPE_SIZE);
+		assert!(analysis.cell(MAX_TAPE_SIZE + 2, 0).unwrap().contains(0));
+
+		assert_eq!(analyse("->>>+", 3).cell(4, 0), Some(Interval::CELL));
+		assert_eq!(analyse("->>>+", 4).cell(4, 0), Some(Interval::constant(0)));
+	}
+}
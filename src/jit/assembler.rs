@@ -0,0 +1,284 @@
+//! Code generation for x86-64
+//!
+//! Only the handful of instructions the compiled programs need are encoded,
+//! with a fixed register assignment:
+//!  - `rbx`: the address of the first cell
+//!  - `rbp`: the data pointer, always inside the tape
+//!  - `r13`: the context passed to the callbacks, a [`Context`](super::Context) when running in the
+//!    JIT
+//!  - `eax`: the index of a cell at an offset from the data pointer
+//!  - `ecx`, `edx`, `esi`, `edi`: values
+//!
+//! All of these but the scratch registers are callee saved, so they survive
+//! the callbacks.
+
+use crate::instruction::{Instruction, Node};
+
+const EAX: u8 = 0;
+const ECX: u8 = 1;
+const EDX: u8 = 2;
+const ESI: u8 = 6;
+const EDI: u8 = 7;
+/// The data pointer
+const EBP: u8 = 5;
+
+/// The addresses of the functions the generated code calls
+#[derive(Clone, Copy)]
+pub(crate) struct Callbacks {
+	pub read:          u64,
+	pub write:         u64,
+	pub write_decimal: u64,
+}
+
+pub(crate) struct Assembler {
+	code:      Vec<u8>,
+	tape_size: u32,
+	callbacks: Callbacks,
+	/// The positions of the rel32 jumps to the error exit
+	exits:     Vec<usize>,
+}
+
+impl Assembler {
+	pub fn new(tape_size: u32, callbacks: Callbacks) -> Self {
+		Self { code: Vec::with_capacity(4096), tape_size, callbacks, exits: vec![] }
+	}
+
+	/// Compile a whole program into a function taking the context and the
+	/// address of the tape, returning 0 or, if a callback failed, its status
+	pub fn compile(mut self, nodes: &[Node]) -> Vec<u8> {
+		// push rbx; push rbp; push r13, which also aligns the stack for calls
+		self.emit(&[0x53, 0x55, 0x41, 0x55]);
+		// mov r13, rdi; mov rbx, rsi; mov rbp, [rdi]
+		self.emit(&[0x49, 0x89, 0xFD, 0x48, 0x89, 0xF3, 0x48, 0x8B, 0x2F]);
+
+		self.block(nodes);
+
+		// xor eax, eax
+		self.emit(&[0x31, 0xC0]);
+		let exit = self.code.len();
+		for jump in std::mem::take(&mut self.exits) {
+			self.patch(jump, exit);
+		}
+		// mov [r13], rbp; pop r13; pop rbp; pop rbx; ret
+		self.emit(&[0x49, 0x89, 0x6D, 0x00, 0x41, 0x5D, 0x5D, 0x5B, 0xC3]);
+
+		self.code
+	}
+
+	fn block(&mut self, nodes: &[Node]) {
+		for node in nodes {
+			match node {
+				Node::Inst(inst) => self.instruction(*inst),
+				Node::Loop(body) => {
+					self.compare_zero();
+					let skip = self.jump(0x84);
+					let start = self.code.len();
+
+					self.block(body);
+
+					self.compare_zero();
+					let repeat = self.jump(0x85);
+					self.patch(repeat, start);
+					self.patch(skip, self.code.len());
+				},
+				Node::If(body) => {
+					self.compare_zero();
+					let skip = self.jump(0x84);
+					self.block(body);
+					self.patch(skip, self.code.len());
+				},
+			}
+		}
+	}
+
+	fn instruction(&mut self, inst: Instruction) {
+		match inst {
+			Instruction::IncrDp { amount } => {
+				let amount = self.wrap_offset(amount);
+				// add ebp, amount
+				self.emit(&[0x81, 0xC5]);
+				self.emit(&amount.to_le_bytes());
+				self.wrap(EBP);
+			},
+			Instruction::Incr { amount, offset } => {
+				let idx = self.cell(offset);
+				// add byte [rbx + idx], amount
+				self.emit(&[0x80, 0x04, sib(idx), amount as u8]);
+			},
+			Instruction::Set { amount, offset } => self.set(offset, amount as u8),
+			Instruction::Mul { amount, offset } => {
+				self.load(EDX, EBP);
+				self.multiply(EDX, amount);
+				let idx = self.cell(offset);
+				self.add(idx, EDX);
+			},
+			Instruction::MulAcc { amount, source, offset } => {
+				self.load(EDX, EBP);
+				let idx = self.cell(source);
+				self.load(EAX, idx);
+				// imul edx, eax
+				self.emit(&[0x0F, 0xAF, 0xD0]);
+				self.multiply(EDX, amount);
+				let idx = self.cell(offset);
+				self.add(idx, EDX);
+			},
+			Instruction::TriAcc { amount, offset } => {
+				self.load(EDX, EBP);
+				// lea ecx, [rdx + 1]; imul edx, ecx; shr edx, 1
+				self.emit(&[0x8D, 0x4A, 0x01, 0x0F, 0xAF, 0xD1, 0xD1, 0xEA]);
+				self.multiply(EDX, amount);
+				let idx = self.cell(offset);
+				self.add(idx, EDX);
+			},
+			Instruction::Read => {
+				// lea rsi, [rbx + rbp]
+				self.emit(&[0x48, 0x8D, 0x34, 0x2B]);
+				self.call(self.callbacks.read);
+			},
+			Instruction::Write => {
+				self.load(ESI, EBP);
+				self.call(self.callbacks.write);
+			},
+			Instruction::WriteDecimal => {
+				self.load(ESI, EBP);
+				self.call(self.callbacks.write_decimal);
+				for offset in 1..=8 {
+					self.set(offset, 0);
+				}
+			},
+			Instruction::DivMod => {
+				let idx = self.cell(1);
+				self.load(ECX, idx);
+				self.load(EAX, EBP);
+				// A divisor of 0 behaves like 256:
+				// test ecx, ecx; mov edx, 256; cmovz ecx, edx
+				self.emit(&[0x85, 0xC9, 0xBA, 0x00, 0x01, 0x00, 0x00, 0x0F, 0x44, 0xCA]);
+				// xor edx, edx; div ecx; mov esi, eax; mov edi, edx; sub ecx, edi
+				self.emit(&[0x31, 0xD2, 0xF7, 0xF1, 0x89, 0xC6, 0x89, 0xD7, 0x29, 0xF9]);
+
+				self.set(0, 0);
+				for (offset, value) in [(2, EDI), (3, ESI), (4, ECX)] {
+					let idx = self.cell(offset);
+					self.store(idx, value);
+				}
+				self.set(5, 0);
+				self.set(6, 0);
+			},
+			Instruction::Compare => {
+				let idx = self.cell(1);
+				self.load(ECX, idx);
+				self.load(EDX, EBP);
+				// cmp dl, cl; setb dl
+				self.emit(&[0x38, 0xCA, 0x0F, 0x92, 0xC2]);
+				self.store(EBP, EDX);
+				for offset in 2..=6 {
+					self.set(offset, 0);
+				}
+			},
+			// The tree structure of the Ast already took care of branches
+			Instruction::BranchIfZero { .. }
+			| Instruction::BranchIfNotZero { .. }
+			| Instruction::If { .. }
+			| Instruction::EndIf => (),
+		}
+	}
+
+	fn emit(&mut self, bytes: &[u8]) { self.code.extend_from_slice(bytes); }
+
+	/// An offset from the data pointer as the equivalent offset in
+	/// `0..tape_size`
+	fn wrap_offset(&self, offset: i16) -> u32 {
+		(offset as i64).rem_euclid(self.tape_size as i64) as u32
+	}
+
+	/// Wrap `reg`, which is at most `2 * tape_size - 2`, around the end of the
+	/// tape
+	fn wrap(&mut self, reg: u8) {
+		if self.tape_size == 65536 {
+			// movzx reg, reg16
+			self.emit(&[0x0F, 0xB7, 0xC0 | reg << 3 | reg]);
+		} else {
+			// cmp reg, tape_size; jb +6; sub reg, tape_size
+			self.emit(&[0x81, 0xF8 | reg]);
+			self.emit(&self.tape_size.to_le_bytes());
+			self.emit(&[0x72, 0x06, 0x81, 0xE8 | reg]);
+			self.emit(&self.tape_size.to_le_bytes());
+		}
+	}
+
+	/// Compute the index of the cell at `offset` from the data pointer,
+	/// returning the register holding it
+	fn cell(&mut self, offset: i16) -> u8 {
+		let offset = self.wrap_offset(offset);
+		if offset == 0 {
+			return EBP;
+		}
+
+		// lea eax, [rbp + offset]
+		self.emit(&[0x8D, 0x85]);
+		self.emit(&offset.to_le_bytes());
+		self.wrap(EAX);
+
+		EAX
+	}
+
+	/// movzx reg, byte [rbx + idx]
+	fn load(&mut self, reg: u8, idx: u8) { self.emit(&[0x0F, 0xB6, 0x04 | reg << 3, sib(idx)]); }
+
+	/// mov byte [rbx + idx], reg8
+	fn store(&mut self, idx: u8, reg: u8) {
+		// Without a REX prefix, the low bytes of esi and edi can't be addressed
+		if reg >= 4 {
+			self.emit(&[0x40]);
+		}
+		self.emit(&[0x88, 0x04 | reg << 3, sib(idx)]);
+	}
+
+	/// add byte [rbx + idx], reg8
+	fn add(&mut self, idx: u8, reg: u8) { self.emit(&[0x00, 0x04 | reg << 3, sib(idx)]); }
+
+	/// mov byte [rbx + idx], value
+	fn set(&mut self, offset: i16, value: u8) {
+		let idx = self.cell(offset);
+		self.emit(&[0xC6, 0x04, sib(idx), value]);
+	}
+
+	/// imul reg, reg, amount
+	fn multiply(&mut self, reg: u8, amount: i8) {
+		self.emit(&[0x69, 0xC0 | reg << 3 | reg]);
+		self.emit(&(amount as i32).to_le_bytes());
+	}
+
+	/// cmp byte [rbx + rbp], 0
+	fn compare_zero(&mut self) { self.emit(&[0x80, 0x3C, sib(EBP), 0x00]); }
+
+	/// Emit a conditional rel32 jump with the given second opcode byte,
+	/// returning its position for [`Assembler::patch`]
+	fn jump(&mut self, condition: u8) -> usize {
+		self.emit(&[0x0F, condition, 0, 0, 0, 0]);
+		self.code.len()
+	}
+
+	/// Point the jump ending at `jump` to `target`
+	fn patch(&mut self, jump: usize, target: usize) {
+		let distance = (target as i64 - jump as i64) as i32;
+		self.code[jump - 4..jump].copy_from_slice(&distance.to_le_bytes());
+	}
+
+	/// Call a callback with the context as its first argument, leaving the
+	/// program if it returns anything but 0
+	fn call(&mut self, address: u64) {
+		// mov rdi, r13; mov rax, address; call rax
+		self.emit(&[0x4C, 0x89, 0xEF, 0x48, 0xB8]);
+		self.emit(&address.to_le_bytes());
+		self.emit(&[0xFF, 0xD0]);
+
+		// test eax, eax; jnz exit
+		self.emit(&[0x85, 0xC0]);
+		let exit = self.jump(0x85);
+		self.exits.push(exit);
+	}
+}
+
+/// The SIB byte addressing `[rbx + idx]`
+fn sib(idx: u8) -> u8 { idx << 3 | 3 }
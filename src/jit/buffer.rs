@@ -0,0 +1,87 @@
+//! Executable memory for the generated code
+
+use std::ffi::c_void;
+
+use crate::error::Error;
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod sys {
+	use std::ffi::c_void;
+
+	pub const PROT_READ: i32 = 1;
+	pub const PROT_WRITE: i32 = 2;
+	pub const PROT_EXEC: i32 = 4;
+	pub const MAP_PRIVATE: i32 = 2;
+	pub const MAP_ANONYMOUS: i32 = 0x20;
+	pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+	extern "C" {
+		pub fn mmap(
+			addr: *mut c_void,
+			len: usize,
+			prot: i32,
+			flags: i32,
+			fd: i32,
+			offset: i64,
+		) -> *mut c_void;
+		pub fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+		pub fn munmap(addr: *mut c_void, len: usize) -> i32;
+	}
+}
+
+/// A private mapping holding a copy of some code, which is executable but
+/// never writable at the same time
+pub(super) struct ExecutableBuffer {
+	ptr: *mut c_void,
+	len: usize,
+}
+
+impl ExecutableBuffer {
+	#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+	pub fn new(code: &[u8]) -> Result<Self, Error> {
+		let len = code.len().max(1);
+
+		// SAFETY: an anonymous mapping doesn't alias any existing memory, and
+		// the copy stays inside the `len` bytes that were just mapped
+		unsafe {
+			let ptr = sys::mmap(
+				std::ptr::null_mut(),
+				len,
+				sys::PROT_READ | sys::PROT_WRITE,
+				sys::MAP_PRIVATE | sys::MAP_ANONYMOUS,
+				-1,
+				0,
+			);
+			if ptr == sys::MAP_FAILED {
+				return Err(std::io::Error::last_os_error().into());
+			}
+
+			// Dropping unmaps the memory again if anything below fails
+			let buffer = Self { ptr, len };
+			std::ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, code.len());
+
+			if sys::mprotect(ptr, len, sys::PROT_READ | sys::PROT_EXEC) != 0 {
+				return Err(std::io::Error::last_os_error().into());
+			}
+
+			Ok(buffer)
+		}
+	}
+
+	#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+	pub fn new(_code: &[u8]) -> Result<Self, Error> { Err(Error::UnsupportedJit) }
+
+	/// The address of the first byte of code
+	pub fn as_ptr(&self) -> *const u8 { self.ptr as *const u8 }
+}
+
+impl Drop for ExecutableBuffer {
+	fn drop(&mut self) {
+		// SAFETY: the mapping was created by `new` and nothing refers to it
+		// once the buffer is gone
+		#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+		unsafe {
+			sys::munmap(self.ptr, self.len);
+		}
+	}
+}
@@ -0,0 +1,158 @@
+//! A backend compiling instructions to x86-64 machine code
+//!
+//! The program is compiled into a single function operating directly on the
+//! tape, with reads and writes going through callbacks into Rust, so they
+//! use the same streams and EOF handling as every other backend. Compiling
+//! works everywhere, but running the code is only supported on x86-64 Linux,
+//! elsewhere [`JitProgram::with_options`] fails with
+//! [`Error::UnsupportedJit`].
+
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use self::assembler::{Assembler, Callbacks};
+use self::buffer::ExecutableBuffer;
+use crate::error::Error;
+use crate::instruction::{Ast, LinkedInstructions};
+use crate::interpret::{Eof, MAX_TAPE_SIZE, Options, read_cell};
+
+pub(crate) mod assembler;
+mod buffer;
+
+/// Everything the callbacks need, a pointer to this is passed to the
+/// generated code
+#[repr(C)]
+struct Context<'a> {
+	/// The data pointer, read and written by the generated code so it has to
+	/// be the first field
+	dp:     u64,
+	reader: &'a mut dyn Read,
+	writer: &'a mut dyn Write,
+	eof:    Eof,
+	/// The error of the callback that stopped the program
+	error:  Option<Error>,
+}
+
+/// The signature of the generated code
+type Entry = unsafe extern "C" fn(context: *mut Context, memory: *mut u8) -> u32;
+
+/// Store the outcome of a callback in the context, turning it into the status
+/// returned to the generated code
+fn status(context: &mut Context, result: Result<(), Error>) -> u32 {
+	match result {
+		Ok(()) => 0,
+		Err(e) => {
+			context.error = Some(e);
+			1
+		},
+	}
+}
+
+extern "C" fn read_callback(context: *mut Context, cell: *mut u8) -> u32 {
+	// SAFETY: the generated code passes on the context it was called with,
+	// and the address of the current cell
+	let (context, cell) = unsafe { (&mut *context, &mut *cell) };
+
+	let result = match context.writer.flush() {
+		Ok(()) => read_cell(context.reader, context.eof, cell),
+		Err(e) => Err(e.into()),
+	};
+
+	status(context, result)
+}
+
+extern "C" fn write_callback(context: *mut Context, value: u32) -> u32 {
+	// SAFETY: the generated code passes on the context it was called with
+	let context = unsafe { &mut *context };
+
+	let result = context.writer.write_all(&[value as u8]).map_err(Error::from);
+	status(context, result)
+}
+
+extern "C" fn write_decimal_callback(context: *mut Context, value: u32) -> u32 {
+	// SAFETY: the generated code passes on the context it was called with
+	let context = unsafe { &mut *context };
+
+	let result = write!(context.writer, "{}", value as u8).map_err(Error::from);
+	status(context, result)
+}
+
+/// A program compiled to machine code, together with the tape it runs on
+pub struct JitProgram {
+	code:   ExecutableBuffer,
+	dp:     u16,
+	memory: Vec<u8>,
+	eof:    Eof,
+}
+
+impl JitProgram {
+	/// Compile linked instructions for the default machine
+	pub fn new(insts: &LinkedInstructions) -> Result<Self, Error> {
+		Self::with_options(insts, Options::default())
+	}
+
+	/// Compile linked instructions for a differently configured machine, the
+	/// tape size gets clamped to `1..=MAX_TAPE_SIZE`
+	///
+	/// The instructions are compiled from their [`Ast`], so jump targets in
+	/// the linked instructions, which might come from untrusted bytecode,
+	/// never make it into the machine code
+	pub fn with_options(insts: &LinkedInstructions, options: Options) -> Result<Self, Error> {
+		let tape_size = options.tape_size.clamp(1, MAX_TAPE_SIZE);
+
+		let ast = Ast::from_linked(insts)?;
+		let callbacks = Callbacks {
+			read:          read_callback as *const () as u64,
+			write:         write_callback as *const () as u64,
+			write_decimal: write_decimal_callback as *const () as u64,
+		};
+		let code = Assembler::new(tape_size as u32, callbacks).compile(&ast.0);
+
+		Ok(Self {
+			code:   ExecutableBuffer::new(&code)?,
+			dp:     0,
+			memory: vec![0; tape_size],
+			eof:    options.eof,
+		})
+	}
+
+	/// Run the program
+	pub fn run(&mut self) -> Result<(), Error> {
+		let mut writer = BufWriter::new(std::io::stdout());
+		let mut reader = BufReader::new(std::io::stdin());
+
+		self.run_with(&mut reader, &mut writer)
+	}
+
+	/// Run the program, reading from and writing to the given streams instead
+	/// of stdin and stdout
+	pub fn run_with<R: Read, W: Write>(
+		&mut self,
+		reader: &mut R,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let mut context =
+			Context { dp: self.dp as u64, reader, writer, eof: self.eof, error: None };
+
+		// SAFETY: the buffer holds a function with the `Entry` signature, the
+		// assembler only emits code that keeps the data pointer inside the
+		// tape, and the tape lives as long as this call
+		let status = unsafe {
+			let entry: Entry = std::mem::transmute(self.code.as_ptr());
+			entry(&mut context, self.memory.as_mut_ptr())
+		};
+
+		self.dp = context.dp as u16;
+		context.writer.flush()?;
+
+		match context.error.take() {
+			Some(e) if status != 0 => Err(e),
+			_ => Ok(()),
+		}
+	}
+
+	/// The position of the data pointer
+	pub fn dp(&self) -> u16 { self.dp }
+
+	/// The contents of the tape
+	pub fn memory(&self) -> &[u8] { &self.memory }
+}
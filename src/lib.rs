@@ -1,11 +1,23 @@
-#![feature(iter_advance_by)]
-
 #[macro_use]
 extern crate bitflags;
 #[macro_use]
 extern crate thiserror;
 
+pub mod closure;
+pub mod emit;
 pub mod error;
+pub mod format;
+pub mod fuzz;
+pub mod idiom;
 pub mod instruction;
 pub mod interpret;
+pub mod interval;
+pub mod jit;
+pub mod lint;
 pub mod optimise;
+pub mod packed;
+pub mod pass;
+pub mod portability;
+pub mod reduce;
+pub mod termination;
+pub mod verify;
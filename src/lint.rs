@@ -0,0 +1,609 @@
+//! Warnings about brainfuck source
+//!
+//! The rules only rely on what the optimiser can prove as well: which cells
+//! are certainly zero, which cells a balanced loop body can change, and the
+//! range of the data pointer found by [`interval::Analysis`]. A warning is
+//! only given if it holds on every run of the program, on a tape of
+//! [`MAX_TAPE_SIZE`] cells.
+//!
+//! [`interval::Analysis`]: crate::interval::Analysis
+//!
+//! A comment containing `allow(rule)` silences a rule on its own line, or, if
+//! the line has no commands, on the next line that does. Several rules are
+//! separated by spaces, as in `allow(dead_loop tape_wrap)`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::error::Error;
+use crate::instruction::{Instruction, UnlinkedInstructions};
+use crate::interpret::MAX_TAPE_SIZE;
+use crate::interval::Analysis;
+
+const TAPE: i64 = MAX_TAPE_SIZE as i64;
+
+/// What a warning is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rule {
+	/// Adjacent commands that undo each other, like `+-` and `<>`
+	CancellingPair,
+	/// A loop whose cell is always zero when it is reached
+	DeadLoop,
+	/// A loop that is entered and never changes its cell
+	InfiniteLoop,
+	/// A cell left of the first one or past the end of the largest tape is
+	/// accessed
+	TapeWrap,
+	/// A cell known to be 0 is decremented, or one known to be 255 incremented
+	CellWrap,
+	/// A loop that never changes its cell, so it either never runs or never
+	/// stops
+	UnchangedCondition,
+}
+
+impl Rule {
+	pub const ALL: [Self; 6] = [
+		Self::CancellingPair,
+		Self::DeadLoop,
+		Self::InfiniteLoop,
+		Self::TapeWrap,
+		Self::CellWrap,
+		Self::UnchangedCondition,
+	];
+
+	/// The name used to silence the rule, which never contains commands
+	pub fn id(self) -> &'static str {
+		match self {
+			Self::CancellingPair => "cancelling_pair",
+			Self::DeadLoop => "dead_loop",
+			Self::InfiniteLoop => "infinite_loop",
+			Self::TapeWrap => "tape_wrap",
+			Self::CellWrap => "cell_wrap",
+			Self::UnchangedCondition => "unchanged_condition",
+		}
+	}
+
+	pub fn from_id(id: &str) -> Option<Self> { Self::ALL.into_iter().find(|rule| rule.id() == id) }
+}
+
+/// A place in the source, both starting at 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+	pub line:   usize,
+	/// The byte in the line
+	pub column: usize,
+}
+
+/// The end of the tape a [`Rule::TapeWrap`] warning is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TapeEnd {
+	/// Left of the first cell
+	Start,
+	/// Past the last cell of the largest tape
+	End,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+	pub rule:     Rule,
+	pub position: Position,
+	pub message:  String,
+	/// Which end of the tape the data pointer is past, for
+	/// [`Rule::TapeWrap`] only
+	pub tape_end: Option<TapeEnd>,
+}
+
+impl fmt::Display for Warning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{}:{}: {} [{}]",
+			self.position.line,
+			self.position.column,
+			self.message,
+			self.rule.id()
+		)
+	}
+}
+
+/// Check brainfuck source, returning the warnings that weren't silenced in
+/// the order they appear in
+///
+/// Bracket positions in errors count commands only, like when linking
+pub fn lint(bytes: &[u8]) -> Result<Vec<Warning>, Error> {
+	let lines = Lines::new(bytes);
+	let mut warnings = lint_all(bytes)?;
+	warnings.retain(|warning| !lines.allows(warning.position.line, warning.rule));
+
+	Ok(warnings)
+}
+
+/// Check brainfuck source like [`lint`], including silenced warnings
+pub fn lint_all(bytes: &[u8]) -> Result<Vec<Warning>, Error> {
+	let nodes = parse(bytes)?;
+
+	let mut linter = Linter { warnings: vec![] };
+	linter.block(&nodes, &mut State::start());
+	linter.tape_wrap(bytes)?;
+
+	let lines = Lines::new(bytes);
+	let mut warnings: Vec<Warning> = linter
+		.warnings
+		.into_iter()
+		.map(|(at, rule, message, tape_end)| {
+			Warning { rule, position: lines.position(at), message, tape_end }
+		})
+		.collect();
+	warnings.sort_by_key(|warning| warning.position);
+
+	Ok(warnings)
+}
+
+/// A command or loop, with the byte offsets of its brackets
+enum Node {
+	Command { command: u8, at: usize },
+	Loop { open: usize, body: Vec<Node> },
+}
+
+fn parse(bytes: &[u8]) -> Result<Vec<Node>, Error> {
+	let mut blocks = vec![];
+	let mut current = vec![];
+	let mut open = vec![];
+	let mut position = 0;
+
+	for (at, byte) in bytes.iter().enumerate() {
+		match byte {
+			b'+' | b'-' | b'<' | b'>' | b'.' | b',' => {
+				current.push(Node::Command { command: *byte, at });
+			},
+			b'[' => {
+				blocks.push(std::mem::take(&mut current));
+				open.push((position, at));
+			},
+			b']' => {
+				let (Some(outer), Some((_, open))) = (blocks.pop(), open.pop()) else {
+					return Err(Error::MissingOpeningBracket(position));
+				};
+				let body = std::mem::replace(&mut current, outer);
+				current.push(Node::Loop { open, body });
+			},
+			_ => continue,
+		}
+
+		position += 1;
+	}
+
+	if let Some((position, _)) = open.pop() {
+		return Err(Error::MissingClosingBracket(position));
+	}
+
+	Ok(current)
+}
+
+/// What is known about the tape at some point in the program
+#[derive(Clone)]
+struct State {
+	/// The position of the data pointer, relative to where it was when the
+	/// pointer last became unknown
+	cursor:   i64,
+	/// Whether `cursor` is the actual data pointer, which holds as long as
+	/// every loop so far was balanced
+	anchored: bool,
+	/// Known and unknown cells by cursor position, `None` if unknown
+	///
+	/// Known cells are always on the tape if the state is anchored, and less
+	/// than half the tape away from position 0 otherwise, so no two of them
+	/// are the same cell
+	values:   HashMap<i64, Option<u8>>,
+	/// Whether cells not in `values` are zero
+	zeroed:   bool,
+}
+
+impl State {
+	fn start() -> Self { Self { cursor: 0, anchored: true, values: HashMap::new(), zeroed: true } }
+
+	fn value(&self, position: i64) -> Option<u8> {
+		match self.values.get(&position) {
+			Some(value) => *value,
+			None if self.zeroed => Some(0),
+			None => None,
+		}
+	}
+
+	/// Forget the cells a loop body may change, at offsets from the cursor
+	fn forget(&mut self, changed: &HashSet<i64>) {
+		for offset in changed {
+			let position = self.cursor + offset;
+			if self.anchored {
+				self.values.insert(position.rem_euclid(TAPE), None);
+			} else if position.abs() < TAPE / 2 {
+				self.values.insert(position, None);
+			} else {
+				// The body may reach a known cell by wrapping around the tape
+				self.forget_all();
+			}
+		}
+	}
+
+	/// Forget every cell once the cursor may have left the tape, as it can
+	/// reach cells known under another position by wrapping around
+	fn check_bounds(&mut self) {
+		let inside = if self.anchored {
+			(0..TAPE).contains(&self.cursor)
+		} else {
+			self.cursor.abs() < TAPE / 2
+		};
+		if !inside {
+			self.forget_all();
+			if !self.anchored {
+				self.cursor = 0;
+			}
+		}
+	}
+
+	fn forget_all(&mut self) {
+		self.values.clear();
+		self.zeroed = false;
+	}
+
+	/// Forget everything but the pointer moving by an unknown amount
+	fn lose_track(&mut self) {
+		self.anchored = false;
+		self.cursor = 0;
+		self.forget_all();
+	}
+}
+
+struct Linter {
+	/// The byte offset, rule, message, and end of the tape of every warning
+	warnings: Vec<(usize, Rule, String, Option<TapeEnd>)>,
+}
+
+impl Linter {
+	fn warn(&mut self, at: usize, rule: Rule, message: String) {
+		self.warnings.push((at, rule, message, None));
+	}
+
+	fn block(&mut self, nodes: &[Node], state: &mut State) {
+		self.cancelling_pairs(nodes);
+
+		for node in nodes {
+			match node {
+				Node::Command { command, at } => self.command(*command, *at, state),
+				Node::Loop { open, body } => self.looped(*open, body, state),
+			}
+		}
+	}
+
+	fn cancelling_pairs(&mut self, nodes: &[Node]) {
+		let mut previous: Option<(u8, usize)> = None;
+
+		for node in nodes {
+			let Node::Command { command, at } = node else {
+				previous = None;
+				continue;
+			};
+
+			match (previous, command) {
+				(Some((first @ b'+', at)), b'-')
+				| (Some((first @ b'-', at)), b'+')
+				| (Some((first @ b'<', at)), b'>')
+				| (Some((first @ b'>', at)), b'<') => {
+					let message = format!("`{}{}` cancels out", first as char, *command as char);
+					self.warn(at, Rule::CancellingPair, message);
+					previous = None;
+				},
+				_ => previous = Some((*command, *at)),
+			}
+		}
+	}
+
+	fn command(&mut self, command: u8, at: usize, state: &mut State) {
+		match command {
+			b'<' => {
+				state.cursor -= 1;
+				return;
+			},
+			b'>' => {
+				state.cursor += 1;
+				return;
+			},
+			_ => (),
+		}
+
+		state.check_bounds();
+		let previous = state.value(state.cursor);
+		if let (b'+', Some(255)) | (b'-', Some(0)) = (command, previous) {
+			let message = format!(
+				"the cell is {} here, this only works if cells wrap around at 8 bits",
+				previous.unwrap()
+			);
+			self.warn(at, Rule::CellWrap, message);
+		}
+
+		let value = match command {
+			b'+' => previous.map(|v| v.wrapping_add(1)),
+			b'-' => previous.map(|v| v.wrapping_sub(1)),
+			b',' => None,
+			_ => return,
+		};
+		state.values.insert(state.cursor, value);
+	}
+
+	fn looped(&mut self, open: usize, body: &[Node], state: &mut State) {
+		state.check_bounds();
+		let condition = state.value(state.cursor);
+
+		if condition == Some(0) {
+			let message = "this loop never runs, its cell is always 0 here".to_owned();
+			self.warn(open, Rule::DeadLoop, message);
+
+			// The body is unreachable, but still worth checking on its own
+			let mut unknown = State { anchored: false, zeroed: false, ..State::start() };
+			self.block(body, &mut unknown);
+			return;
+		}
+
+		// A body moving a whole tape away changes its own cell as well
+		let changed = changed_cells(body, 0);
+		let keeps_cell = |changed: &HashSet<i64>| !changed.iter().any(|o| o.rem_euclid(TAPE) == 0);
+		match (&changed, condition) {
+			(Some(changed), Some(value)) if keeps_cell(changed) => {
+				let message =
+					format!("this loop never stops, its cell is {} and never changes", value);
+				self.warn(open, Rule::InfiniteLoop, message);
+			},
+			(Some(changed), None) if keeps_cell(changed) => {
+				let message = "this loop never changes its cell, so it either never runs or never \
+				               stops"
+					.to_owned();
+				self.warn(open, Rule::UnchangedCondition, message);
+			},
+			_ => (),
+		}
+
+		match &changed {
+			Some(changed) => {
+				state.forget(changed);
+				let mut inner = state.clone();
+				inner.values.insert(inner.cursor, None);
+				self.block(body, &mut inner);
+			},
+			None => {
+				state.lose_track();
+				self.block(body, &mut state.clone());
+			},
+		}
+
+		state.values.insert(state.cursor, Some(0));
+	}
+
+	/// Warn about the first access after every move that certainly ends up
+	/// outside the tape
+	///
+	/// Unoptimised instructions are the commands of the source, in order
+	fn tape_wrap(&mut self, bytes: &[u8]) -> Result<(), Error> {
+		let insts = UnlinkedInstructions::from_text(bytes).link()?;
+		let analysis = Analysis::new(&insts, MAX_TAPE_SIZE);
+		let mut commands = bytes.iter().enumerate().filter(|(_, b)| b"+-<>[].,".contains(b));
+
+		for (index, inst) in insts.0.iter().enumerate() {
+			// Unwrap is safe as there is a command for every instruction
+			let (at, _) = commands.next().unwrap();
+			let moved = index > 0 && matches!(insts.0[index - 1], Instruction::IncrDp { .. });
+			if matches!(inst, Instruction::IncrDp { .. })
+				|| !moved || !analysis.out_of_bounds(index)
+			{
+				continue;
+			}
+
+			// Unwrap is safe as out of bounds instructions are reachable
+			let dp = analysis.dp(index).unwrap();
+			let (message, end) = if dp.hi < 0 {
+				let message = format!(
+					"the data pointer is at most {} here, left of the first cell, this only works \
+					 if the tape wraps around",
+					dp.hi
+				);
+				(message, TapeEnd::Start)
+			} else {
+				let message = format!(
+					"the data pointer is at least {} here, past the end of the largest tape, this \
+					 only works if the tape wraps around",
+					dp.lo
+				);
+				(message, TapeEnd::End)
+			};
+			self.warnings.push((at, Rule::TapeWrap, message, Some(end)));
+		}
+
+		Ok(())
+	}
+}
+
+/// The offsets of the cells a loop body may change, or `None` if the body
+/// isn't balanced
+///
+/// A nested loop counts as changing its own cell, as that is zero afterwards
+fn changed_cells(body: &[Node], start: i64) -> Option<HashSet<i64>> {
+	let mut changed = HashSet::new();
+	let mut offset = start;
+
+	for node in body {
+		match node {
+			Node::Command { command: b'<', .. } => offset -= 1,
+			Node::Command { command: b'>', .. } => offset += 1,
+			Node::Command { command: b'+' | b'-' | b',', .. } => {
+				changed.insert(offset);
+			},
+			Node::Command { .. } => (),
+			Node::Loop { body, .. } => {
+				changed.extend(changed_cells(body, offset)?);
+				changed.insert(offset);
+			},
+		}
+	}
+
+	(offset == start).then_some(changed)
+}
+
+/// The line structure of the source, and the rules silenced on each line
+pub struct Lines {
+	/// The byte offset every line starts at
+	starts:  Vec<usize>,
+	/// The rules silenced on each line, starting at 1
+	allowed: HashMap<usize, Vec<Rule>>,
+}
+
+impl Lines {
+	pub fn new(bytes: &[u8]) -> Self {
+		let mut starts = vec![0];
+		let mut allowed: HashMap<usize, Vec<Rule>> = HashMap::new();
+		// Rules from lines without commands, waiting for the next line with some
+		let mut pending = vec![];
+
+		for (index, line) in bytes.split(|b| *b == b'\n').enumerate() {
+			let start = starts[index];
+			starts.push(start + line.len() + 1);
+
+			pending.extend(allowed_rules(line));
+			if line.iter().any(|b| b"+-<>[].,".contains(b)) {
+				allowed.insert(index + 1, std::mem::take(&mut pending));
+			}
+		}
+		starts.pop();
+
+		Self { starts, allowed }
+	}
+
+	/// The position of the byte at offset `at` in the source
+	pub fn position(&self, at: usize) -> Position {
+		let line = self.starts.partition_point(|start| *start <= at);
+		Position { line, column: at - self.starts[line - 1] + 1 }
+	}
+
+	fn allows(&self, line: usize, rule: Rule) -> bool {
+		self.allowed.get(&line).is_some_and(|rules| rules.contains(&rule))
+	}
+}
+
+/// The rules named in every `allow(...)` on a line
+fn allowed_rules(line: &[u8]) -> Vec<Rule> {
+	const PREFIX: &[u8] = b"allow(";
+
+	let mut rules = vec![];
+	let mut rest = line;
+	while let Some(start) = rest.windows(PREFIX.len()).position(|w| w == PREFIX) {
+		rest = &rest[start + PREFIX.len()..];
+		let end = rest.iter().position(|b| *b == b')').unwrap_or(rest.len());
+
+		let ids = String::from_utf8_lossy(&rest[..end]);
+		rules.extend(ids.split_ascii_whitespace().filter_map(Rule::from_id));
+		rest = &rest[end..];
+	}
+
+	rules
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The rules of the warnings about some source, in order
+	fn rules(source: &str) -> Vec<Rule> {
+		lint(source.as_bytes()).unwrap().into_iter().map(|warning| warning.rule).collect()
+	}
+
+	#[test]
+	fn cancelling_pair() {
+		assert_eq!(rules("+-"), [Rule::CancellingPair]);
+		assert_eq!(rules(",<>."), [Rule::CancellingPair]);
+		// Pairs don't overlap, and a loop in between separates them
+		assert_eq!(rules(",+-+."), [Rule::CancellingPair]);
+		assert_eq!(rules(",+[-]-."), [Rule::CellWrap]);
+	}
+
+	#[test]
+	fn dead_loop() {
+		assert_eq!(rules("[-]"), [Rule::DeadLoop]);
+		assert_eq!(rules("+[-][-]"), [Rule::DeadLoop]);
+		assert_eq!(rules("+>[<-]"), [Rule::DeadLoop]);
+		assert_eq!(rules(",[-]"), []);
+		// After an unbalanced loop the pointer and the cells are unknown
+		assert_eq!(rules("+[>]>[-]"), []);
+	}
+
+	#[test]
+	fn infinite_loop() {
+		assert_eq!(rules("+[>+<]"), [Rule::InfiniteLoop]);
+		assert_eq!(rules("+[.]"), [Rule::InfiniteLoop]);
+		assert_eq!(rules("+[>+<-]"), []);
+	}
+
+	#[test]
+	fn tape_wrap() {
+		assert_eq!(rules("<+"), [Rule::TapeWrap]);
+		assert_eq!(rules("<+>+<."), [Rule::TapeWrap, Rule::TapeWrap]);
+		assert_eq!(rules(">+<+"), []);
+		assert_eq!(rules(&format!("{}+", ">".repeat(MAX_TAPE_SIZE))), [Rule::TapeWrap]);
+		assert_eq!(rules(&format!("{}+", ">".repeat(MAX_TAPE_SIZE - 1))), []);
+		// Only where the pointer certainly is outside the tape
+		assert_eq!(rules(",[>]<+"), []);
+		assert_eq!(rules(",[<]<+"), [Rule::TapeWrap]);
+	}
+
+	#[test]
+	fn cell_wrap() {
+		assert_eq!(rules("-"), [Rule::CellWrap]);
+		assert_eq!(rules(&"+".repeat(256)), [Rule::CellWrap]);
+		assert_eq!(rules("+[-]-"), [Rule::CellWrap]);
+		assert_eq!(rules("+-"), [Rule::CancellingPair]);
+		assert_eq!(rules(",-"), []);
+	}
+
+	#[test]
+	fn unchanged_condition() {
+		assert_eq!(rules(",[>+<]"), [Rule::UnchangedCondition]);
+		assert_eq!(rules(",[.]"), [Rule::UnchangedCondition]);
+		assert_eq!(rules(",[>+<-]"), []);
+		// A nested loop leaves its cell at 0
+		assert_eq!(rules(",[[-]]"), []);
+	}
+
+	#[test]
+	fn wrapping_around_the_tape() {
+		let right = ">".repeat(MAX_TAPE_SIZE);
+		let left = "<".repeat(MAX_TAPE_SIZE);
+
+		// The pointer ends up on the decremented cell again
+		assert_eq!(rules(&format!("-{}[-]", right)), [Rule::CellWrap, Rule::TapeWrap]);
+		assert_eq!(rules(&format!(">-{}[-]", left)), [Rule::CellWrap, Rule::TapeWrap]);
+		// The loop changes its own cell a whole tape away
+		assert_eq!(rules(&format!(",[{}-{}]", right, left)), [Rule::TapeWrap]);
+		assert_eq!(rules(&format!("+[{}-{}]", left, right)), [Rule::TapeWrap]);
+		// After an unbalanced loop, going half the tape either way
+		let half = ">".repeat(MAX_TAPE_SIZE / 2);
+		assert_eq!(rules(&format!(",[>]-{}{}[-]", half, half)), [Rule::CellWrap, Rule::TapeWrap]);
+	}
+
+	#[test]
+	fn allow() {
+		assert_eq!(rules("+- allow(cancelling_pair)"), []);
+		assert_eq!(rules("+- allow(dead_loop)"), [Rule::CancellingPair]);
+		// A line without commands silences the next one with commands
+		assert_eq!(rules("allow(cancelling_pair)\n\nnothing here\n+-\n+-"), [Rule::CancellingPair]);
+		assert_eq!(rules("allow(dead_loop cancelling_pair)\n+-[-]"), []);
+		assert_eq!(rules("allow(dead_loop) allow(cancelling_pair)\n+-[-]"), []);
+		// Unknown rules and unclosed parentheses are fine
+		assert_eq!(rules("allow(typo dead_loop)\n[-]"), []);
+		assert_eq!(rules("+- allow(cancelling_pair"), []);
+	}
+
+	#[test]
+	fn positions() {
+		let warnings = lint(b"comment\n +-\n\t[-]").unwrap();
+		let positions: Vec<_> =
+			warnings.iter().map(|w| (w.position.line, w.position.column)).collect();
+		assert_eq!(positions, [(2, 2), (3, 2)]);
+	}
+}
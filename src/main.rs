@@ -1,22 +1,124 @@
 //! TODO: make it better
 
-#![feature(iterator_try_collect)]
-
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use bf_rust::closure::ClosureProgram;
 use bf_rust::error::Error;
+use bf_rust::format::{Source, Style};
+use bf_rust::fuzz::{Fuzzer, GeneratorConfig, check_case};
 use bf_rust::instruction::{LinkedInstructions, UnlinkedInstructions};
-use bf_rust::interpret::Interpreter;
-use bf_rust::optimise::Optimisations;
-use clap::{Arg, ArgAction, Command};
+use bf_rust::interpret::{Eof, Interpreter, MAX_TAPE_SIZE, Options};
+use bf_rust::jit::JitProgram;
+use bf_rust::lint::{Lines, Position, lint};
+use bf_rust::optimise::{OPTIMISATION_NAMES, Optimisations};
+use bf_rust::packed::{PackedInterpreter, PackedProgram};
+use bf_rust::pass::{IrDump, OPT_LEVELS, OptStats, PassManager, PassObserver};
+use bf_rust::portability::analyse;
+use bf_rust::reduce::reduce;
+use bf_rust::termination::{Termination, classify};
+use bf_rust::verify::{DEFAULT_MAX_STEPS, verify};
+use clap::{Arg, ArgAction, ArgGroup, Command};
+
+/// What to do with the input file
+enum Task {
+	/// Compile and run, or emit, the file
+	Run,
+	/// Compile the file into an executable
+	Build,
+	/// Compare the file's behaviour with and without optimisations
+	Verify { stdin_path: Option<PathBuf>, max_steps: u64 },
+	/// Shrink the file while it behaves differently with optimisations
+	Reduce { stdin_path: Option<PathBuf>, max_steps: u64, reduce_input: bool },
+	/// Reformat the file in place, or only check if it is formatted
+	Format { style: Style, check: bool },
+	/// Warn about code that is likely a mistake
+	Lint,
+	/// Report the implementation-defined behaviour the file relies on
+	Portability { stdin_path: Option<PathBuf>, max_steps: u64 },
+	/// Classify every loop as terminating, non-terminating, or unknown
+	Termination,
+	/// Check randomly generated programs against every optimisation
+	Fuzz {
+		seed:         u64,
+		iterations:   u64,
+		max_steps:    u64,
+		failures_dir: PathBuf,
+		generator:    GeneratorConfig,
+	},
+}
 
 struct Config {
-	input_path:    PathBuf,
-	bytecode_path: Option<PathBuf>,
-	symcode_path:  Option<PathBuf>,
-	optimisations: Optimisations,
+	task:       Task,
+	input_path: PathBuf,
+	/// What to write instead of running the file, and where to
+	emit:       Option<(Emit, PathBuf)>,
+	passes:     PassManager,
+	opt_stats:  bool,
+	dump_after: Option<String>,
+	backend:    Backend,
+	options:    Options,
+}
+
+/// An output format written instead of running the file
+#[derive(Clone, Copy)]
+enum Emit {
+	Bytecode,
+	Symcode,
+	C,
+	Llvm,
+	Wasm,
+	Wat,
+	Rust,
+	/// Plain brainfuck, see [`LinkedInstructions::to_brainfuck`]
+	Brainfuck,
+	/// A static executable, see [`LinkedInstructions::to_elf`]
+	Executable,
+}
+
+impl Emit {
+	/// The flags selecting each format
+	const FLAGS: [(&'static str, Self); 8] = [
+		("emit_bytecode", Self::Bytecode),
+		("emit_symcode", Self::Symcode),
+		("emit_c", Self::C),
+		("emit_llvm", Self::Llvm),
+		("emit_wasm", Self::Wasm),
+		("emit_wat", Self::Wat),
+		("emit_rust", Self::Rust),
+		("emit_brainfuck", Self::Brainfuck),
+	];
+
+	/// The extension of the output file, unless one was given
+	fn extension(self) -> &'static str {
+		match self {
+			Self::Bytecode => "bfc",
+			Self::Symcode => "bfs",
+			Self::C => "c",
+			Self::Llvm => "ll",
+			Self::Wasm => "wasm",
+			Self::Wat => "wat",
+			Self::Rust => "rs",
+			// Keeps the output from overwriting the input
+			Self::Brainfuck => "min.bf",
+			Self::Executable => "",
+		}
+	}
+}
+
+/// How to run the instructions
+#[derive(Clone, Copy)]
+enum Backend {
+	/// The reference interpreter, see [`Interpreter`]
+	Interpreter,
+	/// The packed instruction format, see [`PackedProgram`]
+	Packed,
+	/// Instructions compiled into closures, see [`ClosureProgram`]
+	Closure,
+	/// Native code, see [`JitProgram`]
+	Jit,
 }
 
 /// Read all command line flags into a neat little struct
@@ -31,165 +133,928 @@ fn make_config() -> Result<Config, Error> {
 				.help("If set, emit bytecode instead of running the file")
 				.short('b')
 				.long("emit-bytecode")
-				.action(ArgAction::SetTrue)
-				.conflicts_with("emit_symcode"),
+				.action(ArgAction::SetTrue),
 		)
 		.arg(
 			Arg::new("emit_symcode")
 				.help("If set, emit symbolic code instead of running the file")
 				.short('s')
 				.long("emit-symcode")
-				.action(ArgAction::SetTrue)
-				.conflicts_with("emit_bytecode"),
+				.action(ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("emit_c")
+				.help("If set, emit a standalone C program instead of running the file")
+				.long("emit-c")
+				.action(ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("emit_llvm")
+				.help("If set, emit an LLVM IR module instead of running the file")
+				.long("emit-llvm")
+				.action(ArgAction::SetTrue),
 		)
+		.arg(
+			Arg::new("emit_wasm")
+				.help("If set, emit a WebAssembly module instead of running the file")
+				.long("emit-wasm")
+				.action(ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("emit_wat")
+				.help(
+					"If set, emit a WebAssembly module in the text format instead of running the \
+					 file",
+				)
+				.long("emit-wat")
+				.action(ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("emit_rust")
+				.help(
+					"If set, emit a Rust function running the program instead of running the file",
+				)
+				.long("emit-rust")
+				.action(ArgAction::SetTrue),
+		)
+		.arg(
+			Arg::new("emit_brainfuck")
+				.help("If set, emit plain brainfuck instead of running the file")
+				.long("emit-bf")
+				.action(ArgAction::SetTrue),
+		)
+		.group(ArgGroup::new("emit").args(Emit::FLAGS.map(|(flag, _)| flag)))
 		.arg(
 			Arg::new("output_file")
-				.help("The file to write the bytecode/symbolic code to")
+				.help("The file to write the emitted code to")
 				.short('p')
 				.long("output")
 				.action(ArgAction::Set),
 		)
+		.arg(optimisation_arg().short('o'))
+		.args(pipeline_args())
+		.arg(
+			Arg::new("list_passes")
+				.help("List all optimisation passes and levels, then exit")
+				.long("list-passes")
+				.action(ArgAction::SetTrue),
+		)
 		.arg(
-			Arg::new("optimisation")
-				.help("Specify what optimisations to apply")
-				.short('o')
-				.long("optimise")
+			Arg::new("backend")
+				.help("How to run the program")
+				.long("backend")
 				.action(ArgAction::Set)
-				.value_delimiter(',')
-				.value_parser([
-					"all",
-					"combine-clears",
-					"group-instructions",
-					"reorder-instructions",
-					"combine-multiply-loops",
-				]),
-		)
-		.arg(Arg::new("file").help("The brainfuck file to run").index(1).required(true))
+				.value_parser(["interpreter", "packed", "closure", "jit"])
+				.default_value("interpreter"),
+		)
+		.args(machine_args())
+		.args(report_args())
+		.arg(
+			Arg::new("file")
+				.help("The brainfuck file to run")
+				.index(1)
+				.required_unless_present("list_passes"),
+		)
+		.args_conflicts_with_subcommands(true)
+		.subcommand(
+			Command::new("verify")
+				.about(
+					"Run a program with and without optimisations, and report the first \
+					 difference in output or tape (defaults to -O3)",
+				)
+				.args(differential_args())
+				.args(pipeline_args()),
+		)
+		.subcommand(
+			Command::new("reduce")
+				.about(
+					"Shrink a program that behaves differently with and without optimisations, \
+					 writing the result to <file>.min.bf (defaults to -O3)",
+				)
+				.args(differential_args())
+				.args(pipeline_args())
+				.arg(
+					Arg::new("reduce_input")
+						.help("Also shrink the input, writing it to <file>.min.in")
+						.long("reduce-input")
+						.action(ArgAction::SetTrue),
+				),
+		)
+		.subcommand(
+			Command::new("build")
+				.about(
+					"Compile a program into a static x86-64 Linux executable, written to the file \
+					 without its extension unless given (defaults to -O3)",
+				)
+				.arg(optimisation_arg())
+				.args(pipeline_args())
+				.args(machine_args())
+				.args(report_args())
+				.arg(
+					Arg::new("output_file")
+						.help("The file to write the executable to")
+						.short('o')
+						.long("output")
+						.action(ArgAction::Set),
+				)
+				.arg(
+					Arg::new("file")
+						.help("The brainfuck or bytecode file to compile")
+						.index(1)
+						.required(true),
+				),
+		)
+		.subcommand(
+			Command::new("fmt")
+				.about("Reformat a brainfuck file in place, keeping its comments")
+				.arg(
+					Arg::new("check")
+						.help(
+							"Only check whether the file is formatted, exiting with a non-zero \
+							 status if it isn't",
+						)
+						.long("check")
+						.action(ArgAction::SetTrue),
+				)
+				.arg(
+					Arg::new("width")
+						.help("The column to wrap long lines of commands at, tabs count as 4")
+						.long("width")
+						.action(ArgAction::Set)
+						.value_parser(clap::value_parser!(usize))
+						.default_value("80"),
+				)
+				.arg(
+					Arg::new("normalise")
+						.help("Remove adjacent commands that cancel out, like +- and <>")
+						.long("normalise")
+						.action(ArgAction::SetTrue),
+				)
+				.arg(Arg::new("file").help("The brainfuck file to format").index(1).required(true)),
+		)
+		.subcommand(
+			Command::new("lint")
+				.about(
+					"Warn about code that is likely a mistake, exiting with a non-zero status if \
+					 there is any. A comment containing allow(<rule>) silences a rule on its \
+					 line, or on the next line with commands",
+				)
+				.arg(Arg::new("file").help("The brainfuck file to check").index(1).required(true)),
+		)
+		.subcommand(
+			Command::new("portability")
+				.about(
+					"Report whether a program relies on 8-bit cells, moving left of cell 0, more \
+					 than 30000 cells, or a particular EOF value, exiting with a non-zero status \
+					 if it does",
+				)
+				.arg(
+					Arg::new("input")
+						.help("A file to use as the program's input, empty if not given")
+						.short('i')
+						.long("input")
+						.action(ArgAction::Set),
+				)
+				.arg(
+					Arg::new("max_steps")
+						.help("How many instructions the run may execute, 10000000 by default")
+						.long("max-steps")
+						.action(ArgAction::Set)
+						.value_parser(clap::value_parser!(u64)),
+				)
+				.arg(Arg::new("file").help("The brainfuck file to check").index(1).required(true)),
+		)
+		.subcommand(
+			Command::new("termination")
+				.about(
+					"Report whether every loop stops, exiting with a non-zero status if a loop \
+					 that can be entered never does",
+				)
+				.arg(Arg::new("file").help("The brainfuck file to check").index(1).required(true)),
+		)
+		.subcommand(
+			Command::new("fuzz")
+				.about(
+					"Check random programs against every combination of optimisations, the \
+					 bytecode format, and every backend",
+				)
+				.arg(
+					Arg::new("seed")
+						.help("The seed of the random generator, based on the time if not given")
+						.long("seed")
+						.action(ArgAction::Set)
+						.value_parser(clap::value_parser!(u64)),
+				)
+				.arg(
+					Arg::new("iterations")
+						.help("How many programs to generate")
+						.short('n')
+						.long("iterations")
+						.action(ArgAction::Set)
+						.value_parser(clap::value_parser!(u64))
+						.default_value("1000"),
+				)
+				.arg(
+					Arg::new("max_steps")
+						.help("How many instructions each run may execute")
+						.long("max-steps")
+						.action(ArgAction::Set)
+						.value_parser(clap::value_parser!(u64))
+						.default_value("100000"),
+				)
+				.arg(
+					Arg::new("failures")
+						.help("The directory failing programs get saved to")
+						.long("failures")
+						.action(ArgAction::Set)
+						.default_value("fuzz-failures"),
+				)
+				.arg(
+					Arg::new("length")
+						.help("Roughly how many commands a program consists of")
+						.long("length")
+						.action(ArgAction::Set)
+						.value_parser(clap::value_parser!(usize)),
+				)
+				.arg(
+					Arg::new("max_depth")
+						.help("How deep loops may be nested")
+						.long("max-depth")
+						.action(ArgAction::Set)
+						.value_parser(clap::value_parser!(usize)),
+				)
+				.arg(
+					Arg::new("loop_weight")
+						.help("The relative weight of generic loops")
+						.long("loop-weight")
+						.action(ArgAction::Set)
+						.value_parser(clap::value_parser!(u32)),
+				)
+				.arg(
+					Arg::new("multiply_weight")
+						.help("The relative weight of multiply loops")
+						.long("multiply-weight")
+						.action(ArgAction::Set)
+						.value_parser(clap::value_parser!(u32)),
+				)
+				.arg(
+					Arg::new("scan_weight")
+						.help("The relative weight of scan loops")
+						.long("scan-weight")
+						.action(ArgAction::Set)
+						.value_parser(clap::value_parser!(u32)),
+				),
+		)
 		.get_matches();
 
-	// Unwrap is safe as file is required
-	let input_path = PathBuf::from(matches.get_one::<String>("file").unwrap());
+	if matches.get_flag("list_passes") {
+		list_passes();
+		std::process::exit(0);
+	}
 
-	let output_path_raw = matches.get_one::<String>("output_file").map(PathBuf::from);
+	let (args, task) = match matches.subcommand() {
+		Some(("verify", args)) => {
+			let task = Task::Verify {
+				stdin_path: args.get_one::<String>("input").map(PathBuf::from),
+				max_steps:  args.get_one::<u64>("max_steps").copied().unwrap_or(DEFAULT_MAX_STEPS),
+			};
 
-	let opt_types: Vec<String> = match matches.get_many::<String>("optimisation") {
-		Some(vals) => vals.cloned().collect(),
-		None => vec![],
+			(args, task)
+		},
+		Some(("reduce", args)) => {
+			let task = Task::Reduce {
+				stdin_path:   args.get_one::<String>("input").map(PathBuf::from),
+				max_steps:    args
+					.get_one::<u64>("max_steps")
+					.copied()
+					.unwrap_or(DEFAULT_MAX_STEPS),
+				reduce_input: args.get_flag("reduce_input"),
+			};
+
+			(args, task)
+		},
+		Some(("fuzz", args)) => {
+			let mut generator = GeneratorConfig::default();
+			if let Some(length) = args.get_one::<usize>("length") {
+				generator.length = *length;
+			}
+			if let Some(max_depth) = args.get_one::<usize>("max_depth") {
+				generator.max_depth = *max_depth;
+			}
+			if let Some(weight) = args.get_one::<u32>("loop_weight") {
+				generator.loop_weight = *weight;
+			}
+			if let Some(weight) = args.get_one::<u32>("multiply_weight") {
+				generator.multiply_weight = *weight;
+			}
+			if let Some(weight) = args.get_one::<u32>("scan_weight") {
+				generator.scan_weight = *weight;
+			}
+
+			let seed = match args.get_one::<u64>("seed") {
+				Some(seed) => *seed,
+				None => {
+					let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+					now.as_nanos() as u64
+				},
+			};
+
+			// Unwraps are safe as these have defaults
+			let task = Task::Fuzz {
+				seed,
+				iterations: *args.get_one::<u64>("iterations").unwrap(),
+				max_steps: *args.get_one::<u64>("max_steps").unwrap(),
+				failures_dir: PathBuf::from(args.get_one::<String>("failures").unwrap()),
+				generator,
+			};
+
+			(args, task)
+		},
+		Some(("build", args)) => (args, Task::Build),
+		Some(("fmt", args)) => {
+			// Unwrap is safe as width has a default
+			let style = Style {
+				width:     *args.get_one::<usize>("width").unwrap(),
+				normalise: args.get_flag("normalise"),
+			};
+
+			(args, Task::Format { style, check: args.get_flag("check") })
+		},
+		Some(("lint", args)) => (args, Task::Lint),
+		Some(("termination", args)) => (args, Task::Termination),
+		Some(("portability", args)) => {
+			let task = Task::Portability {
+				stdin_path: args.get_one::<String>("input").map(PathBuf::from),
+				max_steps:  args.get_one::<u64>("max_steps").copied().unwrap_or(DEFAULT_MAX_STEPS),
+			};
+
+			(args, task)
+		},
+		_ => (&matches, Task::Run),
 	};
 
-	let bytecode_path = if matches.get_flag("emit_bytecode") {
-		match &output_path_raw {
-			Some(p) => Some(p.to_owned()),
-			None => {
-				let mut original = input_path.clone();
-				original.set_extension("bfc");
-				Some(original)
-			},
-		}
+	// Every task but fuzzing requires a file
+	let input_path = match args.try_get_one::<String>("file") {
+		Ok(Some(file)) => PathBuf::from(file),
+		_ => PathBuf::new(),
+	};
+
+	let output_path_raw = match args.try_get_one::<String>("output_file") {
+		Ok(Some(file)) => Some(PathBuf::from(file)),
+		_ => None,
+	};
+
+	let opt_types: Vec<String> = match args.try_get_many::<String>("optimisation") {
+		Ok(Some(vals)) => vals.cloned().collect(),
+		_ => vec![],
+	};
+
+	let emit = match task {
+		Task::Build => Some(Emit::Executable),
+		_ => Emit::FLAGS.into_iter().find(|(flag, _)| matches.get_flag(flag)).map(|(_, emit)| emit),
+	};
+	let emit = emit.map(|emit| {
+		let path = match &output_path_raw {
+			Some(p) => p.to_owned(),
+			None => input_path.with_extension(emit.extension()),
+		};
+
+		(emit, path)
+	});
+
+	// Not every command takes every pipeline argument
+	let mut passes = if let Ok(Some(spec)) = args.try_get_one::<String>("passes") {
+		PassManager::from_pipeline(spec)?
+	} else if let Ok(Some(level)) = args.try_get_one::<u8>("opt_level") {
+		PassManager::from_level(*level)?
+	} else if opt_types.is_empty() && !matches!(task, Task::Run) {
+		PassManager::from_level(3)?
 	} else {
-		None
+		PassManager::from_optimisations(&Optimisations::from_strings(&opt_types)?)
 	};
+	if let Ok(Some(max_iterations)) = args.try_get_one::<usize>("max_iterations") {
+		passes.set_max_iterations(*max_iterations);
+	}
 
-	let symcode_path = if matches.get_flag("emit_symcode") {
-		match &output_path_raw {
-			Some(p) => Some(p.to_owned()),
-			None => {
-				let mut original = input_path.clone();
-				original.set_extension("bfs");
-				Some(original)
-			},
+	let opt_stats = matches!(args.try_get_one::<bool>("opt_stats"), Ok(Some(true)));
+	let dump_after = match args.try_get_one::<String>("dump_after") {
+		Ok(Some(name)) => Some(name.to_owned()),
+		_ => None,
+	};
+	if let Some(name) = &dump_after {
+		if !passes.passes().any(|p| p.name() == name) {
+			return Err(Error::UnknownPass(name.to_owned()));
 		}
-	} else {
-		None
+	}
+
+	// Unwrap is safe as backend has a default
+	let backend = match matches.get_one::<String>("backend").unwrap().as_str() {
+		"packed" => Backend::Packed,
+		"closure" => Backend::Closure,
+		"jit" => Backend::Jit,
+		_ => Backend::Interpreter,
+	};
+
+	let tape_size = match args.try_get_one::<u64>("tape_size") {
+		Ok(Some(size)) => *size as usize,
+		_ => MAX_TAPE_SIZE,
+	};
+	let eof = match args.try_get_one::<String>("eof") {
+		Ok(Some(eof)) if eof == "unchanged" => Eof::Unchanged,
+		Ok(Some(eof)) if eof == "zero" => Eof::Zero,
+		Ok(Some(eof)) if eof == "minus-one" => Eof::MinusOne,
+		_ => Eof::Error,
 	};
 
 	Ok(Config {
+		task,
 		input_path,
-		bytecode_path,
-		symcode_path,
-		optimisations: Optimisations::from_strings(&opt_types),
+		emit,
+		passes,
+		opt_stats,
+		dump_after,
+		backend,
+		options: Options { tape_size, eof },
 	})
 }
 
+/// The flag selecting individual optimisations
+///
+/// Its short flag is added by the commands that take it, as `build` uses `-o`
+/// for its output instead
+fn optimisation_arg() -> Arg {
+	Arg::new("optimisation")
+		.help("Specify what optimisations to apply")
+		.long("optimise")
+		.action(ArgAction::Set)
+		.value_delimiter(',')
+		.value_parser(OPTIMISATION_NAMES.map(|(name, _)| name))
+		.conflicts_with_all(["passes", "opt_level"])
+}
+
+/// The arguments choosing the optimisation pipeline, for every command that
+/// optimises a program
+fn pipeline_args() -> [Arg; 3] {
+	[
+		Arg::new("opt_level")
+			.help("Apply the pass pipeline of an optimisation level, see --list-passes")
+			.short('O')
+			.long("opt-level")
+			.action(ArgAction::Set)
+			.value_parser(clap::value_parser!(u8).range(0..=3))
+			.conflicts_with("passes"),
+		Arg::new("passes")
+			.help(
+				"Specify an ordered, comma separated pipeline of passes to apply, a pass ending \
+				 in '*' runs until it stops changing the code",
+			)
+			.long("passes")
+			.action(ArgAction::Set),
+		Arg::new("max_iterations")
+			.help("How often the optimisation pipeline may be repeated")
+			.long("max-iterations")
+			.action(ArgAction::Set)
+			.value_parser(clap::value_parser!(usize)),
+	]
+}
+
+/// The arguments reporting on the passes, for the commands that optimise a
+/// program once to run or compile it
+fn report_args() -> [Arg; 2] {
+	[
+		Arg::new("opt_stats")
+			.help("Print the instruction count before and after every pass to stderr")
+			.long("opt-stats")
+			.action(ArgAction::SetTrue),
+		Arg::new("dump_after")
+			.help("Write the symbolic code after every run of the given pass to <file>.<pass>.bfs")
+			.long("dump-after")
+			.value_name("PASS")
+			.action(ArgAction::Set),
+	]
+}
+
+/// The arguments configuring the machine a program runs on
+fn machine_args() -> [Arg; 2] {
+	[
+		Arg::new("tape_size")
+			.help("The number of cells on the tape, the data pointer wraps around at either end")
+			.long("tape-size")
+			.action(ArgAction::Set)
+			.value_parser(clap::value_parser!(u64).range(1..=MAX_TAPE_SIZE as u64)),
+		Arg::new("eof")
+			.help("What reading past the end of the input does")
+			.long("eof")
+			.action(ArgAction::Set)
+			.value_parser(["error", "unchanged", "zero", "minus-one"])
+			.default_value("error"),
+	]
+}
+
+/// The arguments shared by subcommands that compare optimised and unoptimised
+/// runs
+fn differential_args() -> [Arg; 4] {
+	[
+		optimisation_arg().short('o'),
+		Arg::new("input")
+			.help("A file to use as the program's input, empty if not given")
+			.short('i')
+			.long("input")
+			.action(ArgAction::Set),
+		Arg::new("max_steps")
+			.help("How many instructions each run may execute, 10000000 by default")
+			.long("max-steps")
+			.action(ArgAction::Set)
+			.value_parser(clap::value_parser!(u64)),
+		Arg::new("file").help("The brainfuck file to check").index(1).required(true),
+	]
+}
+
+/// Print every available pass with its description, and the pipelines of the
+/// optimisation levels
+fn list_passes() {
+	let manager = PassManager::new();
+	let width = manager.passes().map(|p| p.name().len()).max().unwrap_or(0);
+
+	println!("Passes:");
+	for pass in manager.passes() {
+		println!("  {:width$}  {}", pass.name(), pass.description());
+	}
+
+	println!();
+	println!("Optimisation levels:");
+	for (level, spec) in OPT_LEVELS.iter().enumerate() {
+		println!("  -O{}  {}", level, if spec.is_empty() { "(none)" } else { spec });
+	}
+}
+
+/// Run linked instructions on the requested backend
+fn execute(insts: &LinkedInstructions, cfg: &Config) -> Result<(), Error> {
+	match cfg.backend {
+		Backend::Interpreter => Interpreter::with_options(insts, cfg.options).run(),
+		Backend::Packed => {
+			let program = PackedProgram::new(insts)?;
+			PackedInterpreter::with_options(&program, cfg.options).run()
+		},
+		Backend::Closure => ClosureProgram::with_options(insts, cfg.options)?.run(),
+		Backend::Jit => {
+			match JitProgram::with_options(insts, cfg.options) {
+				Ok(mut program) => program.run(),
+				Err(Error::UnsupportedJit) => {
+					eprintln!(
+						"Warning: {}, falling back to the interpreter",
+						Error::UnsupportedJit
+					);
+					Interpreter::with_options(insts, cfg.options).run()
+				},
+				Err(e) => Err(e),
+			}
+		},
+	}
+}
+
+/// Write linked instructions in the requested format
+fn write_emit(
+	insts: &LinkedInstructions,
+	emit: Emit,
+	path: &Path,
+	cfg: &Config,
+) -> Result<(), Error> {
+	let output = match emit {
+		Emit::Bytecode => insts.to_bytecode(),
+		Emit::Symcode => insts.to_symcode().into_bytes(),
+		Emit::C => insts.to_c(&cfg.options)?.into_bytes(),
+		Emit::Llvm => insts.to_llvm(&cfg.options)?.into_bytes(),
+		Emit::Wasm => insts.to_wasm(&cfg.options)?,
+		Emit::Wat => insts.to_wat(&cfg.options)?.into_bytes(),
+		Emit::Rust => insts.to_rust(&cfg.options)?.into_bytes(),
+		Emit::Brainfuck => insts.to_brainfuck()?.into_bytes(),
+		Emit::Executable => insts.to_elf(&cfg.options)?,
+	};
+
+	let mut output_writer = File::create(path)?;
+	output_writer.write_all(&output)?;
+
+	#[cfg(unix)]
+	if let Emit::Executable = emit {
+		use std::os::unix::fs::PermissionsExt;
+
+		output_writer.set_permissions(std::fs::Permissions::from_mode(0o755))?;
+	}
+
+	Ok(())
+}
+
 /// Read and transpile brainfuck code, then optimise and run it
 fn handle_file(bytes: &[u8], cfg: &Config) -> Result<(), Error> {
 	let instructions = UnlinkedInstructions::from_text(bytes);
 
-	let optimised_instructions = instructions.optimise(&cfg.optimisations)?;
+	let mut stats = OptStats::default();
+	let mut dump = match &cfg.dump_after {
+		Some(name) => {
+			let mut path = cfg.input_path.clone();
+			path.set_extension(format!("{}.bfs", name));
+			Some(IrDump::new(name, BufWriter::new(File::create(path)?)))
+		},
+		None => None,
+	};
 
-	if let Some(path) = &cfg.bytecode_path {
-		let mut output_writer = File::create(path)?;
-		let bytecode = optimised_instructions.to_bytecode();
+	let mut observers: Vec<&mut dyn PassObserver> = vec![];
+	if cfg.opt_stats {
+		observers.push(&mut stats);
+	}
+	if let Some(dump) = &mut dump {
+		observers.push(dump);
+	}
 
-		output_writer.write_all(&bytecode)?;
-		Ok(())
-	} else if let Some(path) = &cfg.symcode_path {
-		let mut output_writer = File::create(path)?;
+	let (optimised_instructions, report) = cfg.passes.run_observed(instructions, &mut observers)?;
+	if cfg.opt_stats {
+		eprint!("{}", stats);
+	}
+	if let Some(dump) = dump {
+		dump.into_inner().flush()?;
+	}
+	if !report.converged {
+		eprintln!("Warning: optimisations did not converge after {} iterations", report.iterations);
+	}
 
-		let mut repr = String::with_capacity(optimised_instructions.0.len() * 4);
-		for i in optimised_instructions.0 {
-			repr.push_str(&i.to_string());
-			repr.push('\n');
-		}
+	match &cfg.emit {
+		Some((emit, path)) => write_emit(&optimised_instructions, *emit, path, cfg),
+		None => execute(&optimised_instructions, cfg),
+	}
+}
 
-		output_writer.write_all(repr.as_bytes())?;
-		Ok(())
-	} else {
-		let mut interpreter = Interpreter::new(&optimised_instructions);
-		interpreter.run()
+/// Run brainfuck code with and without optimisations, exiting with a non-zero
+/// status if they behave differently
+fn handle_verify(
+	bytes: &[u8],
+	cfg: &Config,
+	stdin_path: &Option<PathBuf>,
+	max_steps: u64,
+) -> Result<(), Error> {
+	let input = match stdin_path {
+		Some(path) => std::fs::read(path)?,
+		None => vec![],
+	};
+
+	let report = verify(bytes, &cfg.passes, &input, max_steps)?;
+	print!("{}", report);
+
+	if report.divergence.is_some() {
+		std::process::exit(1);
 	}
+
+	Ok(())
 }
 
-/// Read and run pre-generated bytecode
-fn handle_bytecode(bytes: &[u8], cfg: &Config) -> Result<(), Error> {
-	let linked_instructions = LinkedInstructions::from_bytecode(bytes);
+/// Shrink brainfuck code, and optionally its input, while it behaves
+/// differently with and without optimisations
+fn handle_reduce(
+	bytes: &[u8],
+	cfg: &Config,
+	stdin_path: &Option<PathBuf>,
+	max_steps: u64,
+	reduce_input: bool,
+) -> Result<(), Error> {
+	let input = match stdin_path {
+		Some(path) => std::fs::read(path)?,
+		None => vec![],
+	};
+
+	let reduction = match reduce(bytes, &input, &cfg.passes, max_steps, reduce_input)? {
+		Some(reduction) => reduction,
+		None => {
+			println!("The program behaves the same with and without optimisations");
+			return Ok(());
+		},
+	};
+
+	let mut source_path = cfg.input_path.clone();
+	source_path.set_extension("min.bf");
+	std::fs::write(&source_path, &reduction.source)?;
+	println!(
+		"Reduced {} to {} bytes after {} tests: {}",
+		bytes.len(),
+		reduction.source.len(),
+		reduction.tests,
+		source_path.display()
+	);
 
-	if let Some(path) = &cfg.bytecode_path {
-		let mut output_writer = File::create(path)?;
-		let bytecode = linked_instructions.to_bytecode();
+	if reduce_input {
+		let mut input_path = cfg.input_path.clone();
+		input_path.set_extension("min.in");
+		std::fs::write(&input_path, &reduction.input)?;
+		println!(
+			"Reduced input from {} to {} bytes: {}",
+			input.len(),
+			reduction.input.len(),
+			input_path.display()
+		);
+	}
+
+	println!("pipeline:    {}", cfg.passes.pipeline());
+	println!("DIVERGED:    {}", reduction.divergence);
+
+	Ok(())
+}
+
+/// Generate random programs and check them against every combination of
+/// optimisations, saving failing programs to a directory
+fn handle_fuzz(
+	seed: u64,
+	iterations: u64,
+	max_steps: u64,
+	failures_dir: &Path,
+	generator: &GeneratorConfig,
+) -> Result<(), Error> {
+	println!("Fuzzing {} programs with seed {}", iterations, seed);
 
-		output_writer.write_all(&bytecode)?;
-		Ok(())
-	} else if let Some(path) = &cfg.symcode_path {
-		let mut output_writer = File::create(path)?;
+	let mut fuzzer = Fuzzer::new(seed, generator.clone());
+	let mut failures = 0;
 
-		let mut repr = String::with_capacity(linked_instructions.0.len() * 4);
-		for i in linked_instructions.0 {
-			repr.push_str(&i.to_string());
-			repr.push('\n');
+	for case in 0..iterations {
+		let (source, input) = fuzzer.next_case();
+
+		if let Some(failure) = check_case(&source, &input, max_steps) {
+			failures += 1;
+
+			std::fs::create_dir_all(failures_dir)?;
+			let base = failures_dir.join(format!("{}-{}", seed, case));
+			std::fs::write(base.with_extension("bf"), &source)?;
+			std::fs::write(base.with_extension("in"), &input)?;
+			std::fs::write(base.with_extension("txt"), format!("{}\n", failure))?;
+
+			println!("{}: {}", base.with_extension("bf").display(), failure);
 		}
+	}
 
-		output_writer.write_all(repr.as_bytes())?;
-		Ok(())
-	} else {
-		let mut interpreter = Interpreter::new(&linked_instructions);
-		interpreter.run()
+	println!("{} of {} programs failed", failures, iterations);
+	if failures > 0 {
+		std::process::exit(1);
+	}
+
+	Ok(())
+}
+
+/// Reformat brainfuck code in place, or in check mode exit with a non-zero
+/// status if that would change it
+fn handle_format(bytes: &[u8], cfg: &Config, style: &Style, check: bool) -> Result<(), Error> {
+	let formatted = Source::parse(bytes)?.format(style);
+	if formatted == bytes {
+		return Ok(());
+	}
+
+	if check {
+		println!("{} is not formatted", cfg.input_path.display());
+		std::process::exit(1);
+	}
+
+	std::fs::write(&cfg.input_path, formatted)?;
+
+	Ok(())
+}
+
+/// Print the warnings about brainfuck code, exiting with a non-zero status if
+/// there are any
+fn handle_lint(bytes: &[u8], cfg: &Config) -> Result<(), Error> {
+	let warnings = lint(bytes)?;
+	for warning in &warnings {
+		println!("{}:{}", cfg.input_path.display(), warning);
+	}
+
+	if !warnings.is_empty() {
+		std::process::exit(1);
+	}
+
+	Ok(())
+}
+
+/// Print what implementation-defined behaviour brainfuck code relies on,
+/// exiting with a non-zero status if it relies on any
+fn handle_portability(
+	bytes: &[u8],
+	stdin_path: &Option<PathBuf>,
+	max_steps: u64,
+) -> Result<(), Error> {
+	let input = match stdin_path {
+		Some(path) => std::fs::read(path)?,
+		None => vec![],
+	};
+
+	let report = analyse(bytes, &input, max_steps)?;
+	print!("{}", report);
+
+	if !report.is_portable() {
+		std::process::exit(1);
+	}
+
+	Ok(())
+}
+
+/// Print whether every loop of brainfuck code stops, exiting with a non-zero
+/// status if one that can be entered never does
+fn handle_termination(bytes: &[u8], cfg: &Config) -> Result<(), Error> {
+	let insts = UnlinkedInstructions::from_text(bytes).link()?;
+	let loops = classify(&insts);
+
+	// Unoptimised instructions are the commands of the source, in order
+	let commands: Vec<usize> =
+		(0..bytes.len()).filter(|index| b"+-<>[].,".contains(&bytes[*index])).collect();
+
+	let lines = Lines::new(bytes);
+	for l in &loops {
+		let Position { line, column } = lines.position(commands[l.start]);
+		let note = match (l.reachable, l.hangs()) {
+			(false, _) => " (never entered)",
+			(true, true) => " (always entered once reached)",
+			(true, false) => "",
+		};
+		println!(
+			"{}:{}:{}: {}, {}{}",
+			cfg.input_path.display(),
+			line,
+			column,
+			l.termination,
+			l.reason,
+			note
+		);
+	}
+
+	let count = |termination| loops.iter().filter(|l| l.termination == termination).count();
+	println!(
+		"{} {}: {} terminating, {} non-terminating, {} unknown",
+		loops.len(),
+		if loops.len() == 1 { "loop" } else { "loops" },
+		count(Termination::Terminating),
+		count(Termination::NonTerminating),
+		count(Termination::Unknown)
+	);
+
+	let hangs = loops.iter().any(|l| l.reachable && l.termination == Termination::NonTerminating);
+	if hangs {
+		std::process::exit(1);
+	}
+
+	Ok(())
+}
+
+/// Read and run pre-generated bytecode
+fn handle_bytecode(bytes: &[u8], cfg: &Config) -> Result<(), Error> {
+	let linked_instructions = LinkedInstructions::from_bytecode(bytes);
+
+	match &cfg.emit {
+		Some((emit, path)) => write_emit(&linked_instructions, *emit, path, cfg),
+		None => execute(&linked_instructions, cfg),
 	}
 }
 
 fn run() -> Result<(), Error> {
 	let config = make_config()?;
 
-	let file = File::open(&config.input_path)?;
-	let bytes: Vec<u8> = file.bytes().try_collect()?;
+	if let Task::Fuzz { seed, iterations, max_steps, failures_dir, generator } = &config.task {
+		return handle_fuzz(*seed, *iterations, *max_steps, failures_dir, generator);
+	}
+
+	let bytes = std::fs::read(&config.input_path)?;
 
 	let extension = match config.input_path.extension() {
 		Some(ext) => ext.to_str().unwrap(),
 		None => return Err(Error::UnknownFileExtension("".to_owned())),
 	};
 
-	if extension == "bf" {
-		handle_file(&bytes, &config)
-	} else if extension == "bfc" {
-		handle_bytecode(&bytes, &config)
-	} else {
-		Err(Error::UnknownFileExtension(extension.to_owned()))
+	match &config.task {
+		Task::Verify { .. }
+		| Task::Reduce { .. }
+		| Task::Format { .. }
+		| Task::Lint
+		| Task::Portability { .. }
+		| Task::Termination
+			if extension != "bf" =>
+		{
+			Err(Error::UnknownFileExtension(extension.to_owned()))
+		},
+		Task::Verify { stdin_path, max_steps } => {
+			handle_verify(&bytes, &config, stdin_path, *max_steps)
+		},
+		Task::Reduce { stdin_path, max_steps, reduce_input } => {
+			handle_reduce(&bytes, &config, stdin_path, *max_steps, *reduce_input)
+		},
+		Task::Format { style, check } => handle_format(&bytes, &config, style, *check),
+		Task::Lint => handle_lint(&bytes, &config),
+		Task::Termination => handle_termination(&bytes, &config),
+		Task::Portability { stdin_path, max_steps } => {
+			handle_portability(&bytes, stdin_path, *max_steps)
+		},
+		Task::Run | Task::Build if extension == "bf" => handle_file(&bytes, &config),
+		Task::Run | Task::Build if extension == "bfc" => handle_bytecode(&bytes, &config),
+		Task::Run | Task::Build => Err(Error::UnknownFileExtension(extension.to_owned())),
+		Task::Fuzz { .. } => unreachable!(),
 	}
 }
 
 fn main() {
-	match run() {
-		Ok(_) => (),
-		Err(e) => eprintln!("{}", e),
+	if let Err(e) = run() {
+		eprintln!("{}", e);
+		std::process::exit(1);
 	}
 }
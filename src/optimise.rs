@@ -1,40 +1,66 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::hash::Hash;
 
 use itertools::Itertools;
 
 use crate::error::Error;
-use crate::instruction::{Cell, Instruction, LinkedInstructions, UnlinkedInstructions};
-
-const MAX_OPT_ITER: u8 = 20;
+use crate::idiom::{self, IDIOMS};
+use crate::instruction::{Ast, Cell, Instruction, LinkedInstructions, Node, UnlinkedInstructions};
+use crate::pass::{Pass, PassManager};
 
 bitflags! {
-	pub struct Optimisations: u8 {
+	pub struct Optimisations: u16 {
 		const COMBINE_CLEARS     = 0b00000001;
 		const GROUP_INSTRUCTIONS = 0b00000010;
 		const REORDER_INSTRUCTIONS = 0b00000100;
 		const COMBINE_MULTIPLY_LOOPS = 0b00001000;
 		const COMBINE_SCAN_LOOPS = 0b00010000;
+		const COMBINE_NESTED_LOOPS = 0b00100000;
+		const HOIST_LOOP_INVARIANTS = 0b01000000;
+		const CONVERT_IF_STATEMENTS = 0b10000000;
+		const REPLACE_IDIOMS = 0b100000000;
 	}
 }
 
+/// The names accepted by [`Optimisations::from_strings`]
+///
+/// `COMBINE_SCAN_LOOPS` has no pass implementing it yet, so it has no name
+/// either and asking for it is an error
+pub const OPTIMISATION_NAMES: [(&str, Optimisations); 9] = [
+	("all", Optimisations::all()),
+	("combine-clears", Optimisations::COMBINE_CLEARS),
+	("group-instructions", Optimisations::GROUP_INSTRUCTIONS),
+	("reorder-instructions", Optimisations::REORDER_INSTRUCTIONS),
+	("combine-multiply-loops", Optimisations::COMBINE_MULTIPLY_LOOPS),
+	("combine-nested-loops", Optimisations::COMBINE_NESTED_LOOPS),
+	("hoist-loop-invariants", Optimisations::HOIST_LOOP_INVARIANTS),
+	("convert-if-statements", Optimisations::CONVERT_IF_STATEMENTS),
+	("replace-idioms", Optimisations::REPLACE_IDIOMS),
+];
+
 impl Optimisations {
-	pub fn from_strings(strs: &[String]) -> Self {
+	pub fn from_strings(strs: &[String]) -> Result<Self, Error> {
 		let mut opts = Self::empty();
 
 		for s in strs {
-			match s.as_str() {
-				"all" => opts.set(Self::all(), true),
-				"combine-clears" => opts.set(Self::COMBINE_CLEARS, true),
-				"group-instructions" => opts.set(Self::GROUP_INSTRUCTIONS, true),
-				"reorder-instructions" => opts.set(Self::REORDER_INSTRUCTIONS, true),
-				"combine-multiply-loops" => opts.set(Self::COMBINE_MULTIPLY_LOOPS, true),
-				"combine-scan-loops" => opts.set(Self::COMBINE_SCAN_LOOPS, true),
-				_ => (),
+			match OPTIMISATION_NAMES.iter().find(|(name, _)| name == s) {
+				Some((_, flags)) => opts.insert(*flags),
+				None => return Err(Error::UnknownOptimisation(s.to_owned())),
 			}
 		}
 
-		opts
+		Ok(opts)
+	}
+
+	/// The names of the individual optimisations that are set, the inverse of
+	/// [`Optimisations::from_strings`]
+	pub fn to_strings(&self) -> Vec<&'static str> {
+		OPTIMISATION_NAMES
+			.iter()
+			.skip(1)
+			.filter(|(_, flags)| self.contains(*flags))
+			.map(|(name, _)| *name)
+			.collect()
 	}
 }
 
@@ -42,133 +68,167 @@ impl UnlinkedInstructions {
 	/// Apply all requested optimisations
 	///
 	/// List of optimisations:
-	///  - Clear pattern combination: `[-]` patterns get combined into a `clear`
-	/// instruction
-	///  - Instruction grouping: repeated sequences of add/sub and left/right
-	/// instructions get combined into a single instruction
+	///  - Clear pattern combination: `[-]` patterns get combined into a `clear` instruction
+	///  - Instruction grouping: repeated sequences of add/sub and left/right instructions get
+	///    combined into a single instruction
+	///  - Nested loop combination: balanced loops whose body is a straight line of (already
+	///    combined) multiply loops get replaced by their closed form
+	///  - Loop invariant hoisting: writes that leave a cell with the same value in every iteration
+	///    of a balanced loop get moved in front of it
+	///  - If statement conversion: loops that provably run at most once get turned into
+	///    forward-only conditionals
+	///  - Idiom replacement: snippets from the idiom catalogue get replaced by a native instruction
 	pub fn optimise(self, opts: &Optimisations) -> Result<LinkedInstructions, Error> {
-		let mut prev = self.clone();
-		let mut result = self.optimise_single_pass(opts)?;
+		let (optimised_insts, _) = PassManager::from_optimisations(opts).run(self)?;
 
-		let mut counter = 0;
-		while prev.link()? != result && counter <= MAX_OPT_ITER {
-			prev = UnlinkedInstructions(result.0);
-			result = prev.clone().optimise_single_pass(opts)?;
+		Ok(optimised_insts)
+	}
+}
 
-			counter += 1;
-		}
+/// One of the passes built into the optimiser
+#[derive(Clone)]
+pub struct BuiltinPass {
+	pub name:        &'static str,
+	pub description: &'static str,
+	/// The optimisation this pass implements
+	pub flag:        Optimisations,
+	transform:       fn(Ast) -> Ast,
+}
 
-		Ok(result)
-	}
+impl Pass for BuiltinPass {
+	fn name(&self) -> &str { self.name }
 
-	fn optimise_single_pass(self, opts: &Optimisations) -> Result<LinkedInstructions, Error> {
-		let mut optimised_insts = self.link()?;
+	fn description(&self) -> &str { self.description }
 
-		if opts.contains(Optimisations::COMBINE_CLEARS) {
-			optimised_insts = optimised_insts.combine_clears().link()?;
-		}
-		if opts.contains(Optimisations::GROUP_INSTRUCTIONS) {
-			optimised_insts = optimised_insts.group_instructions().link()?;
-		}
-		if opts.contains(Optimisations::REORDER_INSTRUCTIONS) {
-			optimised_insts = optimised_insts.reorder().link()?;
-		}
-		if opts.contains(Optimisations::COMBINE_MULTIPLY_LOOPS) {
-			optimised_insts = optimised_insts.combine_multiply_loops().link()?;
-		}
-		// if opts.contains(Optimisations::COMBINE_SCAN_LOOPS) {
-		// 	optimised_insts = optimised_insts.combine_scan_loops().link()?;
-		// }
+	fn run(&self, ast: Ast) -> Result<Ast, Error> { Ok((self.transform)(ast)) }
+}
 
-		Ok(optimised_insts)
+/// All built in passes, in the order they get applied by
+/// [`UnlinkedInstructions::optimise`]
+pub const BUILTIN_PASSES: [BuiltinPass; 8] = [
+	// Idioms are matched against unoptimised code, so this has to go first
+	BuiltinPass {
+		name:        "idioms",
+		description: "Replace snippets from the idiom catalogue with a native instruction",
+		flag:        Optimisations::REPLACE_IDIOMS,
+		transform:   Ast::replace_idioms,
+	},
+	BuiltinPass {
+		name:        "clears",
+		description: "Combine `[-]` and `[+]` into a Set(0)",
+		flag:        Optimisations::COMBINE_CLEARS,
+		transform:   Ast::combine_clears,
+	},
+	BuiltinPass {
+		name:        "group",
+		description: "Group repeated Incr, Set, and IncrDp instructions into one",
+		flag:        Optimisations::GROUP_INSTRUCTIONS,
+		transform:   Ast::group_instructions,
+	},
+	BuiltinPass {
+		name:        "reorder",
+		description: "Reorder straight line code to use offsets instead of pointer movement",
+		flag:        Optimisations::REORDER_INSTRUCTIONS,
+		transform:   Ast::reorder,
+	},
+	BuiltinPass {
+		name:        "mul",
+		description: "Combine multiply loops into Mul instructions",
+		flag:        Optimisations::COMBINE_MULTIPLY_LOOPS,
+		transform:   Ast::combine_multiply_loops,
+	},
+	BuiltinPass {
+		name:        "nested",
+		description: "Replace balanced loops of combined multiply loops by their closed form",
+		flag:        Optimisations::COMBINE_NESTED_LOOPS,
+		transform:   Ast::combine_nested_loops,
+	},
+	BuiltinPass {
+		name:        "hoist",
+		description: "Move invariant Set instructions in front of balanced loops",
+		flag:        Optimisations::HOIST_LOOP_INVARIANTS,
+		transform:   Ast::hoist_loop_invariants,
+	},
+	BuiltinPass {
+		name:        "ifs",
+		description: "Turn loops that run at most once into If instructions",
+		flag:        Optimisations::CONVERT_IF_STATEMENTS,
+		transform:   Ast::convert_if_statements,
+	},
+];
+
+impl Ast {
+	/// Replace snippets from the idiom catalogue with their native instruction
+	///
+	/// eg. the `x < y` snippet -> Compare
+	fn replace_idioms(self) -> Ast {
+		let patterns: Vec<Vec<Node>> = IDIOMS.iter().map(|i| i.nodes()).collect();
+
+		Ast(transform_top_down(self.0, &|nodes| {
+			let mut result = Vec::with_capacity(nodes.len());
+
+			let mut idx = 0;
+			while idx < nodes.len() {
+				match idiom::match_idiom(&patterns, &nodes[idx..]) {
+					Some(idiom) => {
+						result.push(Node::Inst(idiom.replacement));
+						idx += idiom.nodes().len();
+					},
+					None => {
+						result.push(nodes[idx].clone());
+						idx += 1;
+					},
+				}
+			}
+
+			result
+		}))
 	}
-}
 
-impl LinkedInstructions {
 	/// Combine `[-]` and `[+]` into a Set 0 instruction
-	fn combine_clears(self) -> UnlinkedInstructions {
-		let mut optimised_insts = Vec::with_capacity(self.0.len());
-
-		let mut inst_iter = self.0.iter().peekable();
-		while let Some(inst) = inst_iter.next() {
-			let optimised_instruction = match inst {
-				Instruction::BranchIfZero { .. } => {
-					match inst_iter.peek().unwrap() {
-						Instruction::Incr { amount: n, offset: 0 } if *n == 1 || *n == -1 => {
-							inst_iter.next();
-
-							if let Instruction::BranchIfNotZero { .. } = inst_iter.peek().unwrap() {
-								inst_iter.next();
-
-								Instruction::Set { amount: 0, offset: 0 }
-							} else {
-								optimised_insts.push(Instruction::BranchIfZero { destination: 0 });
-								Instruction::Incr { amount: *n, offset: 0 }
-							}
+	fn combine_clears(self) -> Ast {
+		Ast(transform_bottom_up(self.0, &|nodes| {
+			nodes
+				.into_iter()
+				.map(|node| {
+					match node.as_loop() {
+						Some([Node::Inst(Instruction::Incr { amount: 1 | -1, offset: 0 })]) => {
+							Node::Inst(Instruction::Set { amount: 0, offset: 0 })
 						},
-						_ => Instruction::BranchIfZero { destination: 0 },
+						_ => node,
 					}
-				},
-				inst => inst.to_owned(),
-			};
-
-			optimised_insts.push(optimised_instruction);
-		}
-
-		UnlinkedInstructions(optimised_insts)
+				})
+				.collect()
+		}))
 	}
 
 	/// Group repeated sequences of Incr, Set, and IncrIp instructions into one
 	///
 	/// Also merges consecutive Incr and Set instructions into a single Set
-	fn group_instructions(self) -> UnlinkedInstructions {
-		UnlinkedInstructions(
-			self.0
+	fn group_instructions(self) -> Ast {
+		Ast(transform_bottom_up(self.0, &|nodes| {
+			nodes
 				.into_iter()
 				.coalesce(|prev, curr| {
-					match (prev, curr) {
-						// Incr(x), Incr(y) -> Incr(x + y)
-						(
-							Instruction::Incr { amount: prev_amt, offset: prev_ofst },
-							Instruction::Incr { amount, offset },
-						) if prev_ofst == offset => {
-							let new_amt = prev_amt + amount;
-							Ok(Instruction::Incr { amount: new_amt, offset })
-						},
-						// IncrIp(x), IncrIp(y) -> IncrIp(x + y)
-						(
-							Instruction::IncrDp { amount: prev_amt },
-							Instruction::IncrDp { amount },
-						) => Ok(Instruction::IncrDp { amount: prev_amt + amount }),
-						// Incr(x), Set(y) -> Set(y)
-						(
-							Instruction::Incr { offset: prev_ofst, .. },
-							Instruction::Set { amount, offset },
-						) if prev_ofst == offset => Ok(Instruction::Set { amount, offset }),
-						// Set(x), Incr(y) -> Set(x + y)
-						(
-							Instruction::Set { amount: prev_amt, offset: prev_ofst },
-							Instruction::Incr { amount, offset },
-						) if prev_ofst == offset => {
-							let new_amt = prev_amt + amount;
-							Ok(Instruction::Set { amount: new_amt, offset })
-						},
-						// Set(x), Set(y) -> Set(y)
-						(
-							Instruction::Set { offset: prev_ofst, .. },
-							Instruction::Set { amount, offset },
-						) if prev_ofst == offset => Ok(Instruction::Set { amount, offset }),
-						_ => Err((prev, curr)),
+					let (Node::Inst(prev_inst), Node::Inst(curr_inst)) = (&prev, &curr) else {
+						return Err((prev, curr));
+					};
+
+					match group_pair(*prev_inst, *curr_inst) {
+						Some(grouped) => Ok(Node::Inst(grouped)),
+						None => Err((prev, curr)),
 					}
 				})
-				.filter(|i| {
+				.filter(|n| {
 					!(matches!(
-						i,
-						Instruction::Incr { amount: 0, .. } | Instruction::IncrDp { amount: 0 }
+						n,
+						Node::Inst(
+							Instruction::Incr { amount: 0, .. } | Instruction::IncrDp { amount: 0 }
+						)
 					))
 				})
-				.collect(),
-		)
+				.collect()
+		}))
 	}
 
 	/// Reorder Incr, Set, and IncrIp instructions so the instructions use
@@ -179,68 +239,486 @@ impl LinkedInstructions {
 	/// Incr { amount: 3, offset: 1}
 	/// Incr { amount: 1, offset: 2}
 	/// IncrIp { amount: 2 }
-	fn reorder(self) -> UnlinkedInstructions {
-		let mut sequence = vec![];
-		let mut result = vec![];
-
-		for inst in self.0 {
-			match inst {
-				Instruction::Incr { .. } | Instruction::Set { .. } | Instruction::IncrDp { .. } => {
-					sequence.push(inst);
-				},
-				_ => {
-					if !(sequence.is_empty()) {
-						result.extend(reorder_sequence(&sequence));
-						sequence = vec![];
-					}
+	fn reorder(self) -> Ast {
+		Ast(transform_bottom_up(self.0, &|nodes| {
+			let mut sequence = vec![];
+			let mut result = vec![];
+
+			for node in nodes {
+				match node {
+					Node::Inst(
+						inst @ (Instruction::Incr { .. }
+						| Instruction::Set { .. }
+						| Instruction::IncrDp { .. }),
+					) => {
+						sequence.push(inst);
+					},
+					_ => {
+						if !(sequence.is_empty()) {
+							result.extend(reorder_sequence(&sequence).into_iter().map(Node::Inst));
+							sequence = vec![];
+						}
 
-					result.push(inst);
-				},
+						result.push(node);
+					},
+				}
 			}
-		}
 
-		if !(sequence.is_empty()) {
-			result.extend(reorder_sequence(&sequence));
-		}
+			if !(sequence.is_empty()) {
+				result.extend(reorder_sequence(&sequence).into_iter().map(Node::Inst));
+			}
 
-		UnlinkedInstructions(result)
+			result
+		}))
 	}
 
 	/// Recognize multiply loop patterns and combine them into a set of Mul
 	/// instructions and a Set(0)
 	///
 	/// eg. [->++>+++<<] -> Mul(2, 1), Mul(3, 2), Set(0)
-	fn combine_multiply_loops(self) -> UnlinkedInstructions {
-		let mut result = vec![];
-
-		let mut iter = self.0.iter().enumerate();
-		while let Some((idx, inst)) = iter.next() {
-			match inst {
-				Instruction::BranchIfZero { destination } => {
-					let loop_body = &self.0[(idx + 1)..(*destination as usize)];
-					if let Some(mut changes) = is_multiply_loop(loop_body) {
+	fn combine_multiply_loops(self) -> Ast {
+		Ast(transform_bottom_up(self.0, &|nodes| {
+			let mut result = vec![];
+
+			for node in nodes {
+				let changes = node.as_loop().and_then(is_multiply_loop);
+
+				match changes {
+					Some(mut changes) => {
 						// The first entry will be -1 as multiply loops clear
 						// their current cell, this will be replaced with a
 						// Set(0)
 						changes.remove(&0);
 
 						for (ofst, amt) in changes.iter() {
-							result.push(Instruction::Mul { amount: *amt, offset: *ofst });
+							result
+								.push(Node::Inst(Instruction::Mul { amount: *amt, offset: *ofst }));
 						}
-						result.push(Instruction::Set { amount: 0, offset: 0 });
+						result.push(Node::Inst(Instruction::Set { amount: 0, offset: 0 }));
+					},
+					None => result.push(node),
+				}
+			}
+
+			result
+		}))
+	}
+
+	/// Recognize balanced loops whose body is a straight line of Incr, Set,
+	/// and Mul instructions (usually the result of combining inner multiply
+	/// loops) and replace them with their closed form
+	///
+	/// eg. [>[->+>+<<]>>[-<<+>>]<<<-] computes MEM[2] += MEM[0] * MEM[1]
+	fn combine_nested_loops(self) -> Ast {
+		Ast(transform_bottom_up(self.0, &|nodes| {
+			let mut result = vec![];
+
+			for node in nodes {
+				let replacement = node
+					.as_loop()
+					.and_then(straight_line)
+					.and_then(|body| nested_loop_closed_form(&body));
+
+				match replacement {
+					Some(replacement) => result.extend(replacement),
+					None => result.push(node),
+				}
+			}
+
+			result
+		}))
+	}
+
+	/// Move writes that leave a cell with the same value in every iteration of
+	/// a balanced loop in front of it
+	///
+	/// These are Set instructions, and the writes to the same cell that follow
+	/// them while it holds a constant. The loop is wrapped in another loop that
+	/// runs at most once, so the moved instructions only run when the original
+	/// loop would
+	///
+	/// eg. [->+>[-]+<<] -> [>>[-]+<<[->+<]]
+	fn hoist_loop_invariants(self) -> Ast {
+		Ast(transform_bottom_up(self.0, &|nodes| {
+			nodes
+				.into_iter()
+				.map(|node| {
+					let Node::Loop(body) = node else {
+						return node;
+					};
+
+					let invariants = match cell_offsets(&body) {
+						Some(offsets)
+							if net_movement(&body) == Some(0)
+								&& !runs_at_most_once(&body, &offsets) =>
+						{
+							loop_invariants(&body, &offsets)
+								.into_iter()
+								.map(|i| (i, offsets[i]))
+								.collect::<Vec<_>>()
+						},
+						_ => vec![],
+					};
+
+					if invariants.is_empty() {
+						return Node::Loop(body);
+					}
+
+					let mut guard = vec![];
+					for (inv_idx, dp) in invariants.iter() {
+						match body[*inv_idx] {
+							Node::Inst(Instruction::Set { amount, offset }) => {
+								guard.push(Node::Inst(Instruction::Set {
+									amount,
+									offset: dp + offset,
+								}));
+							},
+							Node::Inst(Instruction::Incr { amount, offset }) => {
+								guard.push(Node::Inst(Instruction::Incr {
+									amount,
+									offset: dp + offset,
+								}));
+							},
+							_ => unreachable!(),
+						}
+					}
+
+					let remaining = body
+						.into_iter()
+						.enumerate()
+						.filter(|(body_idx, _)| {
+							invariants.iter().all(|(inv_idx, _)| inv_idx != body_idx)
+						})
+						.map(|(_, body_node)| body_node)
+						.collect();
+					guard.push(Node::Loop(remaining));
+
+					Node::Loop(guard)
+				})
+				.collect()
+		}))
+	}
 
-						// Remove the loop body from the iterator
-						iter.advance_by(*destination as usize - idx).unwrap();
-					} else {
-						result.push(inst.to_owned());
+	/// Turn balanced loops that provably end with their own cell at 0 into If
+	/// instructions, as they run at most once
+	///
+	/// eg. [>+<[-]] -> If, Incr(1, 1), Set(0), EndIf
+	fn convert_if_statements(self) -> Ast {
+		// Loops have to be checked before their body gets converted, as a
+		// nested loop tells more about its cell than an If does
+		Ast(transform_top_down(self.0, &|nodes| {
+			nodes
+				.into_iter()
+				.map(|node| {
+					let is_if = match node.as_loop() {
+						Some(body) => {
+							match cell_offsets(body) {
+								Some(offsets) => {
+									net_movement(body) == Some(0)
+										&& runs_at_most_once(body, &offsets)
+								},
+								None => false,
+							}
+						},
+						None => false,
+					};
+
+					match node {
+						Node::Loop(body) if is_if => Node::If(body),
+						node => node,
 					}
-				},
-				_ => result.push(inst.to_owned()),
+				})
+				.collect()
+		}))
+	}
+}
+
+impl Node {
+	/// The body of the node if it is a loop
+	fn as_loop(&self) -> Option<&[Node]> {
+		match self {
+			Node::Loop(body) => Some(body),
+			_ => None,
+		}
+	}
+}
+
+/// Apply a transformation to the bodies of all loops and ifs, innermost
+/// first, and then to the nodes themselves
+fn transform_bottom_up(nodes: Vec<Node>, f: &impl Fn(Vec<Node>) -> Vec<Node>) -> Vec<Node> {
+	let nodes = nodes
+		.into_iter()
+		.map(|node| {
+			match node {
+				Node::Loop(body) => Node::Loop(transform_bottom_up(body, f)),
+				Node::If(body) => Node::If(transform_bottom_up(body, f)),
+				node => node,
+			}
+		})
+		.collect();
+
+	f(nodes)
+}
+
+/// Apply a transformation to the nodes, and then to the bodies of all loops
+/// and ifs in the result
+fn transform_top_down(nodes: Vec<Node>, f: &impl Fn(Vec<Node>) -> Vec<Node>) -> Vec<Node> {
+	f(nodes)
+		.into_iter()
+		.map(|node| {
+			match node {
+				Node::Loop(body) => Node::Loop(transform_top_down(body, f)),
+				Node::If(body) => Node::If(transform_top_down(body, f)),
+				node => node,
+			}
+		})
+		.collect()
+}
+
+/// Combine two consecutive instructions into one, if possible
+fn group_pair(prev: Instruction, curr: Instruction) -> Option<Instruction> {
+	match (prev, curr) {
+		// Incr(x), Incr(y) -> Incr(x + y)
+		(
+			Instruction::Incr { amount: prev_amt, offset: prev_ofst },
+			Instruction::Incr { amount, offset },
+		) if prev_ofst == offset => Some(Instruction::Incr { amount: prev_amt + amount, offset }),
+		// IncrIp(x), IncrIp(y) -> IncrIp(x + y)
+		(Instruction::IncrDp { amount: prev_amt }, Instruction::IncrDp { amount }) => {
+			Some(Instruction::IncrDp { amount: prev_amt + amount })
+		},
+		// Incr(x), Set(y) -> Set(y)
+		(Instruction::Incr { offset: prev_ofst, .. }, Instruction::Set { amount, offset })
+			if prev_ofst == offset =>
+		{
+			Some(Instruction::Set { amount, offset })
+		},
+		// Set(x), Incr(y) -> Set(x + y)
+		(
+			Instruction::Set { amount: prev_amt, offset: prev_ofst },
+			Instruction::Incr { amount, offset },
+		) if prev_ofst == offset => Some(Instruction::Set { amount: prev_amt + amount, offset }),
+		// Set(x), Set(y) -> Set(y)
+		(Instruction::Set { offset: prev_ofst, .. }, Instruction::Set { amount, offset })
+			if prev_ofst == offset =>
+		{
+			Some(Instruction::Set { amount, offset })
+		},
+		_ => None,
+	}
+}
+
+/// The instructions of a body without any loops or ifs
+fn straight_line(nodes: &[Node]) -> Option<Vec<Instruction>> {
+	nodes
+		.iter()
+		.map(|node| {
+			match node {
+				Node::Inst(inst) => Some(*inst),
+				_ => None,
+			}
+		})
+		.collect()
+}
+
+/// The value of a cell after running a straight line of instructions,
+/// expressed as an affine function of the cell values before running it
+///
+/// All arithmetic wraps, so this is exact for 8 bit cells
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Affine {
+	constant: Cell,
+	coeffs:   BTreeMap<i16, Cell>,
+}
+
+impl Affine {
+	/// The untouched value of the cell at `offset`
+	fn cell(offset: i16) -> Self { Self { constant: 0, coeffs: BTreeMap::from([(offset, 1)]) } }
+
+	fn constant(constant: Cell) -> Self { Self { constant, coeffs: BTreeMap::new() } }
+
+	fn is_constant(&self) -> bool { self.coeffs.is_empty() }
+
+	/// Add `other * factor` to this value
+	fn add_scaled(&mut self, other: &Self, factor: Cell) {
+		self.constant = self.constant.wrapping_add(other.constant.wrapping_mul(factor));
+
+		for (ofst, coeff) in other.coeffs.iter() {
+			let entry = self.coeffs.entry(*ofst).or_insert(0);
+			*entry = entry.wrapping_add(coeff.wrapping_mul(factor));
+		}
+
+		self.coeffs.retain(|_, coeff| *coeff != 0);
+	}
+
+	/// Replace the cells in `values` by their constant value
+	fn substitute(&self, values: &HashMap<i16, Cell>) -> Self {
+		let mut result = Self::constant(self.constant);
+
+		for (ofst, coeff) in self.coeffs.iter() {
+			match values.get(ofst) {
+				Some(value) => result.add_scaled(&Self::constant(*value), *coeff),
+				None => result.add_scaled(&Self::cell(*ofst), *coeff),
+			}
+		}
+
+		result
+	}
+}
+
+/// Symbolically execute a balanced straight line of Incr, Set, Mul, and
+/// IncrDp instructions
+///
+/// Returns the value of every touched cell in terms of the cell values before
+/// the instructions ran
+fn affine_effects(insts: &[Instruction]) -> Option<HashMap<i16, Affine>> {
+	let mut effects: HashMap<i16, Affine> = HashMap::new();
+	let mut cell_index: i16 = 0;
+
+	let value = |effects: &HashMap<i16, Affine>, ofst: i16| -> Affine {
+		effects.get(&ofst).cloned().unwrap_or_else(|| Affine::cell(ofst))
+	};
+
+	for inst in insts {
+		match *inst {
+			Instruction::Incr { amount, offset } => {
+				let target = cell_index + offset;
+				let mut new_value = value(&effects, target);
+				new_value.add_scaled(&Affine::constant(amount), 1);
+				effects.insert(target, new_value);
+			},
+			Instruction::Set { amount, offset } => {
+				effects.insert(cell_index + offset, Affine::constant(amount));
+			},
+			Instruction::Mul { amount, offset } => {
+				let target = cell_index + offset;
+				let mut new_value = value(&effects, target);
+				new_value.add_scaled(&value(&effects, cell_index), amount);
+				effects.insert(target, new_value);
+			},
+			Instruction::IncrDp { amount } => cell_index += amount,
+			// Anything else is not affine or not straight-line
+			_ => return None,
+		}
+	}
+
+	// The loop has to end up on the same cell it started on
+	if cell_index != 0 {
+		return None;
+	}
+
+	Some(effects)
+}
+
+/// Given the effects of a single iteration of a loop, compute the
+/// instructions that apply the remaining iterations all at once
+///
+/// A loop qualifies if every iteration decrements its own cell by exactly 1,
+/// and every other cell either stays the same (an invariant), gets set to a
+/// constant (a temporary), or has a combination of invariants and the loop
+/// counter added to it (an accumulator)
+///
+/// The returned instructions read the loop counter, so a Set(0) has to follow
+/// them
+fn closed_form(effects: &HashMap<i16, Affine>) -> Option<Vec<Instruction>> {
+	let mut counter = Affine::cell(0);
+	counter.add_scaled(&Affine::constant(-1), 1);
+	if effects.get(&0) != Some(&counter) {
+		return None;
+	}
+
+	let is_invariant = |ofst: &i16| {
+		match effects.get(ofst) {
+			Some(effect) => *effect == Affine::cell(*ofst),
+			None => true,
+		}
+	};
+
+	let mut result = vec![];
+	for (ofst, effect) in effects.iter().sorted_by_key(|(ofst, _)| **ofst) {
+		if *ofst == 0 || is_invariant(ofst) || effect.is_constant() {
+			continue;
+		}
+
+		// Accumulators must add to their own previous value exactly once
+		if effect.coeffs.get(ofst) != Some(&1) {
+			return None;
+		}
+
+		if effect.constant != 0 {
+			result.push(Instruction::Mul { amount: effect.constant, offset: *ofst });
+		}
+
+		for (source, coeff) in effect.coeffs.iter() {
+			if source == ofst {
+				continue;
+			}
+
+			if *source == 0 {
+				// The counter takes the values n, n - 1, ..., 1
+				result.push(Instruction::TriAcc { amount: *coeff, offset: *ofst });
+			} else if is_invariant(source) {
+				result.push(Instruction::MulAcc { amount: *coeff, source: *source, offset: *ofst });
+			} else {
+				return None;
 			}
 		}
+	}
+
+	Some(result)
+}
 
-		UnlinkedInstructions(result)
+/// Check if a loop body matches the nested linear loop pattern
+///
+/// If it does, return the nodes that replace the entire loop
+fn nested_loop_closed_form(insts: &[Instruction]) -> Option<Vec<Node>> {
+	// Plain multiply loops are handled by combine_multiply_loops
+	if !insts.iter().any(|i| matches!(i, Instruction::Set { .. } | Instruction::Mul { .. })) {
+		return None;
+	}
+
+	let effects = affine_effects(insts)?;
+
+	let temporaries: HashMap<i16, Cell> = effects
+		.iter()
+		.filter(|(ofst, effect)| **ofst != 0 && effect.is_constant())
+		.map(|(ofst, effect)| (*ofst, effect.constant))
+		.collect();
+
+	// If the closed form holds from the first iteration onwards the
+	// temporaries only need to be set when the loop runs at all
+	if let Some(updates) = closed_form(&effects) {
+		let mut result: Vec<Node> = updates.into_iter().map(Node::Inst).collect();
+
+		if temporaries.is_empty() {
+			result.push(Node::Inst(Instruction::Set { amount: 0, offset: 0 }));
+		} else {
+			for (ofst, amount) in temporaries.iter().sorted() {
+				result.push(Node::Inst(Instruction::Set { amount: *amount, offset: *ofst }));
+			}
+			result.push(Node::Inst(Instruction::Set { amount: 0, offset: 0 }));
+			result = vec![Node::Loop(result)];
+		}
+
+		return Some(result);
+	}
+
+	// Otherwise the first iteration usually only differs because the
+	// temporaries did not have their constant value yet, so peel it off and
+	// apply the closed form to the remaining iterations
+	if temporaries.is_empty() {
+		return None;
 	}
+
+	let peeled_effects: HashMap<i16, Affine> =
+		effects.iter().map(|(ofst, effect)| (*ofst, effect.substitute(&temporaries))).collect();
+	let updates = closed_form(&peeled_effects)?;
+
+	let mut body = insts.to_vec();
+	body.extend(updates);
+	body.push(Instruction::Set { amount: 0, offset: 0 });
+
+	Some(vec![Node::Loop(body.into_iter().map(Node::Inst).collect())])
 }
 
 /// Given a hashmap with sortable keys, return a vec of the values sorted by
@@ -263,12 +741,12 @@ fn reorder_sequence(insts: &[Instruction]) -> Vec<Instruction> {
 		match inst {
 			Instruction::Incr { amount, offset } => {
 				let new_offset = current_offset + offset;
-				let offset_vec = insts_by_offset.entry(new_offset).or_insert_with(Vec::new);
+				let offset_vec = insts_by_offset.entry(new_offset).or_default();
 				offset_vec.push(Instruction::Incr { amount: *amount, offset: new_offset });
 			},
 			Instruction::Set { amount, offset } => {
 				let new_offset = current_offset + offset;
-				let offset_vec = insts_by_offset.entry(new_offset).or_insert_with(Vec::new);
+				let offset_vec = insts_by_offset.entry(new_offset).or_default();
 				offset_vec.push(Instruction::Set { amount: *amount, offset: new_offset });
 			},
 			Instruction::IncrDp { amount } => {
@@ -294,28 +772,186 @@ fn reorder_sequence(insts: &[Instruction]) -> Vec<Instruction> {
 	result
 }
 
-/// Check if a series of instructions matches the multiply loop pattern
+/// Return the offset of the data pointer before each node, relative to where
+/// the data pointer was before the first one
 ///
-/// If it is, return the cells that are affected
-fn is_multiply_loop(insts: &[Instruction]) -> Option<HashMap<i16, Cell>> {
-	let mut net_movement = 0;
+/// Returns None if any loop or if inside the nodes has net movement, as the
+/// offsets after it are unknown
+fn cell_offsets(nodes: &[Node]) -> Option<Vec<i16>> {
+	let mut offsets = Vec::with_capacity(nodes.len());
+	let mut current_offset: i16 = 0;
+
+	for node in nodes {
+		offsets.push(current_offset);
+
+		match node {
+			Node::Inst(Instruction::IncrDp { amount }) => current_offset += amount,
+			Node::Loop(body) | Node::If(body) if net_movement(body) != Some(0) => return None,
+			_ => (),
+		}
+	}
+
+	Some(offsets)
+}
+
+/// Return the net movement of the data pointer over a series of nodes
+///
+/// Returns None if the movement is not statically known
+fn net_movement(nodes: &[Node]) -> Option<i16> {
+	let offsets = cell_offsets(nodes)?;
+
+	match (offsets.last(), nodes.last()) {
+		(Some(ofst), Some(Node::Inst(Instruction::IncrDp { amount }))) => Some(ofst + amount),
+		(Some(ofst), Some(_)) => Some(*ofst),
+		_ => Some(0),
+	}
+}
+
+/// The cells an instruction reads from and writes to, given the offset of
+/// the data pointer when it runs
+fn cell_accesses(inst: &Instruction, dp: i16) -> (Vec<i16>, Vec<i16>) {
+	match *inst {
+		Instruction::IncrDp { .. } => (vec![], vec![]),
+		Instruction::Incr { offset, .. } => (vec![dp + offset], vec![dp + offset]),
+		Instruction::Set { offset, .. } => (vec![], vec![dp + offset]),
+		Instruction::Mul { offset, .. } | Instruction::TriAcc { offset, .. } => {
+			(vec![dp, dp + offset], vec![dp + offset])
+		},
+		Instruction::MulAcc { source, offset, .. } => {
+			(vec![dp, dp + source, dp + offset], vec![dp + offset])
+		},
+		Instruction::Read => (vec![], vec![dp]),
+		Instruction::Write
+		| Instruction::BranchIfZero { .. }
+		| Instruction::BranchIfNotZero { .. }
+		| Instruction::If { .. } => (vec![dp], vec![]),
+		Instruction::EndIf => (vec![], vec![]),
+		Instruction::DivMod => (vec![dp, dp + 1], (0..=6).map(|o| dp + o).collect()),
+		Instruction::Compare => {
+			(vec![dp, dp + 1], [0, 2, 3, 4, 5, 6].iter().map(|o| dp + o).collect())
+		},
+		Instruction::WriteDecimal => (vec![dp], (1..=8).map(|o| dp + o).collect()),
+	}
+}
+
+/// The cells a node reads from and writes to, given the offset of the data
+/// pointer when it runs
+///
+/// Loops and ifs read their own cell, and access everything their body does
+fn node_accesses(node: &Node, dp: i16) -> (Vec<i16>, Vec<i16>) {
+	match node {
+		Node::Inst(inst) => cell_accesses(inst, dp),
+		Node::Loop(body) | Node::If(body) => {
+			let mut reads = vec![dp];
+			let mut writes = vec![];
+
+			// Unwrap is safe as the callers only look at balanced bodies
+			let offsets = cell_offsets(body).unwrap();
+			for (body_node, ofst) in body.iter().zip(offsets) {
+				let (body_reads, body_writes) = node_accesses(body_node, dp + ofst);
+				reads.extend(body_reads);
+				writes.extend(body_writes);
+			}
+
+			(reads, writes)
+		},
+	}
+}
+
+/// Check if a balanced loop body provably leaves its own cell at 0, meaning
+/// the loop runs at most once
+///
+/// The cell is known to be 0 after a Set(0) to it, or after a nested loop on
+/// it, as long as nothing writes to it afterwards
+fn runs_at_most_once(nodes: &[Node], offsets: &[i16]) -> bool {
+	let mut is_zero = false;
+
+	for (node, dp) in nodes.iter().zip(offsets) {
+		match node {
+			Node::Loop(_) if *dp == 0 => is_zero = true,
+			Node::Inst(Instruction::Set { amount, offset }) if dp + offset == 0 => {
+				is_zero = *amount == 0;
+			},
+			_ => {
+				let (_, writes) = node_accesses(node, *dp);
+				if writes.contains(&0) {
+					is_zero = false;
+				}
+			},
+		}
+	}
+
+	is_zero
+}
+
+/// Find the writes in a loop body that can be moved in front of the loop
+///
+/// The writes to a cell can be moved if none of them are inside a nested loop,
+/// the first is a Set and the others are Set or Incr, so the cell goes through
+/// the same values in every iteration, and the cell is only read after the
+/// last of them
+fn loop_invariants(nodes: &[Node], offsets: &[i16]) -> Vec<usize> {
+	let accesses: Vec<_> =
+		nodes.iter().zip(offsets).map(|(node, dp)| node_accesses(node, *dp)).collect();
+
+	let mut invariants = vec![];
 
+	for (idx, node) in nodes.iter().enumerate() {
+		let Node::Inst(Instruction::Set { offset, .. }) = node else {
+			continue;
+		};
+		let target = offsets[idx] + offset;
+		if target == 0 {
+			continue;
+		}
+
+		let writes: Vec<usize> =
+			(0..nodes.len()).filter(|other_idx| accesses[*other_idx].1.contains(&target)).collect();
+		// Only the first write of a cell starts a group
+		if writes[0] != idx {
+			continue;
+		}
+
+		let constant = writes.iter().all(|write_idx| {
+			matches!(
+				nodes[*write_idx],
+				Node::Inst(Instruction::Set { .. } | Instruction::Incr { .. })
+			)
+		});
+		let last = *writes.last().unwrap();
+		let only_read_after = (0..last).all(|other_idx| {
+			writes.contains(&other_idx) || !accesses[other_idx].0.contains(&target)
+		});
+
+		if constant && only_read_after {
+			invariants.extend(writes);
+		}
+	}
+
+	invariants.sort();
+	invariants
+}
+
+/// Check if a loop body matches the multiply loop pattern
+///
+/// If it is, return the cells that are affected
+fn is_multiply_loop(nodes: &[Node]) -> Option<HashMap<i16, Cell>> {
 	// Multiply loops can only contain Incr and IncrIp instructions
-	for inst in insts {
+	let insts = straight_line(nodes)?;
+	for inst in insts.iter() {
 		match inst {
-			Instruction::Incr { .. } => (),
-			Instruction::IncrDp { amount } => net_movement += amount,
+			Instruction::Incr { .. } | Instruction::IncrDp { .. } => (),
 			_ => return None,
 		}
 	}
 
 	// Multiply loops should have no net movement
-	if net_movement != 0 {
+	if net_movement(nodes) != Some(0) {
 		return None;
 	}
 
 	// Multiply loops must decrement their first cell to 0
-	let changes = cell_changes(insts);
+	let changes = cell_changes(&insts);
 	match changes.get(&0) {
 		Some(-1) => (),
 		_ => return None,
@@ -352,3 +988,189 @@ fn cell_changes(insts: &[Instruction]) -> HashMap<i16, Cell> {
 
 	changes
 }
+
+#[cfg(test)]
+mod tests {
+	use std::io::empty;
+
+	use super::*;
+	use crate::interpret::Interpreter;
+
+	/// Run a program on an empty tape, returning the final tape, data pointer
+	/// and output
+	fn run(insts: &LinkedInstructions) -> (Vec<u8>, u16, Vec<u8>) {
+		let mut interpreter = Interpreter::new(insts);
+		let mut output = vec![];
+		interpreter.run_with(&mut empty(), &mut output).unwrap();
+
+		(interpreter.memory().to_vec(), interpreter.dp(), output)
+	}
+
+	/// Check that a loop does the same optimised as unoptimised, with its
+	/// first three cells set to every combination of the given values, and
+	/// whether combining nested loops changes it
+	fn check_nested(body: &str, counters: &[u8], combined: bool) {
+		let values = [0, 1, 2, 5, 255];
+
+		for (a, b, c) in itertools::iproduct!(counters, values, values) {
+			let source = format!(
+				"{}>{}>{}<<{}",
+				"+".repeat(*a as usize),
+				"+".repeat(b as usize),
+				"+".repeat(c as usize),
+				body
+			);
+			let unlinked = UnlinkedInstructions::from_text(source.as_bytes());
+
+			let expected = run(&unlinked.clone().link().unwrap());
+			let optimised = unlinked.clone().optimise(&Optimisations::all()).unwrap();
+			assert_eq!(run(&optimised), expected, "{} on {}, {}, {}", body, a, b, c);
+
+			// Multiply loops come out in any order, but replacing a nested loop
+			// always changes the number of instructions
+			let without = Optimisations::all() - Optimisations::COMBINE_NESTED_LOOPS;
+			let unchanged = unlinked.optimise(&without).unwrap();
+			assert_eq!(optimised.0.len() != unchanged.0.len(), combined, "{}", body);
+		}
+	}
+
+	/// Optimise a program with every optimisation
+	fn optimise(source: &str) -> Vec<Instruction> {
+		UnlinkedInstructions::from_text(source.as_bytes())
+			.optimise(&Optimisations::all())
+			.unwrap()
+			.0
+	}
+
+	#[test]
+	fn nested_multiply() {
+		// MEM[2] += MEM[0] * MEM[1]
+		check_nested("[>[->+>+<<]>>[-<<+>>]<<<-]", &[0, 1, 3, 255], true);
+
+		let insts = optimise("[>[->+>+<<]>>[-<<+>>]<<<-]");
+		assert!(insts.contains(&Instruction::MulAcc { amount: 1, source: 1, offset: 2 }));
+	}
+
+	#[test]
+	fn nested_move() {
+		// The inner loop only does something in the first iteration
+		check_nested("[->[->+<]<]", &[0, 1, 3, 255], true);
+	}
+
+	#[test]
+	fn nested_triangle() {
+		// MEM[1] += MEM[0] + (MEM[0] - 1) + ... + 1
+		check_nested("[[->+>+<<]>>[-<<+>>]<<-]", &[0, 1, 3, 255], true);
+
+		let insts = optimise("[[->+>+<<]>>[-<<+>>]<<-]");
+		assert!(insts.contains(&Instruction::TriAcc { amount: 1, offset: 1 }));
+	}
+
+	#[test]
+	fn nested_unbalanced() { check_nested("[->[->+<]>]", &[0, 1, 3, 255], false); }
+
+	#[test]
+	fn nested_non_unit_counter() {
+		// Odd counters never reach 0
+		check_nested("[-->[->+>+<<]>>[-<<+>>]<<<]", &[0, 2, 6, 254], false);
+	}
+
+	/// Check that a loop does the same optimised as unoptimised, with its
+	/// counter set to a few values, and whether anything gets hoisted out of it
+	///
+	/// The loops write a cell, so no other pass replaces them
+	fn check_hoist(body: &str, opts: Optimisations, hoisted: bool) {
+		// The guard around a hoisted loop becomes an If
+		let count_loops = |insts: &LinkedInstructions| {
+			insts
+				.0
+				.iter()
+				.filter(|i| matches!(i, Instruction::BranchIfZero { .. } | Instruction::If { .. }))
+				.count()
+		};
+
+		for counter in [0, 1, 3] {
+			let source = format!("{}{}", "+".repeat(counter), body);
+			let unlinked = UnlinkedInstructions::from_text(source.as_bytes());
+
+			let expected = run(&unlinked.clone().link().unwrap());
+			let optimised = unlinked.clone().optimise(&opts).unwrap();
+			assert_eq!(run(&optimised), expected, "{} with {}", body, counter);
+
+			let without = opts - Optimisations::HOIST_LOOP_INVARIANTS;
+			let unchanged = unlinked.optimise(&without).unwrap();
+			assert_eq!(count_loops(&optimised) > count_loops(&unchanged), hoisted, "{}", body);
+		}
+	}
+
+	#[test]
+	fn hoist_set() { check_hoist("[->+>[-]<.<]", Optimisations::all(), true); }
+
+	#[test]
+	fn hoist_constant_cell() {
+		check_hoist("[->+>[-]+++<.<]", Optimisations::all(), true);
+		check_hoist("[->>[-]<+.>++<<]", Optimisations::all(), true);
+
+		// Without reordering the Incr stays apart from the Set
+		let opts = Optimisations::COMBINE_CLEARS
+			| Optimisations::GROUP_INSTRUCTIONS
+			| Optimisations::HOIST_LOOP_INVARIANTS;
+		check_hoist("[->>[-]<.>+++<<]", opts, true);
+	}
+
+	#[test]
+	fn hoist_read_after() { check_hoist("[->>[-]+.<.<]", Optimisations::all(), true); }
+
+	#[test]
+	fn no_hoist_read_before() { check_hoist("[->>.[-]<<]", Optimisations::all(), false); }
+
+	#[test]
+	fn no_hoist_read_between() { check_hoist("[->[-].+<]", Optimisations::all(), false); }
+
+	#[test]
+	fn no_hoist_other_writes() {
+		check_hoist("[->>[-]<[->+<]<]", Optimisations::all(), false);
+		check_hoist("[->>[-]<[>+<.-]<]", Optimisations::all(), false);
+	}
+
+	/// Check that a loop does the same with its body converted as unoptimised,
+	/// with its counter set to a few values, and whether it becomes an If
+	fn check_if(body: &str, converted: bool) {
+		let opts = Optimisations::COMBINE_CLEARS
+			| Optimisations::GROUP_INSTRUCTIONS
+			| Optimisations::CONVERT_IF_STATEMENTS;
+
+		for counter in [0, 1, 3] {
+			let source = format!("{}{}", "+".repeat(counter), body);
+			let unlinked = UnlinkedInstructions::from_text(source.as_bytes());
+
+			let expected = run(&unlinked.clone().link().unwrap());
+			let optimised = unlinked.optimise(&opts).unwrap();
+			assert_eq!(run(&optimised), expected, "{} with {}", body, counter);
+
+			let is_if = optimised.0.iter().any(|i| matches!(i, Instruction::If { .. }));
+			assert_eq!(is_if, converted, "{}", body);
+		}
+	}
+
+	#[test]
+	fn if_after_clear() {
+		check_if("[>+<[-]]", true);
+		check_if("[[-]>+<]", true);
+		check_if("[>+<[-]>.<]", true);
+	}
+
+	#[test]
+	fn if_nested() {
+		let insts = optimise("+[>[-]+[>+<[-]]<[-]]");
+		let ifs = insts.iter().filter(|i| matches!(i, Instruction::If { .. })).count();
+		assert_eq!(ifs, 2);
+	}
+
+	#[test]
+	fn no_if_without_trailing_zero() {
+		check_if("[-.]", false);
+		check_if("[[-]>+<+.-]", false);
+		check_if("[[-]>]", false);
+	}
+}
@@ -0,0 +1,315 @@
+//! A compact execution format and the interpreter running it
+//!
+//! Every instruction is packed into 8 bytes, with jumps stored relative to
+//! the instruction doing the jump. A program always ends in a Halt, and
+//! [`PackedProgram::new`] verifies every jump lands inside the program, so
+//! the dispatch loop can fetch instructions without bounds checks.
+//!
+//! This is an alternative backend, selected with `--backend packed`.
+//! [`Instruction`] stays the format every pass and the other backends work on.
+
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::error::Error;
+use crate::instruction::{Instruction, LinkedInstructions};
+use crate::interpret::{FullTape, MAX_TAPE_SIZE, Options, ShortTape, Tape, read_cell};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+	IncrDp,
+	Incr,
+	BranchIfZero,
+	BranchIfNotZero,
+	Read,
+	Write,
+	Set,
+	Mul,
+	MulAcc,
+	TriAcc,
+	If,
+	EndIf,
+	DivMod,
+	Compare,
+	WriteDecimal,
+	/// Stops the program, only the last instruction is a Halt
+	Halt,
+}
+
+/// A single packed instruction
+///
+/// The meaning of `offset` and `arg` depends on the opcode:
+///  - IncrDp: `arg` is the amount
+///  - Incr, Set, Mul, TriAcc: `offset` is the offset
+///  - MulAcc: `offset` is the offset, `arg` is the source
+///  - BranchIfZero, BranchIfNotZero, If: `arg` is the jump distance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C, align(8))]
+pub struct Op {
+	pub code:   OpCode,
+	pub amount: i8,
+	pub offset: i16,
+	pub arg:    i32,
+}
+
+impl Op {
+	fn new(code: OpCode) -> Self { Self { code, amount: 0, offset: 0, arg: 0 } }
+
+	fn with_amount(code: OpCode, amount: i8, offset: i16) -> Self {
+		Self { code, amount, offset, arg: 0 }
+	}
+
+	fn jump(code: OpCode, distance: i32) -> Self {
+		Self { code, amount: 0, offset: 0, arg: distance }
+	}
+}
+
+/// A verified program in the packed format
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackedProgram {
+	ops: Vec<Op>,
+}
+
+impl PackedProgram {
+	/// Pack linked instructions
+	///
+	/// Branches jump past their counterpart instead of onto it, which saves
+	/// a dispatch on every jump
+	pub fn new(insts: &LinkedInstructions) -> Result<Self, Error> {
+		let mut ops = Vec::with_capacity(insts.0.len() + 1);
+
+		for (idx, inst) in insts.0.iter().enumerate() {
+			// Jumps that are too far can't be packed, they will fail verification
+			let distance = |destination: u64| -> i32 {
+				i32::try_from(destination as i64 + 1 - idx as i64).unwrap_or(i32::MAX)
+			};
+
+			ops.push(match *inst {
+				Instruction::IncrDp { amount } => {
+					Op { code: OpCode::IncrDp, amount: 0, offset: 0, arg: amount as i32 }
+				},
+				Instruction::Incr { amount, offset } => {
+					Op::with_amount(OpCode::Incr, amount, offset)
+				},
+				Instruction::BranchIfZero { destination } => {
+					Op::jump(OpCode::BranchIfZero, distance(destination))
+				},
+				Instruction::BranchIfNotZero { destination } => {
+					Op::jump(OpCode::BranchIfNotZero, distance(destination))
+				},
+				Instruction::Read => Op::new(OpCode::Read),
+				Instruction::Write => Op::new(OpCode::Write),
+				Instruction::Set { amount, offset } => Op::with_amount(OpCode::Set, amount, offset),
+				Instruction::Mul { amount, offset } => Op::with_amount(OpCode::Mul, amount, offset),
+				Instruction::MulAcc { amount, source, offset } => {
+					Op { code: OpCode::MulAcc, amount, offset, arg: source as i32 }
+				},
+				Instruction::TriAcc { amount, offset } => {
+					Op::with_amount(OpCode::TriAcc, amount, offset)
+				},
+				Instruction::If { destination } => Op::jump(OpCode::If, distance(destination)),
+				Instruction::EndIf => Op::new(OpCode::EndIf),
+				Instruction::DivMod => Op::new(OpCode::DivMod),
+				Instruction::Compare => Op::new(OpCode::Compare),
+				Instruction::WriteDecimal => Op::new(OpCode::WriteDecimal),
+			});
+		}
+
+		ops.push(Op::new(OpCode::Halt));
+
+		let program = Self { ops };
+		program.verify()?;
+
+		Ok(program)
+	}
+
+	/// Check that the program ends in its only Halt, and that every jump
+	/// lands inside the program
+	///
+	/// Together these mean the instruction pointer can never leave the
+	/// program: it either jumps to a valid instruction, or steps to the next
+	/// one, which exists as the last instruction stops the program
+	fn verify(&self) -> Result<(), Error> {
+		for (idx, op) in self.ops.iter().enumerate() {
+			let is_last = idx == self.ops.len() - 1;
+
+			match op.code {
+				OpCode::BranchIfZero | OpCode::BranchIfNotZero | OpCode::If => {
+					let target = idx as i64 + op.arg as i64;
+					if target < 0 || target >= self.ops.len() as i64 {
+						return Err(Error::InvalidPackedProgram(idx));
+					}
+				},
+				OpCode::Halt if is_last => (),
+				OpCode::Halt => return Err(Error::InvalidPackedProgram(idx)),
+				_ if is_last => return Err(Error::InvalidPackedProgram(idx)),
+				_ => (),
+			}
+		}
+
+		Ok(())
+	}
+
+	/// The packed instructions, including the final Halt
+	pub fn ops(&self) -> &[Op] { &self.ops }
+}
+
+pub struct PackedInterpreter<'p> {
+	dp:      u16,
+	memory:  Box<[u8; MAX_TAPE_SIZE]>,
+	program: &'p PackedProgram,
+	options: Options,
+}
+
+impl<'p> PackedInterpreter<'p> {
+	pub fn new(program: &'p PackedProgram) -> Self {
+		Self::with_options(program, Options::default())
+	}
+
+	/// Create an interpreter for a differently configured machine, the tape
+	/// size gets clamped to `1..=MAX_TAPE_SIZE`
+	pub fn with_options(program: &'p PackedProgram, mut options: Options) -> Self {
+		options.tape_size = options.tape_size.clamp(1, MAX_TAPE_SIZE);
+
+		Self { dp: 0, memory: Box::new([0; MAX_TAPE_SIZE]), program, options }
+	}
+
+	/// Run the program
+	pub fn run(&mut self) -> Result<(), Error> {
+		let mut writer = BufWriter::new(std::io::stdout());
+		let mut reader = BufReader::new(std::io::stdin());
+
+		self.run_with(&mut reader, &mut writer)
+	}
+
+	/// Run the program, reading from and writing to the given streams instead
+	/// of stdin and stdout
+	pub fn run_with<R: Read, W: Write>(
+		&mut self,
+		reader: &mut R,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let result = match self.options.tape_size {
+			MAX_TAPE_SIZE => self.dispatch(FullTape, reader, writer),
+			size => self.dispatch(ShortTape(size), reader, writer),
+		};
+		writer.flush()?;
+
+		result
+	}
+
+	fn dispatch<T: Tape, R: Read, W: Write>(
+		&mut self,
+		tape: T,
+		reader: &mut R,
+		writer: &mut W,
+	) -> Result<(), Error> {
+		let ops = self.program.ops.as_slice();
+		let eof = self.options.eof;
+		let memory = &mut *self.memory;
+		let mut dp = self.dp;
+		let mut ip = 0usize;
+
+		// Any u16 is a valid index into the memory, and the tape makes sure
+		// only its own cells get used
+		macro_rules! cell {
+			($ofst:expr) => {
+				memory[tape.wrap(dp, $ofst as i32) as usize]
+			};
+		}
+
+		loop {
+			// SAFETY: ip starts at 0 and only ever moves to the next instruction
+			// or to a jump target, and `PackedProgram::verify` made sure every
+			// jump target is inside the program and the last instruction, which
+			// doesn't step any further, is a Halt
+			let op = unsafe { *ops.get_unchecked(ip) };
+
+			match op.code {
+				OpCode::IncrDp => dp = tape.wrap(dp, op.arg),
+				OpCode::Incr => cell!(op.offset) = cell!(op.offset).wrapping_add(op.amount as u8),
+				OpCode::BranchIfZero | OpCode::If => {
+					if cell!(0) == 0 {
+						ip = ip.wrapping_add(op.arg as isize as usize);
+						continue;
+					}
+				},
+				OpCode::BranchIfNotZero => {
+					if cell!(0) != 0 {
+						ip = ip.wrapping_add(op.arg as isize as usize);
+						continue;
+					}
+				},
+				OpCode::Read => {
+					writer.flush()?;
+					if let Err(e) = read_cell(reader, eof, &mut cell!(0)) {
+						self.dp = dp;
+						return Err(e);
+					}
+				},
+				OpCode::Write => writer.write_all(&[cell!(0)])?,
+				OpCode::Set => cell!(op.offset) = op.amount as u8,
+				OpCode::Mul => {
+					cell!(op.offset) =
+						cell!(op.offset).wrapping_add(cell!(0).wrapping_mul(op.amount as u8))
+				},
+				OpCode::MulAcc => {
+					let product =
+						cell!(0).wrapping_mul(cell!(op.arg)).wrapping_mul(op.amount as u8);
+					cell!(op.offset) = cell!(op.offset).wrapping_add(product);
+				},
+				OpCode::TriAcc => {
+					let n = cell!(0) as u32;
+					let triangle = (n * (n + 1) / 2) as u8;
+
+					cell!(op.offset) =
+						cell!(op.offset).wrapping_add(triangle.wrapping_mul(op.amount as u8));
+				},
+				OpCode::EndIf => (),
+				OpCode::DivMod => {
+					let n = cell!(0) as u16;
+					let d = match cell!(1) {
+						0 => 256,
+						d => d as u16,
+					};
+
+					cell!(0) = 0;
+					cell!(2) = (n % d) as u8;
+					cell!(3) = (n / d) as u8;
+					cell!(4) = (d - n % d) as u8;
+					cell!(5) = 0;
+					cell!(6) = 0;
+				},
+				OpCode::Compare => {
+					let x = cell!(0);
+					let y = cell!(1);
+
+					cell!(0) = (x < y) as u8;
+					for ofst in 2..=6 {
+						cell!(ofst) = 0;
+					}
+				},
+				OpCode::WriteDecimal => {
+					write!(writer, "{}", cell!(0))?;
+
+					for ofst in 1..=8 {
+						cell!(ofst) = 0;
+					}
+				},
+				OpCode::Halt => break,
+			}
+
+			ip += 1;
+		}
+
+		self.dp = dp;
+
+		Ok(())
+	}
+
+	/// The position of the data pointer
+	pub fn dp(&self) -> u16 { self.dp }
+
+	/// The contents of the tape
+	pub fn memory(&self) -> &[u8] { &self.memory[..self.options.tape_size] }
+}
@@ -0,0 +1,408 @@
+use std::fmt;
+use std::io::Write;
+
+use crate::error::Error;
+use crate::instruction::{Ast, LinkedInstructions, UnlinkedInstructions};
+use crate::optimise::{BUILTIN_PASSES, Optimisations};
+
+/// How often a pipeline, or a single pass running to its own fixpoint, gets
+/// repeated before giving up on convergence
+pub const DEFAULT_MAX_ITERATIONS: usize = 20;
+
+/// The pipelines used for the `-O0` to `-O3` optimisation levels
+///
+///  - `-O0`: no optimisations
+///  - `-O1`: peephole passes that only touch straight line code
+///  - `-O2`: also replaces loops with a statically known effect
+///  - `-O3`: everything, including idiom replacement and loop invariant hoisting
+pub const OPT_LEVELS: [&str; 4] = [
+	"",
+	"clears,group,reorder",
+	"clears,group,reorder,mul,ifs",
+	"idioms,clears,group*,reorder,mul,nested,hoist,ifs",
+];
+
+/// A single transformation of the instructions
+pub trait Pass {
+	/// The name used to refer to the pass in a pipeline
+	fn name(&self) -> &str;
+
+	/// A short description of what the pass does
+	fn description(&self) -> &str;
+
+	/// Run the pass once
+	fn run(&self, ast: Ast) -> Result<Ast, Error>;
+}
+
+/// A pass in the pipeline, and whether it should run until it stops changing
+/// the instructions
+struct Stage {
+	pass:     usize,
+	fixpoint: bool,
+}
+
+/// The outcome of running a pipeline
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PipelineReport {
+	/// How often the entire pipeline ran
+	pub iterations: usize,
+	/// Whether the pipeline, and every pass running to its own fixpoint,
+	/// stopped changing the instructions before running out of iterations
+	pub converged:  bool,
+}
+
+/// Runs an ordered pipeline of passes until it stops changing the
+/// instructions
+pub struct PassManager {
+	passes:         Vec<Box<dyn Pass>>,
+	pipeline:       Vec<Stage>,
+	max_iterations: usize,
+}
+
+impl Default for PassManager {
+	fn default() -> Self { Self::new() }
+}
+
+impl PassManager {
+	/// Create a pass manager that knows all built in passes, with an empty
+	/// pipeline
+	pub fn new() -> Self {
+		let mut passes: Vec<Box<dyn Pass>> = vec![];
+		for pass in BUILTIN_PASSES.iter() {
+			passes.push(Box::new(pass.clone()));
+		}
+
+		Self { passes, pipeline: vec![], max_iterations: DEFAULT_MAX_ITERATIONS }
+	}
+
+	/// Create a pass manager whose pipeline runs the requested optimisations
+	/// in their usual order
+	pub fn from_optimisations(opts: &Optimisations) -> Self {
+		let mut manager = Self::new();
+		for (idx, pass) in BUILTIN_PASSES.iter().enumerate() {
+			if opts.contains(pass.flag) {
+				manager.pipeline.push(Stage { pass: idx, fixpoint: false });
+			}
+		}
+
+		manager
+	}
+
+	/// Create a pass manager from a comma separated pipeline description,
+	/// see [`PassManager::push_pipeline`]
+	pub fn from_pipeline(spec: &str) -> Result<Self, Error> {
+		let mut manager = Self::new();
+		manager.push_pipeline(spec)?;
+
+		Ok(manager)
+	}
+
+	/// Create a pass manager running the pipeline of an optimisation level, see
+	/// [`OPT_LEVELS`]
+	pub fn from_level(level: u8) -> Result<Self, Error> {
+		match OPT_LEVELS.get(level as usize) {
+			Some(spec) => Self::from_pipeline(spec),
+			None => Err(Error::UnknownOptLevel(level)),
+		}
+	}
+
+	/// Make a pass available to pipelines
+	pub fn register(&mut self, pass: Box<dyn Pass>) -> Result<(), Error> {
+		if self.find(pass.name()).is_some() {
+			return Err(Error::DuplicatePass(pass.name().to_owned()));
+		}
+
+		self.passes.push(pass);
+		Ok(())
+	}
+
+	/// All passes available to pipelines
+	pub fn passes(&self) -> impl Iterator<Item = &dyn Pass> { self.passes.iter().map(|p| &**p) }
+
+	/// The pipeline in the format accepted by [`PassManager::push_pipeline`]
+	pub fn pipeline(&self) -> String { self.stages().join(",") }
+
+	/// Every pass in the pipeline, in order, ending in `*` if it runs to its
+	/// own fixpoint
+	pub fn stages(&self) -> Vec<String> {
+		self.pipeline
+			.iter()
+			.map(|stage| {
+				let name = self.passes[stage.pass].name();
+				if stage.fixpoint { format!("{}*", name) } else { name.to_owned() }
+			})
+			.collect()
+	}
+
+	/// Append a pass to the pipeline
+	///
+	/// If `fixpoint` is set, the pass is repeated until it stops changing the
+	/// instructions
+	pub fn push(&mut self, name: &str, fixpoint: bool) -> Result<(), Error> {
+		let pass = self.find(name).ok_or_else(|| Error::UnknownPass(name.to_owned()))?;
+		self.pipeline.push(Stage { pass, fixpoint });
+
+		Ok(())
+	}
+
+	/// Append a comma separated list of passes to the pipeline
+	///
+	/// A pass name ending in `*` runs to its own fixpoint,
+	/// eg. `clears,group*,reorder,mul,group`
+	pub fn push_pipeline(&mut self, spec: &str) -> Result<(), Error> {
+		for name in spec.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+			match name.strip_suffix('*') {
+				Some(name) => self.push(name, true)?,
+				None => self.push(name, false)?,
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Set how often the pipeline, or a pass running to its own fixpoint, may
+	/// be repeated
+	pub fn set_max_iterations(&mut self, max_iterations: usize) {
+		self.max_iterations = max_iterations;
+	}
+
+	/// Repeat the pipeline on the tree of the instructions until it stops
+	/// changing them, or the maximum number of iterations is reached, then
+	/// link the result
+	pub fn run(
+		&self,
+		insts: UnlinkedInstructions,
+	) -> Result<(LinkedInstructions, PipelineReport), Error> {
+		self.run_observed(insts, &mut [])
+	}
+
+	/// Same as [`PassManager::run`], but with only the first `stages` passes
+	/// of the pipeline
+	pub fn run_prefix(
+		&self,
+		insts: UnlinkedInstructions,
+		stages: usize,
+	) -> Result<(LinkedInstructions, PipelineReport), Error> {
+		let stages = stages.min(self.pipeline.len());
+		self.run_stages(&self.pipeline[..stages], insts, &mut [])
+	}
+
+	/// Same as [`PassManager::run`], but notifies the observers after every
+	/// pass
+	pub fn run_observed(
+		&self,
+		insts: UnlinkedInstructions,
+		observers: &mut [&mut dyn PassObserver],
+	) -> Result<(LinkedInstructions, PipelineReport), Error> {
+		self.run_stages(&self.pipeline, insts, observers)
+	}
+
+	/// Repeat some stages of the pipeline until they stop changing the
+	/// instructions, see [`PassManager::run`]
+	fn run_stages(
+		&self,
+		stages: &[Stage],
+		insts: UnlinkedInstructions,
+		observers: &mut [&mut dyn PassObserver],
+	) -> Result<(LinkedInstructions, PipelineReport), Error> {
+		let mut current = Ast::from_unlinked(&insts)?;
+		let mut report = PipelineReport { iterations: 0, converged: false };
+
+		while report.iterations < self.max_iterations {
+			report.iterations += 1;
+			let (next, stages_converged) =
+				self.run_stages_once(stages, current.clone(), report.iterations, observers)?;
+
+			if next == current {
+				report.converged = stages_converged;
+				break;
+			}
+
+			current = next;
+		}
+
+		Ok((current.to_linked(), report))
+	}
+
+	/// Run every pass in the pipeline once, or until its own fixpoint
+	///
+	/// Also returns whether every pass that should reach its fixpoint did
+	pub fn run_single_pass(
+		&self,
+		ast: Ast,
+		iteration: usize,
+		observers: &mut [&mut dyn PassObserver],
+	) -> Result<(Ast, bool), Error> {
+		self.run_stages_once(&self.pipeline, ast, iteration, observers)
+	}
+
+	/// Run some stages of the pipeline once, see
+	/// [`PassManager::run_single_pass`]
+	fn run_stages_once(
+		&self,
+		stages: &[Stage],
+		ast: Ast,
+		iteration: usize,
+		observers: &mut [&mut dyn PassObserver],
+	) -> Result<(Ast, bool), Error> {
+		let mut current = ast;
+		let mut converged = true;
+
+		for stage in stages {
+			let pass = &*self.passes[stage.pass];
+
+			if !stage.fixpoint {
+				current = run_pass(pass, current, iteration, observers)?;
+				continue;
+			}
+
+			let mut stage_converged = false;
+			for _ in 0..self.max_iterations {
+				let next = run_pass(pass, current.clone(), iteration, observers)?;
+				if next == current {
+					stage_converged = true;
+					break;
+				}
+
+				current = next;
+			}
+
+			converged &= stage_converged;
+		}
+
+		Ok((current, converged))
+	}
+
+	/// Find the index of the pass with the given name
+	fn find(&self, name: &str) -> Option<usize> {
+		self.passes.iter().position(|p| p.name() == name)
+	}
+}
+
+/// Run a single pass and notify the observers
+fn run_pass(
+	pass: &dyn Pass,
+	ast: Ast,
+	iteration: usize,
+	observers: &mut [&mut dyn PassObserver],
+) -> Result<Ast, Error> {
+	let before = ast.instruction_count();
+	let after = pass.run(ast)?;
+
+	for observer in observers.iter_mut() {
+		observer.after_pass(iteration, pass.name(), before, &after)?;
+	}
+
+	Ok(after)
+}
+
+/// Gets notified after every pass a [`PassManager`] runs
+pub trait PassObserver {
+	/// Called after `pass` ran during the given iteration of the pipeline,
+	/// `before` is the number of instructions the pass started with
+	fn after_pass(
+		&mut self,
+		iteration: usize,
+		pass: &str,
+		before: usize,
+		after: &Ast,
+	) -> Result<(), Error>;
+}
+
+/// The instruction count before and after a single run of a pass
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PassStats {
+	pub iteration: usize,
+	pub pass:      String,
+	pub before:    usize,
+	pub after:     usize,
+}
+
+/// Collects the instruction counts of every pass that runs
+#[derive(Clone, Debug, Default)]
+pub struct OptStats {
+	pub runs: Vec<PassStats>,
+}
+
+impl PassObserver for OptStats {
+	fn after_pass(
+		&mut self,
+		iteration: usize,
+		pass: &str,
+		before: usize,
+		after: &Ast,
+	) -> Result<(), Error> {
+		self.runs.push(PassStats {
+			iteration,
+			pass: pass.to_owned(),
+			before,
+			after: after.instruction_count(),
+		});
+		Ok(())
+	}
+}
+
+impl fmt::Display for OptStats {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let width = self.runs.iter().map(|r| r.pass.len()).max().unwrap_or(0).max(4);
+
+		writeln!(f, "{:>9}  {:width$}  {:>8}  {:>8}", "iteration", "pass", "before", "after")?;
+		for run in self.runs.iter() {
+			writeln!(
+				f,
+				"{:>9}  {:width$}  {:>8}  {:>8}",
+				run.iteration, run.pass, run.before, run.after
+			)?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Writes the symbolic code after every run of a single pass
+pub struct IrDump<W: Write> {
+	pass:   String,
+	writer: W,
+}
+
+impl<W: Write> IrDump<W> {
+	pub fn new(pass: &str, writer: W) -> Self { Self { pass: pass.to_owned(), writer } }
+
+	/// Get back the writer the dumps were written to
+	pub fn into_inner(self) -> W { self.writer }
+}
+
+impl<W: Write> PassObserver for IrDump<W> {
+	fn after_pass(
+		&mut self,
+		iteration: usize,
+		pass: &str,
+		_before: usize,
+		after: &Ast,
+	) -> Result<(), Error> {
+		if pass == self.pass {
+			writeln!(self.writer, "# after {}, iteration {}", pass, iteration)?;
+			self.writer.write_all(after.to_linked().to_symcode().as_bytes())?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn opt_stats_count_instructions() {
+		let passes = PassManager::from_pipeline("group").unwrap();
+		let mut stats = OptStats::default();
+		passes
+			.run_observed(UnlinkedInstructions::from_text(b"+++[->++<]"), &mut [&mut stats])
+			.unwrap();
+
+		// Both counts include the instructions inside the loop
+		let first = &stats.runs[0];
+		assert_eq!((first.pass.as_str(), first.before, first.after), ("group", 10, 7));
+	}
+}
@@ -0,0 +1,276 @@
+//! Finding implementation-defined behaviour a program relies on
+//!
+//! Interpreters disagree on the size of a cell, on what lies left of the
+//! first cell, on the length of the tape, and on what a read does at the end
+//! of the input. The linter proves some of these statically, and an
+//! instrumented run of the unoptimised program on some input catches the
+//! ones it actually runs into.
+
+use std::fmt;
+use std::io::Read;
+
+use itertools::Itertools;
+
+use crate::error::Error;
+use crate::instruction::{LinkedInstructions, UnlinkedInstructions};
+use crate::interpret::{Eof, Interpreter, MAX_TAPE_SIZE, Options, Usage};
+use crate::lint::{Lines, Position, Rule, TapeEnd, lint_all};
+use crate::verify::End;
+
+/// The number of cells the original implementation has, and every
+/// interpreter is expected to provide
+pub const PORTABLE_TAPE_SIZE: usize = 30_000;
+
+/// Implementation-defined behaviour
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reliance {
+	/// Cells wrapping around from 255 to 0 and back
+	CellWrap,
+	/// Moving left of the first cell
+	LeftOfOrigin,
+	/// Using more than [`PORTABLE_TAPE_SIZE`] cells
+	LargeTape,
+	/// Reading past the end of the input with one EOF behaviour, while
+	/// behaving differently with another
+	EofValue,
+}
+
+impl Reliance {
+	pub const ALL: [Self; 4] =
+		[Self::CellWrap, Self::LeftOfOrigin, Self::LargeTape, Self::EofValue];
+
+	fn label(self) -> &'static str {
+		match self {
+			Self::CellWrap => "cell wrap:",
+			Self::LeftOfOrigin => "left of 0:",
+			Self::LargeTape => "tape size:",
+			Self::EofValue => "eof value:",
+		}
+	}
+}
+
+impl fmt::Display for Reliance {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::CellWrap => write!(f, "8-bit cell wrap-around"),
+			Self::LeftOfOrigin => write!(f, "moving left of cell 0"),
+			Self::LargeTape => write!(f, "more than {} cells", PORTABLE_TAPE_SIZE),
+			Self::EofValue => write!(f, "a particular EOF value"),
+		}
+	}
+}
+
+/// Why a program relies on something
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+	pub reliance: Reliance,
+	pub evidence: String,
+}
+
+/// Everything a program was found to rely on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+	pub findings: Vec<Finding>,
+	/// Why the instrumented run stopped
+	pub end:      End,
+	pub steps:    u64,
+	/// The leftmost and rightmost cell the run used, negative if it moved
+	/// left of cell 0
+	pub cells:    (i64, i64),
+	/// Whether the run read past the end of the input
+	pub eof:      bool,
+}
+
+impl Report {
+	pub fn relies_on(&self, reliance: Reliance) -> bool {
+		self.findings.iter().any(|finding| finding.reliance == reliance)
+	}
+
+	/// Whether nothing implementation-defined was found
+	///
+	/// The run only covers the paths taken with its input, so this is no
+	/// proof the program is portable
+	pub fn is_portable(&self) -> bool { self.findings.is_empty() }
+}
+
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"run:        {} after {} steps, using cells {} to {}",
+			self.end, self.steps, self.cells.0, self.cells.1
+		)?;
+		if self.eof {
+			write!(f, ", reading past the end of the input")?;
+		}
+		writeln!(f)?;
+
+		for reliance in Reliance::ALL {
+			let mut findings = self.findings.iter().filter(|finding| finding.reliance == reliance);
+			match findings.next() {
+				Some(first) => {
+					writeln!(f, "{:11} relied on, {}", reliance.label(), first.evidence)?;
+					for finding in findings {
+						writeln!(f, "{:11} {}", "", finding.evidence)?;
+					}
+				},
+				None => writeln!(f, "{:11} no", reliance.label())?,
+			}
+		}
+
+		if self.is_portable() {
+			writeln!(f, "PORTABLE")
+		} else {
+			let reliances =
+				Reliance::ALL.into_iter().filter(|reliance| self.relies_on(*reliance)).join(", ");
+			writeln!(f, "NOT PORTABLE: relies on {}", reliances)
+		}
+	}
+}
+
+/// Check a program statically, and by running it on `input` for at most
+/// `max_steps` instructions
+pub fn analyse(source: &[u8], input: &[u8], max_steps: u64) -> Result<Report, Error> {
+	let mut findings = vec![];
+
+	// Silenced warnings still count, the program relies on the same things
+	for warning in lint_all(source)? {
+		let reliance = match warning.rule {
+			Rule::CellWrap => Reliance::CellWrap,
+			Rule::TapeWrap if warning.tape_end == Some(TapeEnd::Start) => Reliance::LeftOfOrigin,
+			Rule::TapeWrap => Reliance::LargeTape,
+			_ => continue,
+		};
+		findings.push(Finding { reliance, evidence: warning.to_string() });
+	}
+
+	let insts = UnlinkedInstructions::from_text(source).link()?;
+	let run = Run::new(&insts, input, Eof::Zero, max_steps)?;
+
+	// Unoptimised instructions are the commands of the source, in order
+	let commands: Vec<usize> = source.iter().positions(|byte| b"+-<>[].,".contains(byte)).collect();
+	let lines = Lines::new(source);
+	let position = |ip: usize| {
+		let Position { line, column } = lines.position(commands[ip]);
+		format!("{}:{}", line, column)
+	};
+
+	if let Some(ip) = run.usage.first_cell_wrap {
+		let evidence = format!("{}: a cell wrapped around during the run", position(ip));
+		findings.push(Finding { reliance: Reliance::CellWrap, evidence });
+	}
+	if let Some(ip) = run.usage.first_left {
+		let evidence = format!("{}: the run moved left of cell 0", position(ip));
+		findings.push(Finding { reliance: Reliance::LeftOfOrigin, evidence });
+	}
+	if run.usage.highest >= PORTABLE_TAPE_SIZE as i64 {
+		let evidence = format!("the run used cell {}", run.usage.highest);
+		findings.push(Finding { reliance: Reliance::LargeTape, evidence });
+	}
+
+	if run.eof {
+		for eof in [Eof::Unchanged, Eof::MinusOne] {
+			let other = Run::new(&insts, input, eof, max_steps)?;
+			if (other.end, &other.output) != (run.end, &run.output) {
+				let evidence = format!(
+					"the run behaves differently when EOF sets the cell to 0 and when it {}",
+					match eof {
+						Eof::Unchanged => "leaves it unchanged",
+						_ => "sets it to -1",
+					}
+				);
+				findings.push(Finding { reliance: Reliance::EofValue, evidence });
+			}
+		}
+	}
+
+	Ok(Report {
+		findings,
+		end: run.end,
+		steps: run.steps,
+		cells: (run.usage.lowest, run.usage.highest),
+		eof: run.eof,
+	})
+}
+
+/// An instrumented run of a program
+struct Run {
+	end:    End,
+	steps:  u64,
+	output: Vec<u8>,
+	usage:  Usage,
+	/// Whether the program read past the end of its input
+	eof:    bool,
+}
+
+impl Run {
+	fn new(
+		insts: &LinkedInstructions,
+		input: &[u8],
+		eof: Eof,
+		max_steps: u64,
+	) -> Result<Self, Error> {
+		let mut interpreter =
+			Interpreter::instrumented(insts, Options { tape_size: MAX_TAPE_SIZE, eof });
+		let mut reader = Input { bytes: input, exhausted: false };
+		let mut output = vec![];
+
+		let end = match interpreter.run_limited(&mut reader, &mut output, max_steps)? {
+			true => End::Halted,
+			false => End::StepLimit,
+		};
+
+		Ok(Self {
+			end,
+			steps: interpreter.steps(),
+			output,
+			// Unwrap is safe as the interpreter is instrumented
+			usage: interpreter.usage().unwrap().clone(),
+			eof: reader.exhausted,
+		})
+	}
+}
+
+/// Input that notices when it is read past its end
+struct Input<'a> {
+	bytes:     &'a [u8],
+	exhausted: bool,
+}
+
+impl Read for Input<'_> {
+	fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+		let read = self.bytes.read(buffer)?;
+		if read == 0 && !buffer.is_empty() {
+			self.exhausted = true;
+		}
+
+		Ok(read)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// What a program relies on, found without running it
+	fn reliances(source: &str) -> Vec<Reliance> {
+		let report = analyse(source.as_bytes(), b"", 0).unwrap();
+		Reliance::ALL.into_iter().filter(|reliance| report.relies_on(*reliance)).collect()
+	}
+
+	#[test]
+	fn tape_wrap_direction() {
+		assert_eq!(reliances("<+"), [Reliance::LeftOfOrigin]);
+		assert_eq!(
+			reliances(&format!("{}+", ">".repeat(MAX_TAPE_SIZE + 1))),
+			[Reliance::LargeTape]
+		);
+		assert_eq!(reliances(",[>]<+"), []);
+	}
+
+	#[test]
+	fn cell_wrap() {
+		assert_eq!(reliances("-"), [Reliance::CellWrap]);
+		assert_eq!(reliances(",-"), []);
+	}
+}
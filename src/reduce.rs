@@ -0,0 +1,199 @@
+//! Delta debugging of optimiser miscompilations
+//!
+//! Starting from a program that behaves differently after optimisation, parts
+//! of the program, and optionally of its input, get removed for as long as
+//! the optimised and unoptimised runs keep disagreeing in the same way.
+
+use std::mem::discriminant;
+
+use crate::error::Error;
+use crate::instruction::UnlinkedInstructions;
+use crate::pass::PassManager;
+use crate::verify::{Divergence, verify_linked};
+
+/// A minimised program and input that still trigger the miscompilation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reduction {
+	pub source:     Vec<u8>,
+	pub input:      Vec<u8>,
+	/// The divergence of the reduced program
+	pub divergence: Divergence,
+	/// How often a candidate was verified
+	pub tests:      usize,
+}
+
+/// Shrinks programs while they keep diverging
+struct Reducer<'p> {
+	passes:    &'p PassManager,
+	max_steps: u64,
+	/// The kind of divergence every candidate has to keep showing
+	original:  Divergence,
+	tests:     usize,
+}
+
+impl<'p> Reducer<'p> {
+	/// Check if a candidate still diverges like the original program, returning
+	/// its divergence if so
+	fn diverges(&mut self, source: &[u8], input: &[u8]) -> Option<Divergence> {
+		self.tests += 1;
+
+		// Candidates with unbalanced brackets fail to link, and are simply not
+		// interesting
+		let divergence = divergence(source, self.passes, input, self.max_steps).ok()?;
+		divergence.filter(|d| discriminant(d) == discriminant(&self.original))
+	}
+
+	/// Remove as large chunks of `items` as possible while `test` holds, using
+	/// the complement-only variant of ddmin
+	fn ddmin(&mut self, items: Vec<u8>, mut test: impl FnMut(&mut Self, &[u8]) -> bool) -> Vec<u8> {
+		let mut items = items;
+		let mut granularity = 2;
+
+		while items.len() >= 2 {
+			let chunk = items.len().div_ceil(granularity);
+
+			let reduced = (0..items.len()).step_by(chunk).find_map(|start| {
+				let end = (start + chunk).min(items.len());
+				let complement = [&items[..start], &items[end..]].concat();
+
+				if test(self, &complement) { Some(complement) } else { None }
+			});
+
+			match reduced {
+				Some(complement) => {
+					items = complement;
+					granularity = (granularity - 1).max(2);
+				},
+				None if granularity >= items.len() => break,
+				None => granularity = (granularity * 2).min(items.len()),
+			}
+		}
+
+		// A single remaining item can still be removed entirely
+		if items.len() == 1 && test(self, &[]) {
+			items.clear();
+		}
+
+		items
+	}
+
+	/// Try removing every loop, and every pair of brackets while keeping the
+	/// body, returning whether anything got removed
+	fn simplify_loops(&mut self, source: &mut Vec<u8>, input: &[u8]) -> bool {
+		let mut changed = false;
+		let mut idx = 0;
+
+		while idx < source.len() {
+			if source[idx] != b'[' {
+				idx += 1;
+				continue;
+			}
+
+			let close = match matching_bracket(source, idx) {
+				Some(close) => close,
+				None => return changed,
+			};
+
+			let without_loop = [&source[..idx], &source[close + 1..]].concat();
+			if self.diverges(&without_loop, input).is_some() {
+				*source = without_loop;
+				changed = true;
+				continue;
+			}
+
+			let unwrapped =
+				[&source[..idx], &source[idx + 1..close], &source[close + 1..]].concat();
+			if self.diverges(&unwrapped, input).is_some() {
+				*source = unwrapped;
+				changed = true;
+				continue;
+			}
+
+			idx += 1;
+		}
+
+		changed
+	}
+}
+
+/// Find the `]` belonging to the `[` at `open`
+fn matching_bracket(source: &[u8], open: usize) -> Option<usize> {
+	let mut depth = 0;
+	for (idx, c) in source.iter().enumerate().skip(open) {
+		match c {
+			b'[' => depth += 1,
+			b']' => {
+				depth -= 1;
+				if depth == 0 {
+					return Some(idx);
+				}
+			},
+			_ => (),
+		}
+	}
+
+	None
+}
+
+/// Shrink a program that behaves differently after being optimised by
+/// `passes`, and its input if `reduce_input` is set
+///
+/// Comments are dropped immediately, after which chunks of commands and
+/// whole loops are removed until no single removal keeps the divergence.
+/// Returns `None` if the program doesn't diverge in the first place.
+pub fn reduce(
+	source: &[u8],
+	input: &[u8],
+	passes: &PassManager,
+	max_steps: u64,
+	reduce_input: bool,
+) -> Result<Option<Reduction>, Error> {
+	let original = match divergence(source, passes, input, max_steps)? {
+		Some(divergence) => divergence,
+		None => return Ok(None),
+	};
+
+	let mut reducer = Reducer { passes, max_steps, original, tests: 1 };
+
+	let commands: Vec<u8> = source.iter().copied().filter(|c| b"+-<>[].,".contains(c)).collect();
+	let mut source =
+		if reducer.diverges(&commands, input).is_some() { commands } else { source.to_vec() };
+	let mut input = input.to_vec();
+
+	loop {
+		let size = (source.len(), input.len());
+
+		let current_input = input.clone();
+		source =
+			reducer.ddmin(source, |r, candidate| r.diverges(candidate, &current_input).is_some());
+		while reducer.simplify_loops(&mut source, &input) {}
+
+		if reduce_input {
+			let current_source = source.clone();
+			input = reducer
+				.ddmin(input, |r, candidate| r.diverges(&current_source, candidate).is_some());
+		}
+
+		if (source.len(), input.len()) == size {
+			break;
+		}
+	}
+
+	// Unwrap is safe as every accepted candidate diverges
+	let divergence = reducer.diverges(&source, &input).unwrap();
+
+	Ok(Some(Reduction { source, input, divergence, tests: reducer.tests }))
+}
+
+/// Verify a program, without looking for the pass that caused a divergence
+fn divergence(
+	source: &[u8],
+	passes: &PassManager,
+	input: &[u8],
+	max_steps: u64,
+) -> Result<Option<Divergence>, Error> {
+	let unoptimised = UnlinkedInstructions::from_text(source).link()?;
+	let (optimised, _) = passes.run(UnlinkedInstructions::from_text(source))?;
+
+	Ok(verify_linked(&unoptimised, &optimised, "", input, max_steps)?.divergence)
+}
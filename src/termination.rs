@@ -0,0 +1,316 @@
+//! Deciding whether loops stop
+//!
+//! Only loops that leave the data pointer where it was are classified. Their
+//! own cell is then the same cell on every iteration, and if the body only
+//! ever adds a constant to it, or leaves it at a constant, the values it goes
+//! through are known. Cells wrap around at 8 bits, so adding an odd number
+//! always reaches 0 eventually, while adding 2 never does from an odd value.
+//!
+//! Where a loop starts, the [`interval::Analysis`] of the program tells which
+//! values its cell can have. Programs run on a tape of [`MAX_TAPE_SIZE`]
+//! cells, and a loop that may wrap around it is not classified, as two
+//! offsets in its body can be the same cell then.
+//!
+//! [`interval::Analysis`]: crate::interval::Analysis
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::instruction::{Instruction, LinkedInstructions};
+use crate::interpret::MAX_TAPE_SIZE;
+use crate::interval::{Analysis, Interval};
+
+/// Whether a loop stops once it is entered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+	Terminating,
+	NonTerminating,
+	Unknown,
+}
+
+impl fmt::Display for Termination {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Terminating => write!(f, "terminating"),
+			Self::NonTerminating => write!(f, "non-terminating"),
+			Self::Unknown => write!(f, "unknown"),
+		}
+	}
+}
+
+/// A loop and whether it stops
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Loop {
+	/// The index of the `[`
+	pub start:          usize,
+	/// The index of the `]`
+	pub end:            usize,
+	pub termination:    Termination,
+	pub reason:         String,
+	/// Whether the loop may be entered at all
+	pub reachable:      bool,
+	/// Whether the loop is entered every time it is reached, as its cell is
+	/// never 0 there
+	pub always_entered: bool,
+}
+
+impl Loop {
+	/// Whether the program hangs once it gets to the loop
+	pub fn hangs(&self) -> bool {
+		self.termination == Termination::NonTerminating && self.always_entered
+	}
+}
+
+/// Classify every loop of a program, ordered by where they start
+pub fn classify(insts: &LinkedInstructions) -> Vec<Loop> {
+	let analysis = Analysis::new(insts, MAX_TAPE_SIZE);
+	let mut loops: HashMap<usize, Loop> = HashMap::new();
+
+	// Nested loops start later, so they are done before the loops around them
+	let starts: Vec<(usize, usize)> = insts
+		.0
+		.iter()
+		.enumerate()
+		.filter_map(|(index, inst)| {
+			match inst {
+				Instruction::BranchIfZero { destination } => Some((index, *destination as usize)),
+				_ => None,
+			}
+		})
+		.collect();
+
+	for (start, end) in starts.into_iter().rev() {
+		// Later iterations jump back to the `[` as well, only the values from
+		// before the loop tell what it starts with
+		let entry = match start {
+			0 => Some(Interval::constant(0)),
+			_ => analysis.cell_after(start - 1, 0),
+		};
+		let wraps = !(start..=end).all(|index| analysis.in_bounds(index));
+		let (mut termination, mut reason) = match effect(&insts.0, start, end) {
+			None => (Termination::Unknown, "the loop moves the data pointer".to_owned()),
+			Some(_) if wraps => {
+				(Termination::Unknown, "the loop may wrap around the tape".to_owned())
+			},
+			Some(effect) => verdict(&effect, entry),
+		};
+
+		let nested_stop = (start + 1..end).all(|index| {
+			loops.get(&index).is_none_or(|nested| nested.termination == Termination::Terminating)
+		});
+		if termination == Termination::Terminating && !nested_stop {
+			termination = Termination::Unknown;
+			reason = "a nested loop may not stop".to_owned();
+		}
+
+		let reachable = entry.is_some_and(|entry| entry != Interval::constant(0));
+		let always_entered = entry.is_some_and(|entry| !entry.contains(0));
+		loops.insert(start, Loop { start, end, termination, reason, reachable, always_entered });
+	}
+
+	let mut loops: Vec<Loop> = loops.into_values().collect();
+	loops.sort_by_key(|l| l.start);
+
+	loops
+}
+
+/// The value of a loop's own cell after an iteration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Counter {
+	/// The value before the iteration plus a constant, wrapping around
+	Delta(u8),
+	Constant(u8),
+	Unknown,
+}
+
+impl Counter {
+	fn add(self, amount: u8) -> Self {
+		match self {
+			Self::Delta(delta) => Self::Delta(delta.wrapping_add(amount)),
+			Self::Constant(value) => Self::Constant(value.wrapping_add(amount)),
+			Self::Unknown => Self::Unknown,
+		}
+	}
+}
+
+/// What one iteration of a balanced loop body does
+struct Effect {
+	counter: Counter,
+	/// The offsets of every cell the body may write
+	written: BTreeSet<i64>,
+}
+
+impl Effect {
+	fn write(&mut self, offset: i64, counter: Counter) {
+		self.written.insert(offset);
+		if offset == 0 {
+			self.counter = counter;
+		}
+	}
+}
+
+/// The effect of the body of the loop or if from `start` to `end`, or `None`
+/// if it moves the data pointer
+fn effect(insts: &[Instruction], start: usize, end: usize) -> Option<Effect> {
+	let mut body = Effect { counter: Counter::Delta(0), written: BTreeSet::new() };
+	let mut offset = 0;
+	let mut index = start + 1;
+
+	while index < end {
+		match insts[index] {
+			Instruction::IncrDp { amount } => offset += amount as i64,
+			Instruction::Incr { amount, offset: o } => {
+				let position = offset + o as i64;
+				body.write(position, body.counter.add(amount as u8));
+			},
+			Instruction::Set { amount, offset: o } => {
+				body.write(offset + o as i64, Counter::Constant(amount as u8));
+			},
+			Instruction::Mul { offset: o, .. }
+			| Instruction::MulAcc { offset: o, .. }
+			| Instruction::TriAcc { offset: o, .. } => {
+				body.write(offset + o as i64, Counter::Unknown);
+			},
+			Instruction::Read => body.write(offset, Counter::Unknown),
+			Instruction::DivMod => {
+				body.write(offset, Counter::Constant(0));
+				for o in 2..=4 {
+					body.write(offset + o, Counter::Unknown);
+				}
+				body.write(offset + 5, Counter::Constant(0));
+				body.write(offset + 6, Counter::Constant(0));
+			},
+			Instruction::Compare => {
+				body.write(offset, Counter::Unknown);
+				for o in 2..=6 {
+					body.write(offset + o, Counter::Constant(0));
+				}
+			},
+			Instruction::WriteDecimal => {
+				for o in 1..=8 {
+					body.write(offset + o, Counter::Constant(0));
+				}
+			},
+			Instruction::BranchIfZero { destination } | Instruction::If { destination } => {
+				let nested = effect(insts, index, destination as usize)?;
+				for written in nested.written {
+					body.write(offset + written, Counter::Unknown);
+				}
+				// Both loops and ifs end with their cell at 0
+				body.write(offset, Counter::Constant(0));
+				index = destination as usize;
+			},
+			Instruction::Write | Instruction::BranchIfNotZero { .. } | Instruction::EndIf => (),
+		}
+
+		index += 1;
+	}
+
+	(offset == 0).then_some(body)
+}
+
+/// Whether a loop stops given what an iteration does, and the values its cell
+/// may have when the loop is reached, `None` if it never is
+fn verdict(effect: &Effect, entry: Option<Interval>) -> (Termination, String) {
+	if !effect.written.contains(&0) {
+		return (Termination::NonTerminating, "its cell is never written".to_owned());
+	}
+
+	match effect.counter {
+		Counter::Unknown => {
+			let reason = "its cell changes by an amount that isn't constant";
+			(Termination::Unknown, reason.to_owned())
+		},
+		Counter::Constant(0) => {
+			(Termination::Terminating, "its cell is 0 after the first iteration".to_owned())
+		},
+		Counter::Constant(value) => {
+			let reason = format!("its cell is {} after every iteration", value);
+			(Termination::NonTerminating, reason)
+		},
+		Counter::Delta(0) => {
+			let reason = "its cell is the same after every iteration";
+			(Termination::NonTerminating, reason.to_owned())
+		},
+		Counter::Delta(delta) => {
+			// Adding `delta` reaches 0 from exactly the multiples of its largest
+			// power of two factor
+			let step = 1i64 << delta.trailing_zeros();
+			let changes = format!("its cell changes by {} every iteration", delta as i8);
+			if step == 1 {
+				return (Termination::Terminating, changes);
+			}
+
+			// The loop is only entered with a cell that isn't 0, and one that
+			// never is gets judged on its own
+			let entry = entry.filter(|entry| entry.hi > 0).unwrap_or(Interval::CELL);
+			let entry = Interval { lo: entry.lo.max(1), hi: entry.hi };
+			let first_multiple = (entry.lo + step - 1) / step * step;
+
+			if first_multiple > entry.hi {
+				let reason = format!("{}, and is never a multiple of {}", changes, step);
+				(Termination::NonTerminating, reason)
+			} else if entry.as_constant().is_some() {
+				(Termination::Terminating, format!("{}, starting at {}", changes, entry.lo))
+			} else {
+				let reason =
+					format!("{}, which only reaches 0 from multiples of {}", changes, step);
+				(Termination::Unknown, reason)
+			}
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::instruction::UnlinkedInstructions;
+
+	/// The termination of every loop in unoptimised source, and whether it
+	/// hangs the program
+	fn classify_source(source: &str) -> Vec<(Termination, bool)> {
+		let insts = UnlinkedInstructions::from_text(source.as_bytes()).link().unwrap();
+		classify(&insts).iter().map(|l| (l.termination, l.hangs())).collect()
+	}
+
+	#[test]
+	fn never_written() {
+		assert_eq!(classify_source("+[]"), [(Termination::NonTerminating, true)]);
+		assert_eq!(classify_source(",[>+<]"), [(Termination::NonTerminating, false)]);
+	}
+
+	#[test]
+	fn delta() {
+		assert_eq!(classify_source(",[-]"), [(Termination::Terminating, false)]);
+		assert_eq!(classify_source("+[--]"), [(Termination::NonTerminating, true)]);
+		assert_eq!(classify_source("++[--]"), [(Termination::Terminating, false)]);
+		assert_eq!(classify_source(",[--]"), [(Termination::Unknown, false)]);
+		assert_eq!(classify_source("+[+-]"), [(Termination::NonTerminating, true)]);
+	}
+
+	#[test]
+	fn constant() {
+		let inner = (Termination::Terminating, false);
+		assert_eq!(classify_source("+[[-]]"), [(Termination::Terminating, false), inner]);
+		assert_eq!(classify_source("+[[-]+]"), [(Termination::NonTerminating, true), inner]);
+	}
+
+	#[test]
+	fn nested() {
+		let inner = (Termination::Unknown, false);
+		assert_eq!(classify_source("+[->,[--]<]"), [(Termination::Unknown, false), inner]);
+	}
+
+	#[test]
+	fn unbalanced() {
+		assert_eq!(classify_source("+[>]"), [(Termination::Unknown, false)]);
+	}
+
+	#[test]
+	fn tape_wrap() {
+		// The pointer comes back to the decremented cell, so the loop is
+		// never entered
+		let source = format!("-{}+[--]", ">".repeat(MAX_TAPE_SIZE));
+		assert_eq!(classify_source(&source), [(Termination::Unknown, false)]);
+	}
+}
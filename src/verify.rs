@@ -0,0 +1,296 @@
+//! Differential testing of the optimiser
+//!
+//! A program gets run once without and once with optimisations on the same
+//! input, after which the output, the data pointer, and the tape of both runs
+//! are compared.
+
+use std::fmt;
+
+use crate::error::Error;
+use crate::instruction::{LinkedInstructions, UnlinkedInstructions};
+use crate::interpret::Interpreter;
+use crate::pass::PassManager;
+
+/// How many instructions a program may execute by default before it is
+/// considered to hang
+pub const DEFAULT_MAX_STEPS: u64 = 10_000_000;
+
+/// Why a run stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum End {
+	/// The program ran past its last instruction
+	Halted,
+	/// The program tried to read more input than there was
+	OutOfInput,
+	/// The program executed the maximum number of instructions
+	StepLimit,
+}
+
+impl fmt::Display for End {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Halted => write!(f, "halted"),
+			Self::OutOfInput => write!(f, "ran out of input"),
+			Self::StepLimit => write!(f, "hit the step limit"),
+		}
+	}
+}
+
+/// The observable result of running a program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Execution {
+	pub end:    End,
+	pub steps:  u64,
+	pub output: Vec<u8>,
+	pub dp:     u16,
+	pub memory: Vec<u8>,
+}
+
+/// Run a program on the given input for at most `max_steps` instructions
+pub fn execute(
+	insts: &LinkedInstructions,
+	input: &[u8],
+	max_steps: u64,
+) -> Result<Execution, Error> {
+	let mut interpreter = Interpreter::new(insts);
+	let mut reader = input;
+	let mut output = vec![];
+
+	let end = match interpreter.run_limited(&mut reader, &mut output, max_steps) {
+		Ok(true) => End::Halted,
+		Ok(false) => End::StepLimit,
+		Err(Error::CouldNotReadInput) => End::OutOfInput,
+		Err(e) => return Err(e),
+	};
+
+	Ok(Execution {
+		end,
+		steps: interpreter.steps(),
+		output,
+		dp: interpreter.dp(),
+		memory: interpreter.memory().to_vec(),
+	})
+}
+
+/// The first observable difference between two runs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+	/// The byte written at `position` differs, `None` if a run stopped before
+	/// writing it
+	Output { position: usize, expected: Option<u8>, actual: Option<u8> },
+	/// The runs stopped for different reasons
+	End { expected: End, actual: End },
+	/// The data pointer ended up in a different place
+	DataPointer { expected: u16, actual: u16 },
+	/// A cell on the tape holds a different value
+	Tape { cell: usize, expected: u8, actual: u8 },
+}
+
+impl fmt::Display for Divergence {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let byte = |b: &Option<u8>| b.map_or("nothing".to_owned(), |b| format!("{:#04x}", b));
+
+		match self {
+			Self::Output { position, expected, actual } => {
+				write!(
+					f,
+					"output byte {} is {} instead of {}",
+					position,
+					byte(actual),
+					byte(expected)
+				)
+			},
+			Self::End { expected, actual } => {
+				write!(f, "the optimised program {} instead of {}", actual, expected)
+			},
+			Self::DataPointer { expected, actual } => {
+				write!(f, "the data pointer ends at {} instead of {}", actual, expected)
+			},
+			Self::Tape { cell, expected, actual } => {
+				write!(f, "cell {} holds {} instead of {}", cell, actual, expected)
+			},
+		}
+	}
+}
+
+/// Find the first difference between a reference run and another run
+///
+/// Output is compared as far as both runs got, the end state only if neither
+/// hit the step limit
+pub fn compare(expected: &Execution, actual: &Execution) -> Option<Divergence> {
+	let output_divergence =
+		expected.output.iter().zip(actual.output.iter()).position(|(e, a)| e != a).or_else(|| {
+			let shortest = expected.output.len().min(actual.output.len());
+			let expected_stopped = expected.end != End::StepLimit;
+			let actual_stopped = actual.end != End::StepLimit;
+
+			// A run that stopped can't produce any more output, a run that hit
+			// the step limit might have
+			if (expected_stopped && shortest < actual.output.len())
+				|| (actual_stopped && shortest < expected.output.len())
+			{
+				Some(shortest)
+			} else {
+				None
+			}
+		});
+
+	if let Some(position) = output_divergence {
+		return Some(Divergence::Output {
+			position,
+			expected: expected.output.get(position).copied(),
+			actual: actual.output.get(position).copied(),
+		});
+	}
+
+	if expected.end == End::StepLimit || actual.end == End::StepLimit {
+		// Optimised code may take fewer steps, so it finishing first is fine
+		if actual.end == End::StepLimit && expected.end != End::StepLimit {
+			return Some(Divergence::End { expected: expected.end, actual: actual.end });
+		}
+
+		return None;
+	}
+
+	if expected.end != actual.end {
+		return Some(Divergence::End { expected: expected.end, actual: actual.end });
+	}
+
+	if expected.dp != actual.dp {
+		return Some(Divergence::DataPointer { expected: expected.dp, actual: actual.dp });
+	}
+
+	expected.memory.iter().zip(actual.memory.iter()).position(|(e, a)| e != a).map(|cell| {
+		Divergence::Tape { cell, expected: expected.memory[cell], actual: actual.memory[cell] }
+	})
+}
+
+/// The pass that made an optimised program diverge
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Culprit {
+	/// The pass as it appears in the pipeline
+	pub pass:     String,
+	/// The shortest prefix of the pipeline that diverges, ending in `pass`
+	pub pipeline: String,
+}
+
+/// The outcome of comparing an unoptimised and an optimised run
+#[derive(Clone, Debug)]
+pub struct Report {
+	/// The pipeline used to optimise the program
+	pub pipeline:    String,
+	pub unoptimised: Execution,
+	pub optimised:   Execution,
+	pub divergence:  Option<Divergence>,
+	/// The first pass of the pipeline after which the program diverges, only
+	/// looked for by [`verify`]
+	pub culprit:     Option<Culprit>,
+}
+
+impl Report {
+	/// Whether the unoptimised run finished, if not only a prefix of the
+	/// output could be compared
+	pub fn is_conclusive(&self) -> bool { self.unoptimised.end != End::StepLimit }
+}
+
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let pipeline = if self.pipeline.is_empty() { "(none)" } else { &self.pipeline };
+		writeln!(f, "pipeline:    {}", pipeline)?;
+		writeln!(
+			f,
+			"unoptimised: {} after {} steps, {} bytes of output",
+			self.unoptimised.end,
+			self.unoptimised.steps,
+			self.unoptimised.output.len()
+		)?;
+		writeln!(
+			f,
+			"optimised:   {} after {} steps, {} bytes of output",
+			self.optimised.end,
+			self.optimised.steps,
+			self.optimised.output.len()
+		)?;
+
+		match &self.divergence {
+			Some(divergence) => {
+				writeln!(f, "DIVERGED:    {}", divergence)?;
+				match &self.culprit {
+					Some(culprit) => {
+						writeln!(
+							f,
+							"introduced:  by {}, running {} already diverges",
+							culprit.pass, culprit.pipeline
+						)
+					},
+					None => Ok(()),
+				}
+			},
+			None if self.is_conclusive() => writeln!(f, "OK"),
+			None => writeln!(f, "OK so far, the unoptimised program hit the step limit"),
+		}
+	}
+}
+
+/// Run a program without and with the optimisations of the pass manager, and
+/// compare both runs
+///
+/// If they diverge, the program gets optimised again with one more pass of
+/// the pipeline at a time, to find the pass that introduced the divergence
+pub fn verify(
+	source: &[u8],
+	passes: &PassManager,
+	input: &[u8],
+	max_steps: u64,
+) -> Result<Report, Error> {
+	let unoptimised = UnlinkedInstructions::from_text(source).link()?;
+	let (optimised, _) = passes.run(UnlinkedInstructions::from_text(source))?;
+
+	let mut report = verify_linked(&unoptimised, &optimised, &passes.pipeline(), input, max_steps)?;
+	if report.divergence.is_some() {
+		report.culprit = find_culprit(source, passes, &report.unoptimised, input, max_steps)?;
+	}
+
+	Ok(report)
+}
+
+/// Find the shortest prefix of the pipeline whose optimised program diverges
+/// from the unoptimised run
+fn find_culprit(
+	source: &[u8],
+	passes: &PassManager,
+	unoptimised: &Execution,
+	input: &[u8],
+	max_steps: u64,
+) -> Result<Option<Culprit>, Error> {
+	let stages = passes.stages();
+
+	for len in 1..=stages.len() {
+		let (optimised, _) = passes.run_prefix(UnlinkedInstructions::from_text(source), len)?;
+		let optimised = execute(&optimised, input, max_steps)?;
+
+		if compare(unoptimised, &optimised).is_some() {
+			return Ok(Some(Culprit {
+				pass:     stages[len - 1].clone(),
+				pipeline: stages[..len].join(","),
+			}));
+		}
+	}
+
+	Ok(None)
+}
+
+/// Compare two already linked versions of a program, see [`verify`]
+pub fn verify_linked(
+	unoptimised: &LinkedInstructions,
+	optimised: &LinkedInstructions,
+	pipeline: &str,
+	input: &[u8],
+	max_steps: u64,
+) -> Result<Report, Error> {
+	let unoptimised = execute(unoptimised, input, max_steps)?;
+	let optimised = execute(optimised, input, max_steps)?;
+	let divergence = compare(&unoptimised, &optimised);
+
+	Ok(Report { pipeline: pipeline.to_owned(), unoptimised, optimised, divergence, culprit: None })
+}
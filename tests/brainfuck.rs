@@ -0,0 +1,87 @@
+//! The brainfuck `--emit-bf` decompiles optimised programs into, run
+//! unoptimised against the optimised program
+
+mod common;
+
+use bf_rust::error::Error;
+use bf_rust::instruction::{Instruction, LinkedInstructions};
+use bf_rust::interpret::Options;
+use common::{corpus, interpret, machine_dependent, optimise};
+
+/// Decompile a program at an optimisation level, and check that running the
+/// result unoptimised does the same as the optimised program
+fn check(name: &str, source: &[u8], input: &[u8], level: u8) {
+	let insts = optimise(source, level);
+	let bf = insts.to_brainfuck().unwrap();
+
+	assert!(
+		bf.trim_end().bytes().all(|b| b"+-<>[].,".contains(&b)),
+		"{} -O{} decompiles to {}",
+		name,
+		level,
+		bf
+	);
+	assert_eq!(
+		interpret(&optimise(bf.as_bytes(), 0), Options::default(), input),
+		interpret(&insts, Options::default(), input),
+		"{} -O{} decompiles to {}",
+		name,
+		level,
+		bf
+	);
+}
+
+#[test]
+fn examples() {
+	for program in corpus() {
+		for level in 0..=3 {
+			check(&program.name, &program.source, &program.input, level);
+		}
+	}
+}
+
+#[test]
+fn machine_dependent_programs() {
+	for program in machine_dependent() {
+		for level in 0..=3 {
+			check(&program.name, &program.source, &program.input, level);
+		}
+	}
+}
+
+#[test]
+fn multiplications() {
+	let programs: [(&str, &[u8]); 4] = [
+		// A MulAcc, MEM[2] += MEM[0] * MEM[1]
+		(",>,<[>[->+>+<<]>>[-<<+>>]<<<-]>>.", b"\x07\x09"),
+		// A TriAcc, MEM[1] += MEM[0] + (MEM[0] - 1) + ... + 1
+		(",[[->+>+<<]>>[-<<+>>]<<-]>.", b"\x0c"),
+		// Both after a loop, when nothing is known about the tape
+		(",[>,]<<[>[->+>+<<]>>[-<<+>>]<<<-]>>.", b"\x05\x06\x00"),
+		(",[>,]<<[[->+>+<<]>>[-<<+>>]<<-]>.", b"\x05\x06\x00"),
+	];
+
+	for (source, input) in programs {
+		for level in 0..=3 {
+			check(source, source.as_bytes(), input, level);
+		}
+	}
+
+	let insts = optimise(b"[>[->+>+<<]>>[-<<+>>]<<<-]", 3);
+	assert!(insts.0.iter().any(|inst| matches!(inst, Instruction::MulAcc { .. })));
+	let insts = optimise(b"[[->+>+<<]>>[-<<+>>]<<-]", 3);
+	assert!(insts.0.iter().any(|inst| matches!(inst, Instruction::TriAcc { .. })));
+}
+
+#[test]
+fn no_temporary_cell() {
+	// After the loop no cell is known to be 0, and the counter gets cleared
+	// right away, so there is nowhere to copy the source of the MulAcc
+	let mut insts = optimise(b",[>,]<", 0).0;
+	insts.extend([
+		Instruction::MulAcc { amount: 1, source: 1, offset: 2 },
+		Instruction::Set { amount: 0, offset: 0 },
+	]);
+
+	assert!(matches!(LinkedInstructions(insts).to_brainfuck(), Err(Error::NoTemporaryCell)));
+}
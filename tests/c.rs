@@ -0,0 +1,106 @@
+//! The C programs `--emit-c` produces against the interpreter
+//!
+//! These compile every program with `cc`, and pass without checking anything
+//! if there is no `cc` to run.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bf_rust::instruction::LinkedInstructions;
+use bf_rust::interpret::Options;
+use common::{EOFS, Outcome, TAPE_SIZES, corpus, interpret, machine_dependent, optimise};
+
+fn has_cc() -> bool { Command::new("cc").arg("--version").output().is_ok() }
+
+/// Compile the C translation of a program and run it on the input
+fn run(insts: &LinkedInstructions, options: &Options, input: &[u8]) -> Outcome {
+	static COUNT: AtomicUsize = AtomicUsize::new(0);
+	let path = std::env::temp_dir().join(format!(
+		"bf-rust-c-{}-{}",
+		std::process::id(),
+		COUNT.fetch_add(1, Ordering::Relaxed)
+	));
+	let source = path.with_extension("c");
+
+	std::fs::write(&source, insts.to_c(options).unwrap()).unwrap();
+	let status = Command::new("cc").arg("-O1").arg("-o").arg(&path).arg(&source).status().unwrap();
+	assert!(status.success(), "cc failed on {}", source.display());
+
+	let output = execute(&path, input);
+	std::fs::remove_file(&source).unwrap();
+	std::fs::remove_file(&path).unwrap();
+
+	output
+}
+
+fn execute(path: &Path, input: &[u8]) -> Outcome {
+	let mut child = Command::new(path)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.spawn()
+		.unwrap();
+	// The program might exit before reading all of its input
+	let _ = child.stdin.take().unwrap().write_all(input);
+	let output = child.wait_with_output().unwrap();
+
+	// The only way a program fails is by reading past the end of its input
+	(output.stdout, !output.status.success())
+}
+
+#[test]
+fn examples() {
+	if !has_cc() {
+		return;
+	}
+
+	for program in corpus() {
+		for level in 0..=3 {
+			let insts = optimise(&program.source, level);
+			let options = Options::default();
+
+			assert_eq!(
+				run(&insts, &options, &program.input),
+				interpret(&insts, options, &program.input),
+				"{} -O{}",
+				program.name,
+				level
+			);
+		}
+	}
+}
+
+#[test]
+fn tape_sizes_and_eof() {
+	if !has_cc() {
+		return;
+	}
+
+	for program in machine_dependent() {
+		for level in 0..=3 {
+			let insts = optimise(&program.source, level);
+
+			for tape_size in TAPE_SIZES {
+				for eof in EOFS {
+					let options = Options { tape_size, eof };
+
+					assert_eq!(
+						run(&insts, &options, &program.input),
+						interpret(&insts, options, &program.input),
+						"{} -O{} with {} cells and {:?} at the end of the input",
+						program.name,
+						level,
+						tape_size,
+						eof
+					);
+				}
+			}
+		}
+	}
+}
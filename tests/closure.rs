@@ -0,0 +1,49 @@
+//! The closure backend against the interpreter
+
+mod common;
+
+use bf_rust::closure::ClosureProgram;
+use bf_rust::interpret::Options;
+use common::{EOFS, TAPE_SIZES, corpus, interpret, machine_dependent, optimise, outcome};
+
+fn check(name: &str, source: &[u8], input: &[u8], options: Options) {
+	for level in 0..=3 {
+		let insts = optimise(source, level);
+
+		let mut output = vec![];
+		let result = ClosureProgram::with_options(&insts, options)
+			.unwrap()
+			.run_with(&mut &input[..], &mut output);
+
+		assert_eq!(
+			outcome(output, result),
+			interpret(&insts, options, input),
+			"{} -O{} with {:?}",
+			name,
+			level,
+			options
+		);
+	}
+}
+
+#[test]
+fn examples() {
+	for program in corpus() {
+		for tape_size in [30_000, 65_536] {
+			let options = Options { tape_size, ..Default::default() };
+			check(&program.name, &program.source, &program.input, options);
+		}
+	}
+}
+
+#[test]
+fn tape_sizes_and_eof() {
+	for program in machine_dependent() {
+		for tape_size in TAPE_SIZES {
+			for eof in EOFS {
+				let options = Options { tape_size, eof };
+				check(&program.name, &program.source, &program.input, options);
+			}
+		}
+	}
+}
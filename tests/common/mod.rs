@@ -0,0 +1,99 @@
+//! Programs and helpers shared by the integration tests
+
+// Every test binary uses a different part of this
+#![allow(dead_code)]
+
+use std::path::Path;
+
+use bf_rust::error::Error;
+use bf_rust::instruction::{LinkedInstructions, UnlinkedInstructions};
+use bf_rust::interpret::{Eof, Interpreter, MAX_TAPE_SIZE, Options};
+use bf_rust::pass::PassManager;
+
+/// A program and the input to run it on
+pub struct Program {
+	pub name:   String,
+	pub source: Vec<u8>,
+	pub input:  Vec<u8>,
+}
+
+/// Every program in `examples/`, reading `PROGRAM.in` as its input if that
+/// file exists
+pub fn corpus() -> Vec<Program> {
+	let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+	let mut paths: Vec<_> = std::fs::read_dir(examples)
+		.unwrap()
+		.map(|entry| entry.unwrap().path())
+		.filter(|path| path.extension().is_some_and(|ext| ext == "bf"))
+		.collect();
+	paths.sort();
+
+	paths
+		.into_iter()
+		.map(|path| {
+			Program {
+				name:   path.file_name().unwrap().to_string_lossy().into_owned(),
+				source: std::fs::read(&path).unwrap(),
+				input:  std::fs::read(path.with_extension("in")).unwrap_or_default(),
+			}
+		})
+		.collect()
+}
+
+/// Small programs whose output depends on the tape size and what a read does
+/// at the end of the input
+///
+/// They stop on any tape and with any EOF behaviour.
+pub fn machine_dependent() -> Vec<Program> {
+	let program = |name: &str, source: &str, input: &str| {
+		Program {
+			name:   name.to_owned(),
+			source: source.as_bytes().to_vec(),
+			input:  input.as_bytes().to_vec(),
+		}
+	};
+
+	vec![
+		program("wrap right", "+>>>>>>>.<<<<<<<.", ""),
+		program("wrap left", "<+++++.>.<<<<<<<.", ""),
+		program("multiply across the left end", "+++[<++>-]<.>.", ""),
+		program("clear across the right end", "+>>>>>>>[-]<<<<<<<.", ""),
+		program("read past the end", ",.,.,.", "x"),
+		program("read into several cells", ",>,>,<<.>.>.", "ab"),
+	]
+}
+
+/// Tape sizes covering the smallest tape, an odd size, the size of the
+/// original implementation, and the full tape
+pub const TAPE_SIZES: [usize; 4] = [1, 7, 30_000, MAX_TAPE_SIZE];
+
+pub const EOFS: [Eof; 4] = [Eof::Error, Eof::Unchanged, Eof::Zero, Eof::MinusOne];
+
+/// Optimise a program with the pipeline of an optimisation level
+pub fn optimise(source: &[u8], level: u8) -> LinkedInstructions {
+	let passes = PassManager::from_level(level).unwrap();
+	let (insts, _) = passes.run(UnlinkedInstructions::from_text(source)).unwrap();
+
+	insts
+}
+
+/// The output of a run, and whether it stopped because it read past the end
+/// of its input
+pub type Outcome = (Vec<u8>, bool);
+
+/// The outcome of a run that returned `result`
+pub fn outcome(output: Vec<u8>, result: Result<(), Error>) -> Outcome {
+	match result {
+		Ok(()) => (output, false),
+		Err(Error::CouldNotReadInput) => (output, true),
+		Err(e) => panic!("{}", e),
+	}
+}
+
+/// Run a program on the reference interpreter
+pub fn interpret(insts: &LinkedInstructions, options: Options, input: &[u8]) -> Outcome {
+	let mut output = vec![];
+	let result = Interpreter::with_options(insts, options).run_with(&mut &input[..], &mut output);
+
+	outcome(output, result)
+}
@@ -0,0 +1,100 @@
+//! Every backend against the interpreter, for every program in `examples/`
+//! at every optimisation level
+//!
+//! Run these with `make conformance`.
+
+mod common;
+
+use bf_rust::closure::ClosureProgram;
+use bf_rust::error::Error;
+use bf_rust::instruction::LinkedInstructions;
+use bf_rust::interpret::Options;
+use bf_rust::jit::JitProgram;
+use bf_rust::packed::{PackedInterpreter, PackedProgram};
+use common::{EOFS, Outcome, TAPE_SIZES, corpus, interpret, machine_dependent, optimise, outcome};
+
+/// Check that a backend gives the interpreter's outcome for the corpus
+fn check(backend: &str, run: impl Fn(&LinkedInstructions, &[u8]) -> Outcome) {
+	for program in corpus() {
+		for level in 0..=3 {
+			let insts = optimise(&program.source, level);
+
+			assert_eq!(
+				run(&insts, &program.input),
+				interpret(&insts, Options::default(), &program.input),
+				"{} -O{}: the {} backend disagrees",
+				program.name,
+				level,
+				backend
+			);
+		}
+	}
+}
+
+#[test]
+fn packed() {
+	check("packed", |insts, input| {
+		let program = PackedProgram::new(insts).unwrap();
+		let mut output = vec![];
+		let result = PackedInterpreter::new(&program).run_with(&mut &input[..], &mut output);
+
+		outcome(output, result)
+	});
+}
+
+#[test]
+fn closure() {
+	check("closure", |insts, input| {
+		let mut output = vec![];
+		let result = ClosureProgram::new(insts).unwrap().run_with(&mut &input[..], &mut output);
+
+		outcome(output, result)
+	});
+}
+
+#[test]
+fn jit() {
+	if let Err(Error::UnsupportedJit) = JitProgram::new(&LinkedInstructions(vec![])) {
+		return;
+	}
+
+	check("jit", |insts, input| {
+		let mut output = vec![];
+		let result = JitProgram::new(insts).unwrap().run_with(&mut &input[..], &mut output);
+
+		outcome(output, result)
+	});
+}
+
+#[test]
+fn jit_tape_sizes_and_eof() {
+	if let Err(Error::UnsupportedJit) = JitProgram::new(&LinkedInstructions(vec![])) {
+		return;
+	}
+
+	for program in machine_dependent() {
+		for tape_size in TAPE_SIZES {
+			for eof in EOFS {
+				let options = Options { tape_size, eof };
+
+				for level in 0..=3 {
+					let insts = optimise(&program.source, level);
+
+					let mut output = vec![];
+					let result = JitProgram::with_options(&insts, options)
+						.unwrap()
+						.run_with(&mut &program.input[..], &mut output);
+
+					assert_eq!(
+						outcome(output, result),
+						interpret(&insts, options, &program.input),
+						"{} -O{} with {:?}: the jit backend disagrees",
+						program.name,
+						level,
+						options
+					);
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,92 @@
+//! The executables `bf-rust build` produces against the interpreter
+
+#![cfg(all(target_arch = "x86_64", target_os = "linux"))]
+
+mod common;
+
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bf_rust::instruction::LinkedInstructions;
+use bf_rust::interpret::Options;
+use common::{EOFS, Outcome, TAPE_SIZES, corpus, interpret, machine_dependent, optimise};
+
+/// Held while writing and starting an executable, so no other test forks
+/// while the file is open for writing, which would make it busy
+static SPAWN: Mutex<()> = Mutex::new(());
+
+/// Build an executable and run it on the input
+fn run(insts: &LinkedInstructions, options: &Options, input: &[u8]) -> Outcome {
+	static COUNT: AtomicUsize = AtomicUsize::new(0);
+	let path = std::env::temp_dir().join(format!(
+		"bf-rust-executable-{}-{}",
+		std::process::id(),
+		COUNT.fetch_add(1, Ordering::Relaxed)
+	));
+
+	let mut child = {
+		let _guard = SPAWN.lock().unwrap();
+		std::fs::write(&path, insts.to_elf(options).unwrap()).unwrap();
+		std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+		Command::new(&path)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::null())
+			.spawn()
+			.unwrap()
+	};
+	// The program might exit before reading all of its input
+	let _ = child.stdin.take().unwrap().write_all(input);
+	let output = child.wait_with_output().unwrap();
+	std::fs::remove_file(&path).unwrap();
+
+	// The only way a program fails is by reading past the end of its input
+	(output.stdout, !output.status.success())
+}
+
+#[test]
+fn examples() {
+	for program in corpus() {
+		for level in 0..=3 {
+			let insts = optimise(&program.source, level);
+			let options = Options::default();
+
+			assert_eq!(
+				run(&insts, &options, &program.input),
+				interpret(&insts, options, &program.input),
+				"{} -O{}",
+				program.name,
+				level
+			);
+		}
+	}
+}
+
+#[test]
+fn tape_sizes_and_eof() {
+	for program in machine_dependent() {
+		for level in 0..=3 {
+			let insts = optimise(&program.source, level);
+
+			for tape_size in TAPE_SIZES {
+				for eof in EOFS {
+					let options = Options { tape_size, eof };
+
+					assert_eq!(
+						run(&insts, &options, &program.input),
+						interpret(&insts, options, &program.input),
+						"{} -O{} with {} cells and {:?} at the end of the input",
+						program.name,
+						level,
+						tape_size,
+						eof
+					);
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,162 @@
+//! The WebAssembly modules `--emit-wasm` and `--emit-wat` produce, validated
+//! and run with wasmi against the interpreter
+
+mod common;
+
+use std::ops::RangeInclusive;
+
+use bf_rust::emit::wasm::MACHINE_SECTION;
+use bf_rust::instruction::LinkedInstructions;
+use bf_rust::interpret::Options;
+use common::{EOFS, Outcome, TAPE_SIZES, corpus, interpret, machine_dependent, optimise};
+use wasmi::{Caller, Engine, Linker, Module, Store};
+use wasmparser::{Parser, Payload, Validator};
+
+/// The input and output of a module
+struct Host<'a> {
+	input:  &'a [u8],
+	output: Vec<u8>,
+}
+
+/// Both forms of a program's module, as binaries
+fn modules(insts: &LinkedInstructions, options: &Options) -> [(&'static str, Vec<u8>); 2] {
+	[
+		("wasm", insts.to_wasm(options).unwrap()),
+		("wat", wat::parse_str(insts.to_wat(options).unwrap()).unwrap()),
+	]
+}
+
+/// Run a module on the input
+fn run(wasm: &[u8], input: &[u8]) -> Outcome {
+	let engine = Engine::default();
+	let module = Module::new(&engine, wasm).unwrap();
+	let mut store = Store::new(&engine, Host { input, output: vec![] });
+
+	let mut linker = <Linker<Host>>::new(&engine);
+	linker
+		.func_wrap("env", "read", |mut caller: Caller<'_, Host>| -> i32 {
+			let host = caller.data_mut();
+			match host.input.split_first() {
+				Some((byte, rest)) => {
+					host.input = rest;
+					*byte as i32
+				},
+				None => -1,
+			}
+		})
+		.unwrap();
+	linker
+		.func_wrap("env", "write", |mut caller: Caller<'_, Host>, byte: i32| {
+			caller.data_mut().output.push(byte as u8);
+		})
+		.unwrap();
+
+	let instance = linker.instantiate(&mut store, &module).unwrap().start(&mut store).unwrap();
+	let run = instance.get_typed_func::<(), i32>(&store, "run").unwrap();
+	let status = run.call(&mut store, ()).unwrap();
+
+	(store.into_data().output, status != 0)
+}
+
+/// Encode a number as unsigned LEB128
+fn leb128(mut n: usize) -> Vec<u8> {
+	let mut bytes = vec![];
+	loop {
+		let byte = (n & 0x7F) as u8;
+		n >>= 7;
+		if n == 0 {
+			bytes.push(byte);
+			return bytes;
+		}
+		bytes.push(byte | 0x80);
+	}
+}
+
+#[test]
+fn machine_section() {
+	let insts = optimise(b",[.,]", 3);
+
+	for tape_size in TAPE_SIZES {
+		let options = Options { tape_size, ..Default::default() };
+		let mut expected = leb128(8);
+		expected.extend(leb128(tape_size));
+
+		for (form, wasm) in modules(&insts, &options) {
+			Validator::new().validate_all(&wasm).unwrap();
+
+			let machine: Vec<_> = Parser::new(0)
+				.parse_all(&wasm)
+				.filter_map(|payload| {
+					match payload.unwrap() {
+						Payload::CustomSection(section) if section.name() == MACHINE_SECTION => {
+							Some(section.data().to_vec())
+						},
+						_ => None,
+					}
+				})
+				.collect();
+			assert_eq!(machine, [expected.clone()], "{} with {} cells", form, tape_size);
+		}
+	}
+}
+
+/// Check the modules of every program in `examples/` at some optimisation
+/// levels
+fn check_examples(levels: RangeInclusive<u8>) {
+	for program in corpus() {
+		for level in levels.clone() {
+			let insts = optimise(&program.source, level);
+			let options = Options::default();
+			let expected = interpret(&insts, options, &program.input);
+
+			for (form, wasm) in modules(&insts, &options) {
+				Validator::new().validate_all(&wasm).unwrap();
+				assert_eq!(
+					run(&wasm, &program.input),
+					expected,
+					"{} -O{} as {}",
+					program.name,
+					level,
+					form
+				);
+			}
+		}
+	}
+}
+
+#[test]
+fn examples() { check_examples(3..=3); }
+
+// wasmi takes minutes to run the less optimised examples in a debug build
+#[test]
+#[ignore = "slow, run with `make conformance`"]
+fn examples_less_optimised() { check_examples(0..=2); }
+
+#[test]
+fn tape_sizes_and_eof() {
+	for program in machine_dependent() {
+		for level in 0..=3 {
+			let insts = optimise(&program.source, level);
+
+			for tape_size in TAPE_SIZES {
+				for eof in EOFS {
+					let options = Options { tape_size, eof };
+					let expected = interpret(&insts, options, &program.input);
+
+					for (form, wasm) in modules(&insts, &options) {
+						assert_eq!(
+							run(&wasm, &program.input),
+							expected,
+							"{} -O{} as {} with {} cells and {:?} at the end of the input",
+							program.name,
+							level,
+							form,
+							tape_size,
+							eof
+						);
+					}
+				}
+			}
+		}
+	}
+}